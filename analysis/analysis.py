@@ -51,6 +51,8 @@ def get_animation(states, zebra_toml, **kwargs):  # pylint: disable=too-many-loc
     )
     (veh_scat_up,) = ax1.plot([], [], marker=">", color="firebrick", ms=5, ls="None")
     (veh_scat_down,) = ax1.plot([], [], marker="<", color="purple", ms=5, ls="None")
+    (lights_red,) = ax1.plot([], [], marker="s", color="red", ms=8, ls="None")
+    (lights_green,) = ax1.plot([], [], marker="s", color="limegreen", ms=8, ls="None")
 
     time_text = ax1.text(
         0, 1, "", ha="left", va="bottom", transform=ax1.transAxes, fontsize="large"
@@ -92,10 +94,21 @@ def get_animation(states, zebra_toml, **kwargs):  # pylint: disable=too-many-loc
             [], [], marker=">", color="firebrick", ms=5, ls="None"
         )
         (veh_scat_down,) = ax1.plot([], [], marker="<", color="purple", ms=5, ls="None")
+        (lights_red,) = ax1.plot([], [], marker="s", color="red", ms=8, ls="None")
+        (lights_green,) = ax1.plot(
+            [], [], marker="s", color="limegreen", ms=8, ls="None"
+        )
         time_text = ax1.text(
             0, 1, "", ha="left", va="bottom", transform=ax1.transAxes, fontsize="large"
         )
-        return ped_scat, veh_scat_up, veh_scat_down, time_text
+        return (
+            ped_scat,
+            veh_scat_up,
+            veh_scat_down,
+            lights_red,
+            lights_green,
+            time_text,
+        )
 
     def animate(i):
         state = states[i]
@@ -127,12 +140,33 @@ def get_animation(states, zebra_toml, **kwargs):  # pylint: disable=too-many-loc
         ]
         veh_ys_down = len(veh_xs_down) * [-road_width / 2]
 
+        # Pelican crossing lights, coloured by their current phase
+        lights_xs_red = [
+            float(crossing["position"])
+            for crossing in state.get("crossings", [])
+            if crossing["phase"] == "Red"
+        ]
+        lights_xs_green = [
+            float(crossing["position"])
+            for crossing in state.get("crossings", [])
+            if crossing["phase"] == "Green"
+        ]
+
         # Update plots
         ped_scat.set_data(ped_xs, ped_ys)
         veh_scat_up.set_data(veh_xs_up, veh_ys_up)
         veh_scat_down.set_data(veh_xs_down, veh_ys_down)
+        lights_red.set_data(lights_xs_red, len(lights_xs_red) * [0])
+        lights_green.set_data(lights_xs_green, len(lights_xs_green) * [0])
         time_text.set_text(f"Time: {hrs:02.0f}h{mins:02.0f}m{secs:02.0f}s")
-        return (ped_scat, veh_scat_up, veh_scat_down, time_text)
+        return (
+            ped_scat,
+            veh_scat_up,
+            veh_scat_down,
+            lights_red,
+            lights_green,
+            time_text,
+        )
 
     return anim.FuncAnimation(
         fig,
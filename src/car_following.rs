@@ -0,0 +1,171 @@
+use serde::{Serialize, Deserialize};
+
+use crate::{Acceleration, Length, Speed};
+use crate::time::TimeDelta;
+use crate::vehicle::Vehicle;
+
+// Shortest gap used in the IDM, to keep it finite
+const MIN_GAP: Length = 0.1;
+// Shortest reaction time used in Gipps' model, which steps by it
+const MIN_REACTION_TIME: f32 = 0.1;
+
+// A law giving a driver's acceleration from what is ahead. Unlike the
+// event-driven reaction model, acceleration varies continuously, so the
+// simulation updates it at a fixed step.
+pub trait CarFollowingModel {
+    // Acceleration of a vehicle, given the gap to and speed of the vehicle or
+    // obstacle ahead (None on a clear road).
+    fn acceleration(&self, vehicle: &dyn Vehicle, leader: Option<(Length, Speed)>) -> Acceleration;
+}
+
+// Intelligent Driver Model (Treiber, Hennecke & Helbing, 2000). The minimum
+// gap is the vehicle's buffer zone and the comfortable deceleration its max
+// deceleration.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Idm {
+    // Time headway (in s) the driver aims to keep
+    pub time_headway: f32,
+    // How sharply acceleration falls as the desired speed is approached
+    pub exponent: f32
+}
+
+impl Default for Idm {
+    fn default() -> Self {
+        Idm { time_headway: 1.5, exponent: 4.0 }
+    }
+}
+
+impl CarFollowingModel for Idm {
+    fn acceleration(&self, vehicle: &dyn Vehicle, leader: Option<(Length, Speed)>) -> Acceleration {
+        let speed = vehicle.get_speed();
+        let max_acceleration = vehicle.get_max_acceleration();
+        let free_road = 1.0 - (speed / vehicle.get_max_speed()).powf(self.exponent);
+        let interaction = match leader {
+            Some((gap, leader_speed)) => {
+                let braking = f32::sqrt(max_acceleration * -vehicle.get_max_deceleration());
+                let desired_gap = vehicle.get_buffer_zone()
+                    + f32::max(0.0, speed * self.time_headway + speed * (speed - leader_speed) / (2.0 * braking));
+                (desired_gap / f32::max(gap, MIN_GAP)).powi(2)
+            }
+            None => 0.0
+        };
+        max_acceleration * (free_road - interaction)
+    }
+}
+
+// Gipps' model (Gipps, 1981): the driver picks, a reaction time ahead, the
+// fastest speed from which they could still stop behind the leader should
+// it brake as hard as they would. The margin is the vehicle's buffer zone.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Gipps;
+
+impl CarFollowingModel for Gipps {
+    fn acceleration(&self, vehicle: &dyn Vehicle, leader: Option<(Length, Speed)>) -> Acceleration {
+        let speed = vehicle.get_speed();
+        let max_speed = vehicle.get_max_speed();
+        let reaction = f32::max(vehicle.get_reaction_time().into(), MIN_REACTION_TIME);
+        let ratio = speed / max_speed;
+        let free_speed = speed + 2.5 * vehicle.get_max_acceleration() * reaction * (1.0 - ratio) * f32::sqrt(0.025 + ratio);
+        let next_speed = match leader {
+            Some((gap, leader_speed)) => {
+                // Decelerations are negative
+                let braking = vehicle.get_max_deceleration();
+                let gap = gap - vehicle.get_buffer_zone();
+                let radicand = braking * braking * reaction * reaction
+                    - braking * (2.0 * gap - speed * reaction - leader_speed * leader_speed / braking);
+                let safe_speed = if radicand > 0.0 { braking * reaction + f32::sqrt(radicand) } else { 0.0 };
+                f32::min(free_speed, f32::max(safe_speed, 0.0))
+            }
+            None => free_speed
+        };
+        (next_speed - speed) / reaction
+    }
+}
+
+// A car-following model, as selected in the config by `model`. Without one,
+// vehicles follow the event-driven reaction model.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(tag = "model", rename_all = "snake_case")]
+pub enum CarFollowingConfig {
+    Idm {
+        #[serde(default = "default_time_headway")]
+        time_headway: TimeDelta,
+        #[serde(default = "default_exponent")]
+        exponent: f32
+    },
+    Gipps
+}
+
+fn default_time_headway() -> TimeDelta {
+    TimeDelta::new(1500)
+}
+
+fn default_exponent() -> f32 {
+    4.0
+}
+
+impl CarFollowingConfig {
+    pub fn build(&self) -> Box<dyn CarFollowingModel> {
+        match *self {
+            CarFollowingConfig::Idm { time_headway, exponent } => Box::new(Idm { time_headway: time_headway.into(), exponent }),
+            CarFollowingConfig::Gipps => Box::new(Gipps)
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::road::Direction;
+    use crate::vehicle::{Action, Car, VehicleKind, MAX_SPEED};
+
+    #[test]
+    fn test_idm() {
+        let idm = Idm::default();
+        let car = Car::new(0, Direction::Up, 10.0, Action::StaticSpeed);
+
+        // Free road: accelerate towards the max speed
+        let free = idm.acceleration(&car, None);
+        assert!(free > 0.0 && free < car.get_max_acceleration());
+
+        // A distant leader makes little difference; a near one forces braking
+        assert!(idm.acceleration(&car, Some((200.0, 10.0))) > 0.9 * free);
+        assert!(idm.acceleration(&car, Some((5.0, 0.0))) < car.get_max_deceleration());
+
+        // At the max speed on a free road there is no need to accelerate
+        let fast = Car::new(1, Direction::Up, MAX_SPEED, Action::StaticSpeed);
+        assert_eq!(idm.acceleration(&fast, None), 0.0);
+
+        // A slow vehicle ahead holds up a car more than a fast one
+        let bicycle_speed = VehicleKind::Bicycle.spec().max_speed;
+        assert!(idm.acceleration(&fast, Some((30.0, bicycle_speed))) < idm.acceleration(&fast, Some((30.0, MAX_SPEED))));
+    }
+
+    #[test]
+    fn test_gipps() {
+        let mut car = Car::new(0, Direction::Up, 10.0, Action::StaticSpeed);
+        car.set_reaction_time(TimeDelta::from_secs(1));
+
+        // Free road: accelerate, but never beyond the max speed
+        let free = Gipps.acceleration(&car, None);
+        assert!(free > 0.0 && free <= car.get_max_acceleration());
+        let fast = Car::new(1, Direction::Up, MAX_SPEED, Action::StaticSpeed);
+        assert!(Gipps.acceleration(&fast, None).abs() < 1e-6);
+
+        // Behind a leader at the same speed with a safe gap, keep going
+        assert!(Gipps.acceleration(&car, Some((50.0, 10.0))) >= 0.0);
+        // Close behind a stationary obstacle, brake to a stop
+        assert_eq!(Gipps.acceleration(&car, Some((5.0, 0.0))), -10.0);
+    }
+
+    #[test]
+    fn test_deserialize_car_following_config() {
+        let config: CarFollowingConfig = toml::from_str("model = \"idm\"").unwrap();
+        assert_eq!(config, CarFollowingConfig::Idm { time_headway: TimeDelta::new(1500), exponent: 4.0 });
+        let config: CarFollowingConfig = toml::from_str("model = \"idm\"\ntime_headway = 1000").unwrap();
+        assert_eq!(config, CarFollowingConfig::Idm { time_headway: TimeDelta::from_secs(1), exponent: 4.0 });
+        let config: CarFollowingConfig = toml::from_str("model = \"gipps\"").unwrap();
+        assert_eq!(config, CarFollowingConfig::Gipps);
+    }
+}
@@ -1,5 +1,11 @@
-use crate::road::Crossing;
+use crate::road::{Crossing, AMBER_TIME};
+use crate::pedestrian::{WalkingSpeed, CrossingChoice};
+use crate::simulation::RateProfile;
+use crate::headway::HeadwayConfig;
+use crate::lane_change::LaneChange;
+use crate::car_following::CarFollowingConfig;
 use crate::time::TimeDelta;
+use crate::vehicle::{VehicleKind, VehicleSpec, MAX_SPEED, EMERGENCY_DECCELERATION_VALUE};
 use crate::{Time, Position};
 
 use std::fs;
@@ -57,7 +63,79 @@ pub struct SimulationConfig {
     /// Arrival rate for pedestrians - parameter in exponential distribution
     pub pedestrian_arrival_rate: ArrivalRate,
     /// Arrival rate for vehicles
-    pub vehicle_arrival_rate: ArrivalRate
+    pub vehicle_arrival_rate: ArrivalRate,
+    /// Fraction of vehicles travelling up the road
+    #[serde(default = "default_direction_split")]
+    pub vehicle_direction_split: f32,
+    /// Arrival rates for vehicles in each direction, for independent arrivals in each direction
+    #[serde(default)]
+    pub vehicle_arrival_rate_up: Option<ArrivalRate>,
+    #[serde(default)]
+    pub vehicle_arrival_rate_down: Option<ArrivalRate>,
+    /// Time-varying arrival rate for pedestrians, in place of the constant rate
+    #[serde(default)]
+    pub pedestrian_arrival_profile: Option<RateProfile>,
+    /// Time-varying arrival rate for vehicles, in place of the constant rate
+    #[serde(default)]
+    pub vehicle_arrival_profile: Option<RateProfile>,
+    /// Distribution of vehicle headways, in place of Poisson arrivals
+    #[serde(default)]
+    pub vehicle_headway: Option<HeadwayConfig>,
+    /// Distributions of vehicle headways in each direction, for independent arrivals in each direction
+    #[serde(default)]
+    pub vehicle_headway_up: Option<HeadwayConfig>,
+    #[serde(default)]
+    pub vehicle_headway_down: Option<HeadwayConfig>,
+    /// Time step of a fixed-time-step simulation, in place of the event-driven one
+    #[serde(default)]
+    pub time_step: Option<TimeDelta>
+}
+
+impl SimulationConfig {
+    /// The vehicle arrival rates in the up and down directions, if arrivals
+    /// in each direction are drawn separately
+    pub fn directional_vehicle_arrival_rates(&self) -> Option<(ArrivalRate, ArrivalRate)> {
+        match (self.vehicle_arrival_rate_up, self.vehicle_arrival_rate_down) {
+            (Some(up), Some(down)) => Some((up, down)),
+            (None, None) => None,
+            _ => panic!("Vehicle arrival rates must be given for both directions or neither")
+        }
+    }
+
+    /// The headway distributions in the up and down directions, if arrivals
+    /// in each direction are drawn separately
+    pub fn directional_vehicle_headways(&self) -> Option<(&HeadwayConfig, &HeadwayConfig)> {
+        match (&self.vehicle_headway_up, &self.vehicle_headway_down) {
+            (Some(up), Some(down)) => Some((up, down)),
+            (None, None) => None,
+            _ => panic!("Vehicle headways must be given for both directions or neither")
+        }
+    }
+
+    /// Check that at most one way of drawing the vehicle arrivals is given,
+    /// as each would otherwise replace the others.
+    fn validate(&self) -> Result<(), String> {
+        if self.vehicle_arrival_rate_up.is_some() != self.vehicle_arrival_rate_down.is_some() {
+            return Err("vehicle_arrival_rate_up and vehicle_arrival_rate_down must be given together".to_string());
+        }
+        if self.vehicle_headway_up.is_some() != self.vehicle_headway_down.is_some() {
+            return Err("vehicle_headway_up and vehicle_headway_down must be given together".to_string());
+        }
+        let given: Vec<&str> = [
+            ("vehicle_arrival_rate_up/down", self.vehicle_arrival_rate_up.is_some()),
+            ("vehicle_headway", self.vehicle_headway.is_some()),
+            ("vehicle_headway_up/down", self.vehicle_headway_up.is_some()),
+            ("vehicle_arrival_profile", self.vehicle_arrival_profile.is_some())
+        ].into_iter().filter(|(_, given)| *given).map(|(name, _)| name).collect();
+        if given.len() > 1 {
+            return Err(format!("conflicting vehicle arrivals: give only one of {}", given.join(", ")));
+        }
+        let directional = self.vehicle_arrival_rate_up.is_some() || self.vehicle_headway_up.is_some();
+        if directional && self.vehicle_direction_split != default_direction_split() {
+            return Err("vehicle_direction_split conflicts with arrivals drawn for each direction".to_string());
+        }
+        Ok(())
+    }
 }
 
 
@@ -68,26 +146,230 @@ impl Default for SimulationConfig {
             num_pedestrians: 10,
             num_vehicles: 10,
             pedestrian_arrival_rate: 0.5,
-            vehicle_arrival_rate: 0.5
+            vehicle_arrival_rate: 0.5,
+            vehicle_direction_split: default_direction_split(),
+            vehicle_arrival_rate_up: None,
+            vehicle_arrival_rate_down: None,
+            pedestrian_arrival_profile: None,
+            vehicle_arrival_profile: None,
+            vehicle_headway: None,
+            vehicle_headway_up: None,
+            vehicle_headway_down: None,
+            time_step: None
+        }
+    }
+}
+
+
+/// Relative frequencies of the kinds of vehicle arriving. Kinds left out
+/// of the `[vehicle_mix]` table never arrive.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Default, Clone)]
+pub struct VehicleMix {
+    #[serde(default)]
+    pub car: f32,
+    #[serde(default)]
+    pub van: f32,
+    #[serde(default)]
+    pub bus: f32,
+    #[serde(default)]
+    pub hgv: f32,
+    #[serde(default)]
+    pub bicycle: f32,
+    #[serde(default)]
+    pub motorbike: f32
+}
+
+impl VehicleMix {
+    /// The kinds of vehicle with a non-zero frequency, and their weights
+    pub fn weights(&self) -> Vec<(VehicleKind, f32)> {
+        vec![
+            (VehicleKind::Car, self.car),
+            (VehicleKind::Van, self.van),
+            (VehicleKind::Bus, self.bus),
+            (VehicleKind::Hgv, self.hgv),
+            (VehicleKind::Bicycle, self.bicycle),
+            (VehicleKind::Motorbike, self.motorbike)
+        ].into_iter().filter(|(_, weight)| *weight > 0.0).collect()
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        let weights = [self.car, self.van, self.bus, self.hgv, self.bicycle, self.motorbike];
+        if weights.iter().any(|weight| !weight.is_finite() || *weight < 0.0) {
+            return Err("vehicle_mix: weights must be non-negative numbers".to_string());
         }
+        if self.weights().is_empty() {
+            return Err("vehicle_mix: at least one kind of vehicle needs a positive weight".to_string());
+        }
+        Ok(())
+    }
+}
+
+
+/// Dimensions and performance of a kind of vehicle. Values left out keep
+/// the built-in spec of the kind.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Default, Clone, Copy)]
+pub struct VehicleKindConfig {
+    pub length: Option<Length>,
+    pub buffer_zone: Option<Length>,
+    pub max_speed: Option<Speed>,
+    pub max_acceleration: Option<Acceleration>,
+    /// As a positive value
+    pub max_deceleration: Option<Acceleration>,
+    /// As a positive value
+    pub max_emergency_deceleration: Option<Acceleration>
+}
+
+impl VehicleKindConfig {
+    fn apply_to(&self, spec: &mut VehicleSpec) {
+        spec.length = self.length.unwrap_or(spec.length);
+        spec.buffer_zone = self.buffer_zone.unwrap_or(spec.buffer_zone);
+        spec.max_speed = self.max_speed.unwrap_or(spec.max_speed);
+        spec.max_acceleration = self.max_acceleration.unwrap_or(spec.max_acceleration);
+        spec.max_deceleration = self.max_deceleration.map_or(spec.max_deceleration, |deceleration| -deceleration);
+        spec.max_emergency_deceleration = self.max_emergency_deceleration.map_or(spec.max_emergency_deceleration, |deceleration| -deceleration);
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        let positive = [self.length, self.max_speed, self.max_acceleration, self.max_deceleration, self.max_emergency_deceleration];
+        if positive.iter().flatten().any(|value| !(*value > 0.0 && value.is_finite())) {
+            return Err("length, max speed, acceleration and decelerations must be positive".to_string());
+        }
+        if self.buffer_zone.is_some_and(|buffer_zone| !(buffer_zone >= 0.0 && buffer_zone.is_finite())) {
+            return Err("buffer zone must be non-negative".to_string());
+        }
+        Ok(())
     }
 }
 
+/// Specs of each kind of vehicle from the `[vehicle.<kind>]` tables.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Default, Clone)]
+pub struct VehicleKindConfigs {
+    pub car: Option<VehicleKindConfig>,
+    pub van: Option<VehicleKindConfig>,
+    pub bus: Option<VehicleKindConfig>,
+    pub hgv: Option<VehicleKindConfig>,
+    pub bicycle: Option<VehicleKindConfig>,
+    pub motorbike: Option<VehicleKindConfig>
+}
+
+impl VehicleKindConfigs {
+    pub fn get(&self, kind: VehicleKind) -> Option<&VehicleKindConfig> {
+        match kind {
+            VehicleKind::Car => self.car.as_ref(),
+            VehicleKind::Van => self.van.as_ref(),
+            VehicleKind::Bus => self.bus.as_ref(),
+            VehicleKind::Hgv => self.hgv.as_ref(),
+            VehicleKind::Bicycle => self.bicycle.as_ref(),
+            VehicleKind::Motorbike => self.motorbike.as_ref()
+        }
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        let configs = [
+            ("car", &self.car),
+            ("van", &self.van),
+            ("bus", &self.bus),
+            ("hgv", &self.hgv),
+            ("bicycle", &self.bicycle),
+            ("motorbike", &self.motorbike)
+        ];
+        for (name, config) in configs {
+            if let Some(config) = config {
+                config.validate().map_err(|message| format!("vehicle.{}: {}", name, message))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+
+#[derive(Copy, Clone, Serialize, Deserialize, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum CrossingKind {
+    Zebra,
+    Pelican
+}
+
+/// A crossing of either kind. Timings left out take the values in the
+/// top level of the config.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct CrossingConfig {
+    pub position: Position,
+    pub kind: CrossingKind,
+    /// Time for pedestrian to cross the road
+    pub crossing_time: Option<TimeDelta>,
+    /// Waiting time from arrival to change of the lights (pelican only)
+    pub wait_time: Option<TimeDelta>,
+    /// Minimum time after a change to green before another stop (pelican only)
+    pub go_time: Option<TimeDelta>,
+    /// Time the lights show amber between green and red (pelican only)
+    pub amber_time: Option<TimeDelta>,
+    /// Relative pedestrian demand at the crossing
+    #[serde(default = "default_crossing_weight")]
+    pub weight: f32
+}
+
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct ZebraConfig {
-    /// Max road speed ~ 13.4m/s
+    /// Max road speed ~ 13.4m/s, which no kind of vehicle exceeds
     pub max_speed: Speed,
-    /// The maximum acceleration value (the value that it is constantly when accelerating)
+    /// The maximum acceleration value (the value that it is constantly when accelerating) of a car,
+    /// which no kind of vehicle exceeds
     pub max_acceleration: Acceleration,
-    /// Maximum deceleration
+    /// Maximum deceleration of a car, as a positive value, which no kind of vehicle exceeds
     pub max_deceleration: Acceleration,
+    /// Deceleration of a car in an emergency stop, as a positive value, which no kind of
+    /// vehicle exceeds
+    #[serde(default = "default_max_emergency_deceleration")]
+    pub max_emergency_deceleration: Acceleration,
     /// Time for pedestrian to cross the road
     pub crossing_time: TimeDelta,
     /// Waiting time from arrival to change of pelican crossing light
     pub pelican_wait_time: TimeDelta,
     /// Minimum time after a change to green before another stop can happen
     pub pelican_go_time: TimeDelta,
+    /// Time pelican crossing lights show amber between green and red
+    #[serde(default = "default_pelican_amber_time")]
+    pub pelican_amber_time: TimeDelta,
+    /// Mean driver perception-reaction time
+    #[serde(default = "default_reaction_time")]
+    pub reaction_time: TimeDelta,
+    /// Standard deviation of driver reaction times (zero for a fixed reaction time)
+    #[serde(default = "default_reaction_time_sd")]
+    pub reaction_time_sd: TimeDelta,
+    /// Width of the road that pedestrians cross
+    #[serde(default = "default_road_width")]
+    pub road_width: Length,
+    /// Distribution of pedestrian walking speeds. Without it, pedestrians take
+    /// the crossing time of their crossing.
+    #[serde(default)]
+    pub walking_speed: Option<WalkingSpeed>,
+    /// Mean shortest time gap to approaching traffic that pedestrians at a zebra step out in
+    #[serde(default = "default_critical_gap")]
+    pub critical_gap: TimeDelta,
+    /// Standard deviation of pedestrians' critical gaps (zero for a fixed critical gap)
+    #[serde(default = "default_critical_gap_sd")]
+    pub critical_gap_sd: TimeDelta,
+    /// Mean critical gap of pedestrians crossing informally, without priority over traffic
+    #[serde(default = "default_informal_critical_gap")]
+    pub informal_critical_gap: TimeDelta,
+    /// Time after a group of pedestrians steps out onto a zebra during which new arrivals join it
+    #[serde(default = "default_group_join_time")]
+    pub group_join_time: TimeDelta,
+    /// Relative frequency of each kind of vehicle arriving
+    #[serde(default = "default_vehicle_mix")]
+    pub vehicle_mix: VehicleMix,
+    /// Dimensions and performance of each kind of vehicle, from `[vehicle.<kind>]`
+    /// tables, in place of the built-in ones
+    #[serde(default)]
+    pub vehicle: VehicleKindConfigs,
+    /// Standard deviation of drivers' desired speeds about their vehicle's max speed, which none exceed
+    #[serde(default)]
+    pub desired_speed_sd: Speed,
+    /// Standard deviation of drivers' comfortable decelerations about their vehicle's max deceleration
+    #[serde(default)]
+    pub deceleration_sd: Acceleration,
 
     /// The simulation specific config
     pub simulation: SimulationConfig,
@@ -95,10 +377,34 @@ pub struct ZebraConfig {
 
     /// Length of the road
     pub road_length: Length,
+    /// Number of lanes in each direction
+    #[serde(default = "default_lanes_per_direction")]
+    pub lanes_per_direction: usize,
+    /// Lane-change model, for roads with several lanes in each direction
+    #[serde(default)]
+    pub lane_change: LaneChange,
+    /// Car-following model, in place of the event-driven reaction model
+    #[serde(default)]
+    pub car_following: Option<CarFollowingConfig>,
+    /// Time between updates of the car-following model's accelerations
+    #[serde(default = "default_car_following_step")]
+    pub car_following_step: TimeDelta,
     
     /// Definition of crossing positions
+    #[serde(default)]
     pub zebra_crossings: Vec<Position>,
+    #[serde(default)]
     pub pelican_crossings: Vec<Position>,
+    /// Crossings with their own timings, from `[[crossing]]` tables
+    #[serde(default, rename = "crossing")]
+    pub crossings: Vec<CrossingConfig>,
+    /// Relative pedestrian demand for crossing informally, away from any crossing
+    #[serde(default)]
+    pub informal_crossing_weight: f32,
+    /// Longest detour pedestrians walk to reach a crossing. If given, they
+    /// use the nearest crossing on their way, in place of the demand weights.
+    #[serde(default)]
+    pub pedestrian_max_detour: Option<Length>,
 
     // A "catch all" for any keys that we don't define explicitly.
     #[serde(flatten)]
@@ -109,26 +415,198 @@ pub struct ZebraConfig {
 impl Default for ZebraConfig {
     fn default() -> Self {
         ZebraConfig {
-            max_speed: 13.41,
+            max_speed: MAX_SPEED,
             max_acceleration: 4.0,
             max_deceleration: 3.0,
+            max_emergency_deceleration: default_max_emergency_deceleration(),
             crossing_time: TimeDelta::from_secs(8),
             pelican_wait_time: TimeDelta::from_secs(5),
             pelican_go_time: TimeDelta::from_secs(5),
+            pelican_amber_time: default_pelican_amber_time(),
+            reaction_time: default_reaction_time(),
+            reaction_time_sd: default_reaction_time_sd(),
+            road_width: default_road_width(),
+            walking_speed: None,
+            critical_gap: default_critical_gap(),
+            critical_gap_sd: default_critical_gap_sd(),
+            informal_critical_gap: default_informal_critical_gap(),
+            group_join_time: default_group_join_time(),
+            vehicle_mix: default_vehicle_mix(),
+            vehicle: Default::default(),
+            desired_speed_sd: 0.0,
+            deceleration_sd: 0.0,
             simulation: Default::default(),
             road_length: 1000.0,
+            lanes_per_direction: default_lanes_per_direction(),
+            lane_change: Default::default(),
+            car_following: None,
+            car_following_step: default_car_following_step(),
             zebra_crossings: Vec::new(),
             pelican_crossings: Vec::new(),
+            crossings: Vec::new(),
+            informal_crossing_weight: 0.0,
+            pedestrian_max_detour: None,
             other: HashMap::new()
         }
     }
 }
 
 
+impl ZebraConfig {
+    /// Check the values that would otherwise fail later in the run.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.max_emergency_deceleration < self.max_deceleration {
+            return Err("max_emergency_deceleration is less than max_deceleration".to_string());
+        }
+        self.simulation.validate()?;
+        self.vehicle_mix.validate()?;
+        self.vehicle.validate()
+    }
+
+    /// Spec of a kind of vehicle: cars take their performance from the
+    /// config, any `[vehicle.<kind>]` table replaces the built-in values, and
+    /// no vehicle is faster, or accelerates or brakes harder, than the
+    /// top-level config allows.
+    pub fn vehicle_spec(&self, kind: VehicleKind) -> VehicleSpec {
+        let mut spec = kind.spec();
+        if kind == VehicleKind::Car {
+            spec.max_acceleration = self.max_acceleration;
+            spec.max_deceleration = -self.max_deceleration;
+            spec.max_emergency_deceleration = -self.max_emergency_deceleration;
+            spec.max_speed = self.max_speed;
+        }
+        if let Some(config) = self.vehicle.get(kind) {
+            config.apply_to(&mut spec);
+        }
+        spec.max_speed = f32::min(spec.max_speed, self.max_speed);
+        spec.max_acceleration = f32::min(spec.max_acceleration, self.max_acceleration);
+        spec.max_deceleration = f32::max(spec.max_deceleration, -self.max_deceleration);
+        // Braking in an emergency is at least as hard as normal braking.
+        spec.max_emergency_deceleration = f32::max(spec.max_emergency_deceleration, -self.max_emergency_deceleration);
+        spec.max_emergency_deceleration = f32::min(spec.max_emergency_deceleration, spec.max_deceleration);
+        spec
+    }
+
+    /// All crossings in the config, in no particular order. Their IDs are
+    /// placeholders, to be assigned when the road is built.
+    pub fn all_crossings(&self) -> Vec<(Crossing, Position)> {
+        self.crossing_configs().iter()
+            .map(|crossing| (self.build_crossing(crossing), crossing.position))
+            .collect()
+    }
+
+    /// How pedestrians choose where to cross, with the crossings in order
+    /// along the road
+    pub fn crossing_choice(&self) -> CrossingChoice {
+        match self.pedestrian_max_detour {
+            Some(max_detour) => CrossingChoice::Nearest { max_detour },
+            None => CrossingChoice::Weighted {
+                crossing_weights: self.crossing_configs().iter().map(|crossing| crossing.weight).collect(),
+                informal_weight: self.informal_crossing_weight
+            }
+        }
+    }
+
+    // Crossings from the lists and tables, in order along the road.
+    fn crossing_configs(&self) -> Vec<CrossingConfig> {
+        let listed = self.zebra_crossings.iter()
+            .map(|&position| CrossingConfig { position, kind: CrossingKind::Zebra, crossing_time: None, wait_time: None, go_time: None, amber_time: None, weight: default_crossing_weight() })
+            .chain(self.pelican_crossings.iter()
+                .map(|&position| CrossingConfig { position, kind: CrossingKind::Pelican, crossing_time: None, wait_time: None, go_time: None, amber_time: None, weight: default_crossing_weight() }));
+        let mut crossings: Vec<CrossingConfig> = listed.chain(self.crossings.iter().cloned()).collect();
+        crossings.sort_by(|x, y| x.position.partial_cmp(&y.position).unwrap());
+        crossings
+    }
+
+    fn build_crossing(&self, crossing: &CrossingConfig) -> Crossing {
+        let id = u64::MAX;
+        let crossing_time = crossing.crossing_time.unwrap_or(self.crossing_time);
+        match crossing.kind {
+            CrossingKind::Zebra => {
+                assert!(crossing.wait_time.is_none() && crossing.go_time.is_none() && crossing.amber_time.is_none(),
+                        "Zebra crossing at {} has pelican timings", crossing.position);
+                Crossing::Zebra { id, cross_time: crossing_time }
+            },
+            CrossingKind::Pelican => Crossing::Pelican {
+                id,
+                stop_time: crossing_time,
+                wait_time: crossing.wait_time.unwrap_or(self.pelican_wait_time),
+                go_time: crossing.go_time.unwrap_or(self.pelican_go_time),
+                amber_time: crossing.amber_time.unwrap_or(self.pelican_amber_time)
+            }
+        }
+    }
+
+    /// Specs of the kinds of vehicle arriving, with their relative frequencies
+    pub fn vehicle_specs(&self) -> Vec<(VehicleSpec, f32)> {
+        self.vehicle_mix.weights().into_iter()
+            .map(|(kind, weight)| (self.vehicle_spec(kind), weight))
+            .collect()
+    }
+}
+
+
+fn default_lanes_per_direction() -> usize {
+    1
+}
+
+fn default_car_following_step() -> TimeDelta {
+    TimeDelta::new(100)
+}
+
+fn default_crossing_weight() -> f32 {
+    1.0
+}
+
+fn default_direction_split() -> f32 {
+    0.5
+}
+
+fn default_pelican_amber_time() -> TimeDelta {
+    AMBER_TIME
+}
+
+fn default_reaction_time() -> TimeDelta {
+    TimeDelta::new(0)
+}
+
+fn default_reaction_time_sd() -> TimeDelta {
+    TimeDelta::new(0)
+}
+
+fn default_critical_gap() -> TimeDelta {
+    TimeDelta::new(0)
+}
+
+fn default_critical_gap_sd() -> TimeDelta {
+    TimeDelta::new(0)
+}
+
+fn default_informal_critical_gap() -> TimeDelta {
+    TimeDelta::new(0)
+}
+
+fn default_group_join_time() -> TimeDelta {
+    TimeDelta::new(0)
+}
+
+fn default_road_width() -> Length {
+    7.3
+}
+
+fn default_max_emergency_deceleration() -> Acceleration {
+    -EMERGENCY_DECCELERATION_VALUE
+}
+
+fn default_vehicle_mix() -> VehicleMix {
+    VehicleMix { car: 1.0, ..Default::default() }
+}
+
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::simulation::RateWindow;
 
     #[test]
     fn test_deserialize_simulation_config_empty() {
@@ -146,6 +624,7 @@ mod tests {
         num_vehicles = 500
         pedestrian_arrival_rate = 5
         vehicle_arrival_rate = 5
+        time_step = 100
         "#;
         let config: SimulationConfig = toml::from_slice(config_string).unwrap();
 
@@ -154,6 +633,116 @@ mod tests {
         assert_eq!(config.num_vehicles, 500);
         assert_eq!(config.pedestrian_arrival_rate, 5.0);
         assert_eq!(config.vehicle_arrival_rate, 5.0);
+        assert_eq!(config.time_step, Some(TimeDelta::new(100)));
+    }
+
+    #[test]
+    fn test_deserialize_simulation_config_headways() {
+        let config_string = br#"
+        run_time = 600_000
+        num_pedestrians = 500
+        num_vehicles = 500
+        pedestrian_arrival_rate = 5
+        vehicle_arrival_rate = 5
+
+        [vehicle_headway_up]
+        distribution = "shifted_exponential"
+        rate = 0.1
+        min_headway = 1500
+
+        [vehicle_headway_down]
+        distribution = "platoon"
+        rate = 0.1
+        cycle_time = 90_000
+        green_time = 40_000
+        saturation_headway = 2000
+        "#;
+        let config: SimulationConfig = toml::from_slice(config_string).unwrap();
+
+        assert_eq!(config.vehicle_headway, None);
+        let (up, down) = config.directional_vehicle_headways().unwrap();
+        assert_eq!(up, &HeadwayConfig::ShiftedExponential { rate: 0.1, min_headway: TimeDelta::new(1500) });
+        assert!(matches!(down, HeadwayConfig::Platoon {..}));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_one_directional_headway_panics() {
+        let config = SimulationConfig {
+            vehicle_headway_up: Some(HeadwayConfig::Exponential { rate: 0.1 }),
+            ..Default::default()
+        };
+        config.directional_vehicle_headways();
+    }
+
+    #[test]
+    fn test_deserialize_simulation_config_directional_rates() {
+        let config_string = br#"
+        run_time = 600_000
+        num_pedestrians = 500
+        num_vehicles = 500
+        pedestrian_arrival_rate = 5
+        vehicle_arrival_rate = 5
+        vehicle_arrival_rate_up = 0.3
+        vehicle_arrival_rate_down = 0.1
+        "#;
+        let config: SimulationConfig = toml::from_slice(config_string).unwrap();
+
+        assert_eq!(config.vehicle_direction_split, 0.5);
+        assert_eq!(config.directional_vehicle_arrival_rates(), Some((0.3, 0.1)));
+    }
+
+    #[test]
+    fn test_conflicting_vehicle_arrivals() {
+        let headway = HeadwayConfig::Exponential { rate: 0.1 };
+        let config = SimulationConfig { vehicle_arrival_rate_up: Some(0.3), vehicle_arrival_rate_down: Some(0.1), ..Default::default() };
+        assert!(config.validate().is_ok());
+        let config = SimulationConfig { vehicle_headway: Some(headway.clone()), ..config };
+        assert_eq!(config.validate(), Err("conflicting vehicle arrivals: give only one of vehicle_arrival_rate_up/down, vehicle_headway".to_string()));
+
+        let config = SimulationConfig {
+            vehicle_headway_up: Some(headway.clone()),
+            vehicle_headway_down: Some(headway.clone()),
+            vehicle_arrival_profile: Some(RateProfile::new(vec![RateWindow { start: 0, rate: 0.2 }])),
+            ..Default::default()
+        };
+        assert_eq!(config.validate(), Err("conflicting vehicle arrivals: give only one of vehicle_headway_up/down, vehicle_arrival_profile".to_string()));
+        let config = SimulationConfig { vehicle_arrival_profile: None, vehicle_direction_split: 0.7, ..config };
+        assert!(config.validate().is_err());
+        let config = SimulationConfig { vehicle_headway_down: None, vehicle_direction_split: 0.5, ..config };
+        assert!(config.validate().is_err());
+
+        // Reported for the whole config.
+        let config = ZebraConfig {
+            simulation: SimulationConfig { vehicle_headway: Some(headway.clone()), vehicle_arrival_profile: Some(RateProfile::new(vec![RateWindow { start: 0, rate: 0.2 }])), ..Default::default() },
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_deserialize_simulation_config_arrival_profile() {
+        let config_string = br#"
+        run_time = 600_000
+        num_pedestrians = 500
+        num_vehicles = 500
+        pedestrian_arrival_rate = 5
+        vehicle_arrival_rate = 5
+
+        [[pedestrian_arrival_profile]]
+        start = 0
+        rate = 0.05
+
+        [[pedestrian_arrival_profile]]
+        start = 300_000
+        rate = 0.5
+        "#;
+        let config: SimulationConfig = toml::from_slice(config_string).unwrap();
+
+        let profile = config.pedestrian_arrival_profile.unwrap();
+        assert_eq!(profile.rate_at(299_999), 0.05);
+        assert_eq!(profile.rate_at(300_000), 0.5);
+        assert_eq!(config.vehicle_arrival_profile, None);
     }
     #[test]
     fn test_deserialize_zebra_config_all_keys() {
@@ -180,10 +769,396 @@ mod tests {
 
         assert_eq!(config.max_acceleration, 4.);
         assert_eq!(config.max_deceleration, 3.);
+        assert_eq!(config.max_emergency_deceleration, 8.);
         assert_eq!(config.crossing_time, TimeDelta::from_secs(8));
         assert_eq!(config.pelican_wait_time, TimeDelta::from_secs(5));
         assert_eq!(config.pelican_go_time, TimeDelta::from_secs(5));
         assert_eq!(config.pelican_crossings.len(), 1);
+        assert_eq!(config.reaction_time, TimeDelta::new(0));
+        assert_eq!(config.reaction_time_sd, TimeDelta::new(0));
+        assert_eq!(config.vehicle_mix.weights(), vec![(VehicleKind::Car, 1.0)]);
+        assert_eq!(config.lane_change, LaneChange::default());
+    }
+
+    #[test]
+    fn test_deserialize_zebra_config_crossing_tables() {
+        let config_string = br#"
+        road_length = 400
+        zebra_crossings = [80]
+        max_acceleration = 4.0
+        max_deceleration = 3.0
+        crossing_time = 8000
+        pelican_wait_time = 5000
+        pelican_go_time = 5000
+        max_speed = 13.41
+
+        [[crossing]]
+        position = 200
+        kind = "pelican"
+        wait_time = 2000
+        amber_time = 4000
+
+        [[crossing]]
+        position = 300
+        kind = "zebra"
+        crossing_time = 12000
+
+        [simulation]
+        run_time = 600_000
+        num_pedestrians = 500
+        num_vehicles = 500
+        pedestrian_arrival_rate = 5
+        vehicle_arrival_rate = 5
+        "#;
+        let config: ZebraConfig = toml::from_slice(config_string).unwrap();
+
+        assert!(config.pelican_crossings.is_empty());
+        assert_eq!(config.crossings.len(), 2);
+        assert_eq!(config.crossings[0].kind, CrossingKind::Pelican);
+
+        // Timings not given for a crossing come from the top level.
+        let crossings = config.all_crossings();
+        assert_eq!(crossings.len(), 3);
+        assert_eq!(crossings[0], (Crossing::Zebra { id: u64::MAX, cross_time: TimeDelta::from_secs(8) }, 80.0));
+        assert_eq!(crossings[1].1, 200.0);
+        assert_eq!(crossings[1].0.stop_time(), TimeDelta::from_secs(8));
+        assert_eq!(crossings[1].0.arrival_to_stop_time(), TimeDelta::from_secs(2));
+        assert_eq!(crossings[1].0.min_time_to_next_stop(), TimeDelta::from_secs(5));
+        assert_eq!(crossings[1].0.amber_time(), TimeDelta::from_secs(4));
+        assert_eq!(crossings[2].0.stop_time(), TimeDelta::from_secs(12));
+        assert_eq!(config.pelican_amber_time, TimeDelta::from_secs(3));
+    }
+
+    #[test]
+    fn test_crossing_choice() {
+        let config_string = br#"
+        road_length = 400
+        zebra_crossings = [300]
+        max_acceleration = 4.0
+        max_deceleration = 3.0
+        crossing_time = 8000
+        pelican_wait_time = 5000
+        pelican_go_time = 5000
+        max_speed = 13.41
+        informal_crossing_weight = 0.5
+
+        [[crossing]]
+        position = 100
+        kind = "pelican"
+        weight = 4.0
+
+        [simulation]
+        run_time = 600_000
+        num_pedestrians = 500
+        num_vehicles = 500
+        pedestrian_arrival_rate = 5
+        vehicle_arrival_rate = 5
+        "#;
+        let mut config: ZebraConfig = toml::from_slice(config_string).unwrap();
+
+        // Weights are in order along the road, and default to one.
+        assert_eq!(config.crossing_choice(), CrossingChoice::Weighted { crossing_weights: vec![4.0, 1.0], informal_weight: 0.5 });
+
+        config.pedestrian_max_detour = Some(50.0);
+        assert_eq!(config.crossing_choice(), CrossingChoice::Nearest { max_detour: 50.0 });
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_zebra_crossing_with_pelican_timings_panics() {
+        let config = ZebraConfig {
+            crossings: vec![CrossingConfig {
+                position: 10.0,
+                kind: CrossingKind::Zebra,
+                crossing_time: None,
+                wait_time: Some(TimeDelta::from_secs(2)),
+                go_time: None,
+                amber_time: None,
+                weight: 1.0
+            }],
+            ..Default::default()
+        };
+        config.all_crossings();
+    }
+
+    #[test]
+    fn test_deserialize_zebra_config_walking_speed() {
+        let config_string = br#"
+        road_length = 400
+        zebra_crossings = [80]
+        max_acceleration = 4.0
+        max_deceleration = 3.0
+        crossing_time = 8000
+        pelican_wait_time = 5000
+        pelican_go_time = 5000
+        max_speed = 13.41
+        road_width = 9.0
+
+        [walking_speed]
+        mean = 1.3
+        sd = 0.2
+        slow_fraction = 0.1
+        slow_mean = 0.9
+        slow_sd = 0.15
+
+        [simulation]
+        run_time = 600_000
+        num_pedestrians = 500
+        num_vehicles = 500
+        pedestrian_arrival_rate = 5
+        vehicle_arrival_rate = 5
+        "#;
+        let config: ZebraConfig = toml::from_slice(config_string).unwrap();
+
+        assert_eq!(config.road_width, 9.0);
+        assert_eq!(config.walking_speed, Some(WalkingSpeed {
+            mean: 1.3,
+            sd: 0.2,
+            slow_fraction: 0.1,
+            slow_mean: 0.9,
+            slow_sd: 0.15
+        }));
+        assert_eq!(ZebraConfig::default().walking_speed, None);
+        assert_eq!(config.critical_gap, TimeDelta::new(0));
+        assert_eq!(config.critical_gap_sd, TimeDelta::new(0));
+        assert_eq!(config.group_join_time, TimeDelta::new(0));
+    }
+
+    #[test]
+    fn test_deserialize_zebra_config_vehicle_mix() {
+        let config_string = br#"
+        road_length = 400
+        zebra_crossings = [80]
+        pelican_crossings = []
+        max_acceleration = 4.0
+        max_deceleration = 3.0
+        crossing_time = 8000
+        pelican_wait_time = 5000
+        pelican_go_time = 5000
+        max_speed = 13.41
+
+        [vehicle_mix]
+        car = 0.8
+        bus = 0.05
+        hgv = 0.05
+        bicycle = 0.1
+
+        [simulation]
+        run_time = 600_000
+        num_pedestrians = 500
+        num_vehicles = 500
+        pedestrian_arrival_rate = 5
+        vehicle_arrival_rate = 5
+        "#;
+        let config: ZebraConfig = toml::from_slice(config_string).unwrap();
+
+        assert_eq!(config.vehicle_mix.car, 0.8);
+        assert_eq!(config.vehicle_mix.van, 0.0);
+        assert_eq!(config.vehicle_mix.weights(), vec![
+            (VehicleKind::Car, 0.8),
+            (VehicleKind::Bus, 0.05),
+            (VehicleKind::Hgv, 0.05),
+            (VehicleKind::Bicycle, 0.1)
+        ]);
+    }
+
+    #[test]
+    fn test_vehicle_specs() {
+        let config = ZebraConfig {
+            max_speed: 10.0,
+            max_acceleration: 2.0,
+            max_deceleration: 5.0,
+            vehicle_mix: VehicleMix { car: 0.9, bicycle: 0.1, ..Default::default() },
+            ..Default::default()
+        };
+        let specs = config.vehicle_specs();
+        assert_eq!(specs.len(), 2);
+
+        // Cars take their performance from the config, with a negative deceleration.
+        let (car, weight) = specs[0];
+        assert_eq!(weight, 0.9);
+        assert_eq!((car.kind, car.max_speed, car.max_acceleration, car.max_deceleration),
+                   (VehicleKind::Car, 10.0, 2.0, -5.0));
+
+        // Other kinds keep their own performance, up to the config's limits.
+        let (bicycle, _) = specs[1];
+        assert_eq!(bicycle, VehicleKind::Bicycle.spec());
+        let bus = config.vehicle_spec(VehicleKind::Bus);
+        assert_eq!((bus.max_speed, bus.max_acceleration, bus.max_deceleration), (10.0, 1.5, -3.5));
+        let motorbike = config.vehicle_spec(VehicleKind::Motorbike);
+        assert_eq!((motorbike.max_acceleration, motorbike.max_deceleration), (2.0, -5.0));
+        let config = ZebraConfig { max_deceleration: 3.0, ..config };
+        assert_eq!(config.vehicle_spec(VehicleKind::Bus).max_deceleration, -3.0);
+
+        // Emergency braking likewise, but never softer than normal braking.
+        let config = ZebraConfig { max_emergency_deceleration: 6.0, ..config };
+        assert_eq!(config.vehicle_spec(VehicleKind::Car).max_emergency_deceleration, -6.0);
+        assert_eq!(config.vehicle_spec(VehicleKind::Bus).max_emergency_deceleration, -5.0);
+        let config = ZebraConfig { max_deceleration: 2.5, max_emergency_deceleration: 2.5, ..config };
+        assert_eq!(config.vehicle_spec(VehicleKind::Bus).max_emergency_deceleration, -2.5);
+        assert!(config.validate().is_ok());
+        let config = ZebraConfig { max_emergency_deceleration: 2.0, ..config };
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_deserialize_zebra_config_vehicle_kinds() {
+        let config_string = br#"
+        road_length = 400
+        max_acceleration = 4.0
+        max_deceleration = 3.0
+        crossing_time = 8000
+        pelican_wait_time = 5000
+        pelican_go_time = 5000
+        max_speed = 13.41
+
+        [vehicle.bus]
+        length = 10.5
+        max_speed = 11.0
+
+        [vehicle.car]
+        max_deceleration = 2.5
+
+        [simulation]
+        run_time = 600_000
+        num_pedestrians = 500
+        num_vehicles = 500
+        pedestrian_arrival_rate = 5
+        vehicle_arrival_rate = 5
+        "#;
+        let config: ZebraConfig = toml::from_slice(config_string).unwrap();
+        assert!(config.validate().is_ok());
+
+        // Values in the tables replace the built-in spec, and others keep it.
+        let bus = config.vehicle_spec(VehicleKind::Bus);
+        assert_eq!(bus, VehicleSpec { length: 10.5, max_speed: 11.0, max_deceleration: -3.0, ..VehicleKind::Bus.spec() });
+        let car = config.vehicle_spec(VehicleKind::Car);
+        assert_eq!((car.length, car.max_acceleration, car.max_deceleration), (4.0, 4.0, -2.5));
+        assert_eq!(config.vehicle_spec(VehicleKind::Van).length, VehicleKind::Van.spec().length);
+
+        // Still within the top-level limits.
+        let config = ZebraConfig {
+            vehicle: VehicleKindConfigs { hgv: Some(VehicleKindConfig { max_speed: Some(20.0), ..Default::default() }), ..Default::default() },
+            ..config
+        };
+        assert_eq!(config.vehicle_spec(VehicleKind::Hgv).max_speed, 13.41);
+    }
+
+    #[test]
+    fn test_zebra_config_default() {
+        // Baseline performance, with the behaviour models off.
+        let config = ZebraConfig::default();
+        assert_eq!((config.max_acceleration, config.max_deceleration), (4.0, 3.0));
+        assert_eq!(config.reaction_time, TimeDelta::new(0));
+        assert_eq!(config.critical_gap, TimeDelta::new(0));
+        assert_eq!(config.informal_critical_gap, TimeDelta::new(0));
+        assert_eq!(config.group_join_time, TimeDelta::new(0));
+        assert_eq!(config.vehicle_mix.weights(), vec![(VehicleKind::Car, 1.0)]);
+        assert_eq!(config.car_following, None);
+    }
+
+    #[test]
+    fn test_validate_zebra_config() {
+        assert!(ZebraConfig::default().validate().is_ok());
+
+        // No kind of vehicle left to arrive.
+        let config = ZebraConfig { vehicle_mix: VehicleMix::default(), ..Default::default() };
+        assert!(config.validate().is_err());
+        let config = ZebraConfig { vehicle_mix: VehicleMix { car: 1.0, bus: -0.1, ..Default::default() }, ..Default::default() };
+        assert!(config.validate().is_err());
+
+        let bus = VehicleKindConfig { length: Some(0.0), ..Default::default() };
+        let config = ZebraConfig { vehicle: VehicleKindConfigs { bus: Some(bus), ..Default::default() }, ..Default::default() };
+        assert_eq!(config.validate().unwrap_err(), "vehicle.bus: length, max speed, acceleration and decelerations must be positive");
+    }
+
+    #[test]
+    fn test_deserialize_zebra_config_reaction_time() {
+        let config_string = br#"
+        road_length = 400
+        zebra_crossings = [80]
+        pelican_crossings = []
+        max_acceleration = 4.0
+        max_deceleration = 3.0
+        crossing_time = 8000
+        pelican_wait_time = 5000
+        pelican_go_time = 5000
+        max_speed = 13.41
+        reaction_time = 1500
+        reaction_time_sd = 250
+
+        [simulation]
+        run_time = 600_000
+        num_pedestrians = 500
+        num_vehicles = 500
+        pedestrian_arrival_rate = 5
+        vehicle_arrival_rate = 5
+        "#;
+        let config: ZebraConfig = toml::from_slice(config_string).unwrap();
+
+        assert_eq!(config.reaction_time, TimeDelta::new(1500));
+        assert_eq!(config.reaction_time_sd, TimeDelta::new(250));
+    }
+
+    #[test]
+    fn test_deserialize_zebra_config_lane_change() {
+        let config_string = br#"
+        road_length = 400
+        lanes_per_direction = 2
+        max_acceleration = 4.0
+        max_deceleration = 3.0
+        crossing_time = 8000
+        pelican_wait_time = 5000
+        pelican_go_time = 5000
+        max_speed = 13.41
+
+        [lane_change]
+        politeness = 0.2
+        interval = 3000
+
+        [simulation]
+        run_time = 600_000
+        num_pedestrians = 500
+        num_vehicles = 500
+        pedestrian_arrival_rate = 5
+        vehicle_arrival_rate = 5
+        "#;
+        let config: ZebraConfig = toml::from_slice(config_string).unwrap();
+
+        assert_eq!(config.lanes_per_direction, 2);
+        assert_eq!(config.lane_change.politeness, 0.2);
+        assert_eq!(config.lane_change.interval, TimeDelta::from_secs(3));
+        assert_eq!(config.lane_change.threshold, LaneChange::default().threshold);
+        assert_eq!(config.car_following, None);
+    }
+
+    #[test]
+    fn test_deserialize_zebra_config_car_following() {
+        let config_string = br#"
+        road_length = 400
+        max_acceleration = 4.0
+        max_deceleration = 3.0
+        crossing_time = 8000
+        pelican_wait_time = 5000
+        pelican_go_time = 5000
+        max_speed = 13.41
+        car_following_step = 200
+
+        [car_following]
+        model = "idm"
+        time_headway = 1200
+
+        [simulation]
+        run_time = 600_000
+        num_pedestrians = 500
+        num_vehicles = 500
+        pedestrian_arrival_rate = 5
+        vehicle_arrival_rate = 5
+        "#;
+        let config: ZebraConfig = toml::from_slice(config_string).unwrap();
+
+        assert_eq!(config.car_following, Some(CarFollowingConfig::Idm { time_headway: TimeDelta::new(1200), exponent: 4.0 }));
+        assert_eq!(config.car_following_step, TimeDelta::new(200));
     }
 
 
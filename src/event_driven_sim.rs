@@ -2,23 +2,37 @@ use rand::{SeedableRng}; // SeedableRng needed for the seed_from_u64 method.
 use rand::rngs::StdRng;
 use rand::distributions::WeightedIndex;
 use rand::prelude::Distribution;
+use rand::Rng;
+use rand_distr::Normal;
 use serde_json::to_string_pretty as to_json;
 use serde_json::to_string as to_json_flat;
+use serde::Serialize;
 
 use crate::events::{Event, EventResult, EventType};
 use crate::pedestrian::Person;
 use crate::{ID, Time, pedestrian};
-use crate::pedestrian::Pedestrian;
+use crate::{Acceleration, Length, Position, Speed};
+use crate::pedestrian::{Pedestrian, InformalPedestrian, WalkingSpeed, CrossingChoice, Route};
 use crate::time::{TimeDelta, TIME_RESOLUTION};
-use crate::simulation::{Simulation, arrival_times};
-use crate::vehicle::{self, Action, Vehicle, Car, ACCELERATION_VALUE, DECCELERATION_VALUE, MAX_SPEED};
-use crate::road::{Road, Direction, Crossing};
+use crate::simulation::{Simulation, RateProfile, arrival_times, profile_arrival_times};
+use crate::headway::{HeadwayConfig, Exponential};
+use crate::lane_change::LaneChange;
+use crate::car_following::{CarFollowingModel, CarFollowingConfig, Idm};
+use crate::trace::Trace;
+use crate::config::ZebraConfig;
+use crate::vehicle::{Action, Vehicle, VehicleKind, VehicleSpec, Car};
+use crate::road::{Road, Direction, Crossing, CROSSING_TIME};
 use crate::state::{State, SimulatorState};
-use crate::obstacle::Obstacle;
+use crate::signal::{Signal, LightPhase};
+use crate::obstacle::{Obstacle, AsObstacle};
+use crate::safety::{EmergencyStop, ObstacleKind, CollisionDetector, Kinematics, Incident, IncidentKind};
 use std::rc::Rc;
-use crate::{raw_input};
-use std::fs::{OpenOptions};
+use std::cmp::Reverse;
+use std::ops::RangeInclusive;
+use std::collections::{BTreeSet, BinaryHeap, HashMap};
+use std::fs::{self, File, OpenOptions};
 use std::io::Write;
+use log::{Level, debug, info, log_enabled, trace};
 
 // Minimum reaction to obstacle
 // const THRESHOLD_REACT: f32 = -0.001;
@@ -31,45 +45,200 @@ const MIN_DIST_TO_OBS: f32 = 0.;
 const THRESHOLD_REL_SPEED: f32 = -0.1;
 // Reciprocal of rounding used for f32 calculations
 const TIME_TO_EVENT_ROUNDING: f32 = 1000.0;
+// Delay before a vehicle retries entering when the queue reaches the road end
+const ENTRY_RETRY: TimeDelta = TimeDelta::new(1000);
+// Distance short of a pedestrian within which a vehicle in another lane
+// blocks the view of them
+const OCCLUSION_DISTANCE: f32 = 20.0;
+// Length of the zig-zag lines approaching a crossing, on which drivers must
+// not overtake
+const ZIGZAG_LENGTH: f32 = 20.0;
+
+// An obstacle as perceived by a driver: its kind, ID and whether it is
+// stopping (so that a vehicle ahead starting to brake is perceived anew).
+pub(crate) type Hazard = (ObstacleKind, ID, bool);
+
+// What a driver perceives of the road ahead: the hazards, with the times
+// they were first perceived, the time it was first perceived clear, and the
+// hazard calling for the earliest emergency stop, if any.
+#[derive(Default)]
+struct Perception {
+    hazards: Vec<(Hazard, Time)>,
+    clear: Option<Time>,
+    emergency: Option<(Time, Hazard)>
+}
+
+impl Perception {
+    // Note the hazard a braking event is for, if it is an emergency stop.
+    fn note_braking(&mut self, event: &Option<Event>, hazard: Hazard) {
+        if let Some(Event(time, EventType::EmergencyStop(_))) = event {
+            if self.emergency.is_none_or(|(earliest, _)| *time < earliest) {
+                self.emergency = Some((*time, hazard));
+            }
+        }
+    }
+}
+
+// Model drivers use to weigh up a lane change under the reaction model
+static LANE_CHANGE_MODEL: Idm = Idm { time_headway: 1.5, exponent: 4.0 };
+
+// Gap from a follower to its leader, with the leader's speed.
+fn gap_behind(follower: &dyn Vehicle, leader: &dyn Vehicle) -> (Length, Speed) {
+    (leader.get_veh_position() - leader.get_length() - follower.get_veh_position(), leader.get_speed())
+}
+
+// Distance travelled while reacting and then braking to a stop.
+fn stopping_distance(speed: Speed, reaction_secs: f32, deceleration: Acceleration) -> Length {
+    speed * reaction_secs + (speed * speed) / (2.0 * deceleration)
+}
+
+// Fastest a vehicle can travel and still stop within a distance, after
+// reacting. None if there is no room.
+fn stopping_speed(vehicle: &dyn Vehicle, room: Length) -> Option<Speed> {
+    if room <= 0.0 {
+        return None;
+    }
+    let deceleration = -vehicle.get_max_deceleration();
+    let reaction_secs: f32 = vehicle.get_reaction_time().into();
+    // Solve v * reaction + v^2 / (2 * deceleration) = room for v
+    let speed = deceleration * (-reaction_secs + f32::sqrt(reaction_secs * reaction_secs + 2.0 * room / deceleration));
+    Some(f32::min(speed, vehicle.get_max_speed()))
+}
+
+// Ensure big enough gap to brake: 13.41m/s to 0. is 3.35, so round to 3400ms
+fn space_vehicle_arrivals(veh_arrival_times: &mut [Time]) {
+    for i in 0..veh_arrival_times.len() {
+        if i > 0 && veh_arrival_times[i] - veh_arrival_times[i-1] < 3400 {
+            veh_arrival_times[i] = veh_arrival_times[i-1] + 3400;
+        }
+    }
+}
+
+// Vehicles that arrived with no room to enter the road in a direction: how
+// many are waiting, and when the first tries again.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct HeldVehicles {
+    direction: Direction,
+    count: usize,
+    retry_time: Time
+}
+
+// What a run covers: the seed the arrivals and drivers are drawn with, its
+// start and end times, and the arrival rates (per second) of pedestrians and
+// vehicles.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RunConfig {
+    pub seed: u64,
+    pub start_time: Time,
+    pub end_time: Time,
+    pub ped_arrival_rate: f32,
+    pub veh_arrival_rate: f32
+}
+
+#[cfg(test)]
+impl RunConfig {
+    // A run of 500s with no arrivals, for setting up scenarios by hand.
+    pub(crate) fn no_arrivals() -> Self {
+        RunConfig { seed: 147, start_time: 0, end_time: 500_000, ped_arrival_rate: 0.0, veh_arrival_rate: 0.0 }
+    }
+}
 
 pub struct EventDrivenSim  {
 
-    seed: u64,
     rng: StdRng,
 
     start_time: Time,
     end_time: Time,
 
-    ped_arrival_rate: f32,
-    veh_arrival_rate: f32,
-
     pub ped_arrival_times: Vec<Time>,
     pub veh_arrival_times: Vec<Time>,
+    // Direction of each vehicle arrival, if arrivals in each direction are
+    // drawn separately. Otherwise a direction is picked at random on arrival.
+    veh_arrival_directions: Vec<Direction>,
+    // Crossing used by each pedestrian, if replayed from a trace. Otherwise
+    // (or if not recorded) a crossing is picked at random on arrival.
+    ped_arrival_crossings: Vec<Option<ID>>,
+    // Fraction of randomly directed vehicles travelling up the road
+    up_fraction: f32,
+    // Index of the next vehicle arrival to take place
+    veh_arrival_counter: usize,
+    // Vehicles waiting to enter the road, in each direction with any.
+    // Arrivals in the other direction go on entering meanwhile.
+    held_vehicles: Vec<HeldVehicles>,
 
     ped_counter: ID,
     veh_counter: ID,
-    // dist: WeightedIndex<T>,
+    crossing_choice: CrossingChoice,
     pub state: Box<dyn State >,
     road: Road,
+    emergency_stops: Vec<EmergencyStop>,
+    detector: CollisionDetector,
+    reaction_time: TimeDelta,
+    reaction_time_sd: TimeDelta,
+    vehicle_mix: Vec<(VehicleSpec, f32)>,
+    desired_speed_sd: f32,
+    deceleration_sd: f32,
+    deceleration_limit: Acceleration,
+    road_width: Length,
+    walking_speed: Option<WalkingSpeed>,
+    critical_gap: TimeDelta,
+    critical_gap_sd: TimeDelta,
+    informal_critical_gap: TimeDelta,
+    group_join_time: TimeDelta,
+    lane_change: LaneChange,
+    // Car-following model, if vehicles follow one in place of the reaction
+    // model, with the time between updates of their accelerations
+    car_following: Option<Box<dyn CarFollowingModel>>,
+    car_following_step: TimeDelta,
+    car_following_updated: Option<Time>,
+    // Time each pedestrian waited before crossing
+    pedestrian_delays: Vec<(ID, TimeDelta)>,
+    // Times and positions at which pedestrians crossed informally
+    informal_crossings: Vec<(Time, Position)>,
+    // Times at which drivers last considered changing lane
+    lane_change_checks: HashMap<ID, Time>,
+    // Times at which vehicles changed lane, with the lane moved to
+    lane_changes: Vec<(Time, ID, usize)>,
+    // Times at which drivers first perceived the obstacles ahead of them
+    perceived_hazards: HashMap<ID, Vec<(Hazard, Time)>>,
+    // Times at which drivers first perceived they were free to accelerate
+    perceived_clear: HashMap<ID, Time>,
+    // Obstacles calling for the vehicles' next emergency stops
+    emergency_hazards: HashMap<ID, (ObstacleKind, ID)>,
+    // Future events of the vehicles, each with the vehicle's ID and the
+    // version of its schedule it belongs to, earliest first
+    schedule: BinaryHeap<Reverse<(Time, ID, u64, EventType)>>,
+    // Current version of each vehicle's schedule, with the time it was
+    // computed. Events of earlier versions are out of date and skipped.
+    schedule_versions: HashMap<ID, (u64, Time)>,
+    schedule_counter: u64,
+    // Vehicles whose events need recomputing, as their motion or the road
+    // ahead of them changed, or all of them
+    unscheduled: BTreeSet<ID>,
+    unscheduled_all: bool,
+    // Earliest times the pedestrians waiting to cross accept the gap in the
+    // traffic, with their positions up the road, on the current motion of
+    // the vehicles yet to clear them
+    gap_times: HashMap<ID, (Position, Option<Time>)>,
     outfile: Option<String>,
-    verbose: bool
+    // File the emergency stops and incidents are written to at the end of a run
+    records_file: Option<String>,
+    // Whether to wait for Enter before each event
+    step: bool
+}
+
+// Records of a run, written to the records file at its end.
+#[derive(Serialize)]
+struct RunRecords<'a> {
+    emergency_stops: &'a [EmergencyStop],
+    incidents: &'a [Incident]
 }
 
 impl  EventDrivenSim  {
 
-    pub fn new(
-	    seed: u64,
-        start_time: Time,
-        end_time: Time,
-        ped_arrival_rate: f32,
-        veh_arrival_rate: f32,
-	    // crossing_weights: Vec<f64>,
-        state: Box<dyn State>,
-        road: Road,
-        outfile: Option<String>,
-        verbose: bool
-    ) -> Self {
+    pub fn new(run: RunConfig, mut state: Box<dyn State>, road: Road) -> Self {
 
+        let RunConfig { seed, start_time, end_time, ped_arrival_rate, veh_arrival_rate } = run;
         assert!(end_time > start_time);
         assert!(ped_arrival_rate >= 0.0);
         assert!(veh_arrival_rate >= 0.0);
@@ -78,35 +247,68 @@ impl  EventDrivenSim  {
         // See https://stackoverflow.com/questions/59020767/how-can-i-input-an-integer-seed-for-producing-random-numbers-using-the-rand-crat
         let mut rng = StdRng::seed_from_u64(seed);
 
-    	// let dist = WeightedIndex::new(&crossing_weights).unwrap();
-
         // Generate pedestrian & vehicle arrival times.
-        let ped_arrival_times = arrival_times(&start_time, &end_time, ped_arrival_rate, &mut rng);
-        let mut veh_arrival_times = arrival_times(&start_time, &end_time, veh_arrival_rate, &mut rng);
+        let ped_arrival_times = arrival_times(&start_time, &end_time, &mut Exponential::new(ped_arrival_rate), &mut rng);
+        let mut veh_arrival_times = arrival_times(&start_time, &end_time, &mut Exponential::new(veh_arrival_rate), &mut rng);
+        space_vehicle_arrivals(&mut veh_arrival_times);
 
-        // Ensure big enough gap to brake: 13.41m/s to 0. is 3.35, so round to 3400ms
-        for i in 0..veh_arrival_times.len() { 
-            if i > 0 && veh_arrival_times[i] - veh_arrival_times[i-1] < 3400 {
-                veh_arrival_times[i] = veh_arrival_times[i-1] + 3400;
-            }
-        }
+        // Every crossing starts with its lights (if any) on green.
+        let signals = road.get_crossings(&Direction::Up)
+            .iter()
+            .map(|(crossing, position)| Signal::new(Rc::clone(crossing), *position, start_time))
+            .collect();
+        state.set_signals(signals);
 
         Self {
-            seed,
             rng,
             start_time,
             end_time,
-            ped_arrival_rate,
-            veh_arrival_rate,
             ped_arrival_times,
             veh_arrival_times,
+            veh_arrival_directions: Vec::new(),
+            ped_arrival_crossings: Vec::new(),
+            up_fraction: 0.5,
+            veh_arrival_counter: 0,
+            held_vehicles: Vec::new(),
             ped_counter: 0,
             veh_counter: 0,
-            // dist,
+            crossing_choice: CrossingChoice::uniform(road.get_crossings(&Direction::Up).len()),
             road,
             state,
-            outfile,
-            verbose
+            emergency_stops: Vec::new(),
+            detector: CollisionDetector::new(),
+            reaction_time: TimeDelta::new(0),
+            reaction_time_sd: TimeDelta::new(0),
+            vehicle_mix: vec![(VehicleKind::Car.spec(), 1.0)],
+            desired_speed_sd: 0.0,
+            deceleration_sd: 0.0,
+            deceleration_limit: f32::NEG_INFINITY,
+            road_width: 0.0,
+            walking_speed: None,
+            critical_gap: TimeDelta::new(0),
+            critical_gap_sd: TimeDelta::new(0),
+            informal_critical_gap: TimeDelta::new(0),
+            group_join_time: TimeDelta::new(0),
+            lane_change: LaneChange::default(),
+            car_following: None,
+            car_following_step: TimeDelta::new(0),
+            car_following_updated: None,
+            pedestrian_delays: Vec::new(),
+            informal_crossings: Vec::new(),
+            lane_change_checks: HashMap::new(),
+            lane_changes: Vec::new(),
+            perceived_hazards: HashMap::new(),
+            perceived_clear: HashMap::new(),
+            emergency_hazards: HashMap::new(),
+            schedule: BinaryHeap::new(),
+            schedule_versions: HashMap::new(),
+            schedule_counter: 0,
+            unscheduled: BTreeSet::new(),
+            unscheduled_all: true,
+            gap_times: HashMap::new(),
+            outfile: None,
+            records_file: None,
+            step: false
         }
     }
 
@@ -114,879 +316,3480 @@ impl  EventDrivenSim  {
     // fn set_state<'b>(&'b mut self, state: Box<dyn State>) {
     fn set_state(&mut self, state: Box<dyn State>) {
         self.state = state;
+        self.reschedule_all();
     }
 
     pub fn set_ped_arrival_times(&mut self, ped_arrival_times: Vec<Time>) {
         self.ped_arrival_times = ped_arrival_times;
+        self.ped_arrival_crossings = Vec::new();
     }
 
     pub fn set_veh_arrival_times(&mut self, veh_arrival_times: Vec<Time>) {
         self.veh_arrival_times = veh_arrival_times;
+        self.veh_arrival_directions = Vec::new();
     }
 
-    fn generate_ped(&mut self) {
-        // self.state.add_ped();
-        self.ped_counter += 1;
+    // Draw the vehicle arrivals from a headway distribution, in place of
+    // Poisson arrivals.
+    pub fn set_veh_headway(&mut self, headway: &HeadwayConfig) {
+        let veh_arrival_times = arrival_times(&self.start_time, &self.end_time, &mut *headway.build(), &mut self.rng);
+        self.set_veh_arrival_times(veh_arrival_times);
     }
 
-    fn generate_veh(&mut self) {
-        // self.state.add_veh();
-        self.veh_counter += 1;
+    // Draw the vehicle arrivals in each direction independently, from their
+    // own headway distributions.
+    pub fn set_veh_headways_by_direction(&mut self, up: &HeadwayConfig, down: &HeadwayConfig) {
+        let mut arrivals: Vec<(Time, Direction)> = Vec::new();
+        for (direction, headway) in [(Direction::Up, up), (Direction::Down, down)] {
+            let times = arrival_times(&self.start_time, &self.end_time, &mut *headway.build(), &mut self.rng);
+            arrivals.extend(times.into_iter().map(|time| (time, direction)));
+        }
+        arrivals.sort_by_key(|(time, _)| *time);
+        self.veh_arrival_times = arrivals.iter().map(|(time, _)| *time).collect();
+        self.veh_arrival_directions = arrivals.into_iter().map(|(_, direction)| direction).collect();
     }
-    // pub fn current_state() -> State {
 
-    // }
-    fn new_vehicle(&mut self) -> &dyn Vehicle {
-        let direction_dist = rand::distributions::WeightedIndex::new(&[0.5, 0.5]).unwrap();
-        // let direction_dist = rand::distributions::WeightedIndex::new(&[1., 0.]).unwrap();
-        let direction = if direction_dist.sample(&mut self.rng) == 0{
-            Direction::Up
-        } else {
-            Direction::Down
-        };
+    // Set the fraction of vehicles travelling up the road, when a single
+    // arrival stream is shared between the directions.
+    pub fn set_direction_split(&mut self, up_fraction: f32) {
+        assert!((0.0..=1.0).contains(&up_fraction));
+        self.up_fraction = up_fraction;
+    }
 
-        let vehicle = Car::new(self.veh_counter, direction, MAX_SPEED, Action::StaticSpeed);
-        // let vehicle = Car::new(self.veh_counter, direction, 0.0, Action::Accelerate);
+    // Draw Poisson vehicle arrivals in each direction independently, at
+    // their own rates.
+    pub fn set_veh_arrival_rates_by_direction(&mut self, up: f32, down: f32) {
+        self.set_veh_headways_by_direction(&HeadwayConfig::Exponential { rate: up }, &HeadwayConfig::Exponential { rate: down });
+    }
 
-        // Increment veh counter
-        self.veh_counter += 1;
+    // Draw the pedestrian arrivals from a time-varying arrival rate, in place
+    // of the constant rate.
+    pub fn set_ped_arrival_profile(&mut self, profile: &RateProfile) {
+        let ped_arrival_times = profile_arrival_times(&self.start_time, &self.end_time, profile, &mut self.rng);
+        self.set_ped_arrival_times(ped_arrival_times);
+    }
 
-        let idx = self.state.push_vehicle(Box::new(vehicle));
-        self.state.get_vehicle(idx)
+    // Draw the vehicle arrivals from a time-varying arrival rate, in place of
+    // the constant rate.
+    pub fn set_veh_arrival_profile(&mut self, profile: &RateProfile) {
+        let mut veh_arrival_times = profile_arrival_times(&self.start_time, &self.end_time, profile, &mut self.rng);
+        space_vehicle_arrivals(&mut veh_arrival_times);
+        self.set_veh_arrival_times(veh_arrival_times);
     }
-    // fn new_pedestrian(&'a mut self) -> &dyn Person {
-    // fn new_pedestrian<'b>(&'b mut self) -> &dyn Person {
-    fn new_pedestrian(&mut self) -> &dyn Person {
-        let n_crossings = self.road.get_crossings(&Direction::Up).len();
-        let idx_dist = rand::distributions::WeightedIndex::new(vec![1./n_crossings as f32; n_crossings]).unwrap();
-        let (crossing, _) = &self.road.get_crossings(&Direction::Up)[idx_dist.sample(&mut self.rng)];
 
-        let id = self.ped_counter;
-        self.ped_counter += 1;
+    // Log the state to a file, as one line of JSON, after every update.
+    pub fn set_outfile(&mut self, outfile: String) {
+        self.outfile = Some(outfile);
+    }
 
-        let pedestrian = Pedestrian::new(id, Rc::clone(crossing), *self.state.timestamp());
-        let idx =self.state.push_pedestrian(pedestrian);
-        self.state.get_pedestrian(idx)
+    // Wait for Enter before each event, for debugging.
+    pub fn set_stepping(&mut self, step: bool) {
+        self.step = step;
     }
 
-    fn remove_vehicle(&mut self, id: ID) {
-        // If all vehicles are Up, then this should hold.
-        for (idx, veh) in self.state.get_vehicles().into_iter().enumerate() {
-            if veh.get_id() == id {
-                self.state.pop_vehicle(idx);
-                break;
-            }
-        }
+    // Write the run's emergency stops and incidents to a file, as JSON, when it ends.
+    pub fn set_records_file(&mut self, records_file: String) {
+        self.records_file = Some(records_file);
     }
 
-    fn remove_pedestrian(&mut self, id: ID) {
-        for (idx, ped) in self.state.get_pedestrians().into_iter().enumerate() {
-            if ped.get_id() == id {
-                // It should always be oldest at front if crossing times are
-                // the same for all pedestrians.
-                // If this is correct, we can refactor this inefficient loop
-                // and state to do `pedestrians.pop_front();`
-                assert_eq!(idx, 0);
-                self.state.pop_pedestrian(idx);
-                break;
-            }
+    // Replay the arrivals recorded in a trace, in place of generated ones.
+    pub fn set_trace(&mut self, trace: &Trace) {
+        let (ped_arrival_times, ped_arrival_crossings): (Vec<Time>, Vec<Option<ID>>) = trace.pedestrian_arrivals().into_iter().unzip();
+        for id in ped_arrival_crossings.iter().flatten() {
+            assert!(self.road.get_crossings(&Direction::Up).iter().any(|(crossing, _)| crossing.get_id() == *id),
+                "no crossing with id {} on the road", id);
         }
+        self.ped_arrival_times = ped_arrival_times;
+        self.ped_arrival_crossings = ped_arrival_crossings;
+        (self.veh_arrival_times, self.veh_arrival_directions) = trace.vehicle_arrivals().into_iter().unzip();
     }
 
-    
-    fn time_to_exit_event<T:Obstacle + ?Sized>(
-        &self, vehicle: &dyn Vehicle,
-        obstacle: &dyn Obstacle
-    ) -> Option<f32> {
-        let rel_accel = vehicle.relative_acceleration(obstacle);
-        let rel_speed = vehicle.relative_speed(obstacle);
-        let rel_position = vehicle.relative_position(obstacle, &self.get_road());
-
-        assert!(rel_speed >= 0.0);
-
-        if rel_speed == 0.0 {
-            return None;
+    // Set how pedestrians choose where to cross.
+    pub fn set_crossing_choice(&mut self, crossing_choice: CrossingChoice) {
+        if let CrossingChoice::Weighted { crossing_weights, .. } = &crossing_choice {
+            assert_eq!(crossing_weights.len(), self.road.get_crossings(&Direction::Up).len());
         }
+        self.crossing_choice = crossing_choice;
+    }
 
-        if rel_accel == 0.0 {
-            return Some(-rel_position / rel_speed);
-        } else if rel_accel > 0.0 {
-            let t_delta = (
-                -rel_speed + f32::sqrt(rel_speed * rel_speed - 2.0 * rel_position * rel_accel)
-            )/rel_accel;
+    // Set the driver reaction time: fixed when `reaction_time_sd` is zero,
+    // otherwise sampled per driver from a normal distribution.
+    pub fn set_reaction_time(&mut self, reaction_time: TimeDelta, reaction_time_sd: TimeDelta) {
+        self.reaction_time = reaction_time;
+        self.reaction_time_sd = reaction_time_sd;
+    }
 
-            return Some(t_delta);
-        }
-        else {
-            if rel_speed * rel_speed > 2.0 * rel_position * rel_accel {
-                let t_delta = (
-                    -rel_speed + f32::sqrt(rel_speed * rel_speed - 2.0 * rel_position * rel_accel)
-                )/rel_accel;
-                return Some(t_delta);
-            }
-            return None;
-        }
+    // Set the specs of the vehicles arriving and their relative frequencies.
+    pub fn set_vehicle_mix(&mut self, vehicle_mix: Vec<(VehicleSpec, f32)>) {
+        assert!(!vehicle_mix.is_empty());
+        assert!(vehicle_mix.iter().all(|(_, weight)| *weight >= 0.0));
+        self.vehicle_mix = vehicle_mix;
     }
 
-    fn time_to_rel_speed_aim<T:Obstacle + ?Sized>(
-        &self, vehicle: &dyn Vehicle,
-        obstacle: &dyn Obstacle,
-        rel_speed_aim: f32
-    ) -> Option<f32> {
-        let rel_accel = vehicle.relative_acceleration(obstacle);
-        let rel_speed = vehicle.relative_speed(obstacle);
+    // Set the spread of drivers' desired speeds and comfortable decelerations
+    // about those of their vehicle's spec, with desired speeds no faster than
+    // the spec and no driver braking harder than `max_deceleration` (or their
+    // vehicle's emergency braking). Zero for identical drivers.
+    pub fn set_driver_variability(&mut self, desired_speed_sd: f32, deceleration_sd: f32, max_deceleration: Acceleration) {
+        assert!(desired_speed_sd >= 0.0 && deceleration_sd >= 0.0);
+        assert!(max_deceleration < 0.0);
+        self.desired_speed_sd = desired_speed_sd;
+        self.deceleration_sd = deceleration_sd;
+        self.deceleration_limit = max_deceleration;
+    }
 
-        assert!(rel_speed >= 0.0);
-        assert!(rel_accel < 0.0);
+    // Sample each pedestrian's walking speed, giving their time to cross the
+    // road. Otherwise pedestrians take the crossing time of their crossing.
+    pub fn set_walking_speed(&mut self, road_width: Length, walking_speed: WalkingSpeed) {
+        assert!(road_width > 0.0);
+        self.road_width = road_width;
+        self.walking_speed = Some(walking_speed);
+    }
 
-        Some((rel_speed - rel_speed_aim)  / rel_accel)
+    // Set the shortest time gap to approaching traffic that pedestrians at a
+    // zebra will step out in: fixed when `critical_gap_sd` is zero, otherwise
+    // sampled per pedestrian from a normal distribution.
+    pub fn set_critical_gap(&mut self, critical_gap: TimeDelta, critical_gap_sd: TimeDelta) {
+        self.critical_gap = critical_gap;
+        self.critical_gap_sd = critical_gap_sd;
     }
 
-    fn time_to_obstacle_event<T:Obstacle + ?Sized>(
-        &self, vehicle: &dyn Vehicle,
-        obstacle: &dyn Obstacle,
-        veh_acc: bool,
-        obs_dec: bool
-    ) -> Option<f32> {
+    // Set the mean critical gap of pedestrians crossing informally, who
+    // have no priority over traffic. It varies as at a zebra.
+    pub fn set_informal_critical_gap(&mut self, informal_critical_gap: TimeDelta) {
+        self.informal_critical_gap = informal_critical_gap;
+    }
 
+    // Set how long after a group of pedestrians steps out onto a zebra that
+    // new arrivals join it rather than wait for the next gap.
+    pub fn set_group_join_time(&mut self, group_join_time: TimeDelta) {
+        self.group_join_time = group_join_time;
+    }
 
-        let mut rel_accel = vehicle.relative_acceleration(obstacle);
-        let mut rel_speed = vehicle.relative_speed(obstacle);
-        let mut rel_position = vehicle.relative_position(obstacle, &self.get_road());
-        let buffer = vehicle.get_buffer_zone() + obstacle.get_obstacle_length();
+    pub fn set_lane_change(&mut self, lane_change: LaneChange) {
+        self.lane_change = lane_change;
+    }
 
-        // If in standard react mode, and:
-        //   vehicle already decelerating
-        //   OR speed and accel less than or equal to obstacle
-        // no time is returned
-        if !veh_acc && (vehicle.get_acceleration() == DECCELERATION_VALUE || (rel_speed <= 0.0 && rel_accel <= 0.0)) {
-            return None;
-        }
+    pub fn set_car_following(&mut self, car_following: &CarFollowingConfig, step: TimeDelta) {
+        assert!(step > TimeDelta::new(0));
+        self.car_following = Some(car_following.build());
+        self.car_following_step = step;
+        self.reschedule_all();
+    }
 
-        // If obs decelerating or in obs_dec mode
-        if obstacle.get_acceleration() == DECCELERATION_VALUE || obs_dec {
-            // Adjust relative values for future stopped obstacle
-            let x2 = obstacle.get_position(&self.road, &vehicle.get_direction());
-            let u2 = obstacle.get_speed();
-            let a2 = DECCELERATION_VALUE;
-            rel_position = rel_position + x2 - (x2 - ((u2 * u2) / (2.0 * a2)));
-            rel_speed = rel_speed + u2;
-            rel_accel = vehicle.get_acceleration();
+    // Set the traffic, pedestrians and drivers given in a config, on the
+    // road and run it was made with.
+    pub fn configure(&mut self, config: &ZebraConfig) {
+        let simulation = &config.simulation;
+        self.set_direction_split(simulation.vehicle_direction_split);
+        if let Some((up, down)) = simulation.directional_vehicle_arrival_rates() {
+            self.set_veh_arrival_rates_by_direction(up, down);
         }
-
-        // If switching to veh_acc mode
-        if veh_acc {
-            rel_accel = rel_accel - vehicle.get_acceleration() + ACCELERATION_VALUE;
+        if let Some(headway) = &simulation.vehicle_headway {
+            self.set_veh_headway(headway);
         }
-
-        // If already near buffer and testing veh_acc switch,
-        // do not acc so return time = 0.
-        if rel_position > -buffer && veh_acc {
-            return Some(0.0);
+        if let Some((up, down)) = simulation.directional_vehicle_headways() {
+            self.set_veh_headways_by_direction(up, down);
         }
-
-        // Vehicle must be behind obstacle
-        assert!(rel_position <= 0.0);
-
-        // Gamma value for convenience
-        let gamma = 1. - rel_accel / DECCELERATION_VALUE;
-        
-        // Case 1: rel_accel = 0
-        if rel_accel == 0. {
-            // If no relative speed
-            if rel_speed <= 0.0 {
-                return None;
-            }
-            let t_prime = (1. / rel_speed) * (-buffer + (rel_speed*rel_speed)/(2. * DECCELERATION_VALUE) - rel_position);
-            return Some(f32::round(t_prime * TIME_TO_EVENT_ROUNDING)/TIME_TO_EVENT_ROUNDING);
+        if let Some(profile) = &simulation.pedestrian_arrival_profile {
+            self.set_ped_arrival_profile(profile);
+        }
+        if let Some(profile) = &simulation.vehicle_arrival_profile {
+            self.set_veh_arrival_profile(profile);
+        }
+        self.set_crossing_choice(config.crossing_choice());
+        self.set_reaction_time(config.reaction_time, config.reaction_time_sd);
+        self.set_critical_gap(config.critical_gap, config.critical_gap_sd);
+        self.set_informal_critical_gap(config.informal_critical_gap);
+        self.set_group_join_time(config.group_join_time);
+        self.set_lane_change(config.lane_change);
+        if let Some(car_following) = &config.car_following {
+            self.set_car_following(car_following, config.car_following_step);
+        }
+        self.set_vehicle_mix(config.vehicle_specs());
+        self.set_driver_variability(config.desired_speed_sd, config.deceleration_sd, -config.max_deceleration);
+        if let Some(walking_speed) = &config.walking_speed {
+            self.set_walking_speed(config.road_width, walking_speed.clone());
         }
-        // Case 2: rel_accel != 0
-        else if rel_accel != 0.0 {
-            let t_prime = (
-                -rel_speed * gamma
-                + f32::sqrt(
-                    (rel_speed * gamma)*(rel_speed * gamma)
-                    - 2. * rel_accel * gamma * (
-                        rel_position - (rel_speed * rel_speed)/(2. * DECCELERATION_VALUE) + buffer
-                    )
-                )
-            ) / (rel_accel * gamma);
-            return Some(f32::round(t_prime * TIME_TO_EVENT_ROUNDING)/TIME_TO_EVENT_ROUNDING);
-            
-        } else {unreachable!()}
     }
-}
 
-impl  Simulation  for EventDrivenSim  {
-    // get time interval until next event
-    fn next_events(&mut self) -> Vec<Event> {
+    pub fn get_pedestrian_delays(&self) -> &[(ID, TimeDelta)] {
+        &self.pedestrian_delays
+    }
 
-        // Simulation finished event.
-        let curr_time = *self.state.timestamp();
-        let mut events= vec![Event(self.end_time, EventType::StopSimulation)];
+    pub fn get_informal_crossings(&self) -> &[(Time, Position)] {
+        &self.informal_crossings
+    }
 
-        // Pedestrian arrival events.
-        if let Some(&arrival_time) = self.ped_arrival_times.get((self.ped_counter) as usize) {
-            if arrival_time >= curr_time {
-                events.push(Event(arrival_time, EventType::PedestrianArrival));
-            }
-        }
+    pub fn get_lane_changes(&self) -> &[(Time, ID, usize)] {
+        &self.lane_changes
+    }
 
-        // Look over pedestrians to do exits
-        for ped in self.state.get_pedestrians().into_iter() {
-            events.push(Event(ped.location().stop_time() + ped.arrival_time(), EventType::PedestrianExit(ped.get_id())));
-        }
+    pub fn get_emergency_stops(&self) -> &[EmergencyStop] {
+        &self.emergency_stops
+    }
 
-        // Vehicle arrival events
-        if let Some(&arrival_time) = self.veh_arrival_times.get((self.veh_counter) as usize) {
-            if arrival_time >= curr_time {
-                events.push(Event(arrival_time, EventType::VehicleArrival));
-            }
+    pub fn get_incidents(&self) -> &[Incident] {
+        self.detector.get_incidents()
+    }
+
+    // Mean time pedestrians waited before crossing, in seconds.
+    pub fn mean_pedestrian_delay(&self) -> f32 {
+        if self.pedestrian_delays.is_empty() {
+            return 0.0;
         }
+        let total: f32 = self.pedestrian_delays.iter().map(|(_, delay)| Into::<f32>::into(delay)).sum();
+        total / self.pedestrian_delays.len() as f32
+    }
 
-        // Vehicle reaching speed limit or zero speed events.
-        let curr_vehicles = self.state.get_vehicles();
-        for (i, vehicle) in curr_vehicles.iter().enumerate() {
-            let accel = vehicle.get_acceleration();
-            if accel > 0.0 {
-                let speed_delta = vehicle::MAX_SPEED - vehicle.get_speed();
-                let t_delta = TimeDelta::floor(speed_delta / accel);
-                events.push(Event(curr_time + t_delta, EventType::SpeedLimitReached(i)));
-            } else if accel < 0.0 && vehicle.get_speed() > 0.0 {
-                let t_delta = TimeDelta::floor(vehicle.get_speed() / -vehicle.get_acceleration());
-                events.push(Event(curr_time + t_delta, EventType::ZeroSpeedReached(i)));
-            }
+    pub fn get_start_time(&self) -> Time {
+        self.start_time
+    }
 
-            // Logic to check for obstacle-related events.
-            //
-            // Exit time from treating as obstacle
-            if let Some(exit_time) = self.time_to_exit_event::<dyn Obstacle>(&**vehicle, self.road.get_exit()) {
-                let t_delta = TimeDelta::floor(exit_time);
-                events.push(Event(curr_time + t_delta, EventType::VehicleExit(vehicle.get_id())));
-            }
+    // Times of the next pedestrian and vehicle arrivals, if any remain.
+    pub fn next_arrival_times(&self) -> (Option<Time>, Option<Time>) {
+        (
+            self.ped_arrival_times.get(self.ped_counter as usize).copied(),
+            self.next_vehicle_arrival_time()
+        )
+    }
 
-            // Option for min reaction time across obstacles after a vehicles tries switching to accelerating
-            let mut min_react_after_switch: Option<f32> = None;
-            let mut min_dist_to_obs: Option<f32> = None;
-
-            // Bool for no obstacles
-            let mut no_ahead_obs = true;
-
-            // Pedestrian obstacles:
-            // Loop over pedestrians in state to get active pedestrians
-            if let Some(obstacle) = vehicle.next_pedestrian(&self.get_road(), &self.state.get_pedestrians(), *self.state.timestamp())
-            {
-                // An obstacle is present
-                no_ahead_obs = false;
-
-                // Get time braking is required to stop in time for next pedestrian
-                if let Some(t_delta) = self.time_to_obstacle_event::<dyn Obstacle>(&**vehicle, obstacle, false, false) {
-                    // If braking is too late (t_delta < THRESHOLD_REACT), let vehicle continue
-                    if t_delta >= THRESHOLD_REACT {
-                        // Round down to avoid rounding up into a danger zone
-                        events.push(Event(curr_time + TimeDelta::floor(t_delta), EventType::ReactionToObstacle(i)));
-                    }
-                    else {
-                        // Not implementated: if speed is non-zero, must emergency stop
-                        if vehicle.get_speed() != 0.0 {
-                            // events.push(Event(curr_time, EventType::EmergencyStop(i)));
-                        }
-                    }
-                }
-                // If no reaction to next pedestrian, if vehicle starts accelerating, get reaction time
-                // for braking to then begin in order to stop in time for pedestrian
-                else if let Some(t_delta) = self.time_to_obstacle_event::<dyn Obstacle>(&**vehicle, obstacle, true, false) {
-                    // Debugging
-                    if self.verbose {
-                        println!("Veh {} ped t_delta react after accel switch: {:?}", i, t_delta);
-                    }
-                    if min_react_after_switch == None {
-                        min_react_after_switch = Some(t_delta);
-                        min_dist_to_obs = Some(-vehicle.relative_position(obstacle, &self.road));
-                    }
-                }
-            }
+    // Time of the next vehicle arrival, or of the next try of a vehicle
+    // waiting to enter the road, if any.
+    fn next_vehicle_arrival_time(&self) -> Option<Time> {
+        let held = self.held_vehicles.iter().map(|held| held.retry_time);
+        self.veh_arrival_times.get(self.veh_arrival_counter).copied().into_iter().chain(held).min()
+    }
 
-            // Vehicle obstacles:
-            if let Some(ref vehicle_obstacle) = vehicle.next_vehicle(curr_vehicles) {
-                // An obstacle is present
-                no_ahead_obs = false;
+    // Write the records of the run to the records file, if any.
+    fn write_records(&self) {
+        if let Some(records_file) = &self.records_file {
+            let records = RunRecords { emergency_stops: &self.emergency_stops, incidents: self.detector.get_incidents() };
+            fs::write(records_file, to_json(&records).unwrap()).expect("Tried to write records.");
+        }
+    }
 
-                // Prevent vehicles from overtaking as shouldn't happen
-                assert!(vehicle_obstacle.get_position(&self.road, &vehicle_obstacle.get_direction()) > vehicle.get_position(&self.road, &vehicle.get_direction()));
-                assert!(vehicle_obstacle.get_id() < vehicle.get_id());
+    // Log the totals at the end of a run.
+    fn log_summary(&self) {
+        info!("Total vehicles: {}", self.veh_counter+1);
+        info!("Total pedestrians: {}", self.ped_counter+1);
+        info!("Current vehicles: {}", self.state.get_vehicles().len());
+        info!("Current pedestrians: {}", self.state.get_pedestrians().len());
+        info!("Emergency stops: {}", self.emergency_stops.len());
+        info!("Emergency stops still hitting: {}", self.emergency_stops.iter().filter(|record| record.hit).count());
+        info!("Collisions: {}", self.detector.count(IncidentKind::Collision));
+        info!("Near misses: {}", self.detector.count(IncidentKind::NearMiss));
+        info!("Mean pedestrian delay: {:.1}s", self.mean_pedestrian_delay());
+        info!("Informal crossings: {}", self.informal_crossings.len());
+        info!("Lane changes: {}", self.lane_changes.len());
+    }
 
-                if self.verbose {
-                    println!("Vehicle: {}\nhas next Vehicle: {}\n", &to_json(vehicle).unwrap(), &to_json(vehicle_obstacle).unwrap());
-                }
+    fn generate_ped(&mut self) {
+        // self.state.add_ped();
+        self.ped_counter += 1;
+    }
 
-                // Upcast vehicle_obstacle to the Base trait Obstacle.
-                let obstacle: &dyn Obstacle = vehicle_obstacle.as_obstacle();
+    fn generate_veh(&mut self) {
+        // self.state.add_veh();
+        self.veh_counter += 1;
+    }
+    // pub fn current_state() -> State {
 
-                // Get time required to start braking if next vehicle immediately starts braking now
-                if let Some(t_delta) = self.time_to_obstacle_event::<dyn Obstacle>(&**vehicle, obstacle, false, true) {
-                    // TODO: consider making t_delta, f32::max(0., t_delta) so always react even if too late.
-                    if t_delta >= THRESHOLD_REACT {
-                        // Round down to avoid rounding up into a danger zone
-                        events.push(Event(curr_time + TimeDelta::floor(t_delta), EventType::ReactionToObstacle(i)));
-                    }
-                    else {
-                        // Not implementated: if speed is non-zero, must emergency stop
-                        if vehicle.get_speed() != 0.0 {
-                            // events.push(Event(curr_time, EventType::EmergencyStop(i)));
+    // }
+    // Add an arriving vehicle to the road, the first of those waiting to
+    // enter if one is due to try again, unless the queue in its direction
+    // reaches back to the start of the road, in which case it waits to try
+    // again. A vehicle arriving behind others waiting in its direction joins
+    // them.
+    fn new_vehicle(&mut self) -> Option<&dyn Vehicle> {
+        let time = *self.state.timestamp();
+        let held = self.held_vehicles.iter()
+            .enumerate()
+            .filter(|(_, held)| held.retry_time <= time)
+            .min_by_key(|(_, held)| held.retry_time)
+            .map(|(i, _)| i);
+        let direction = match held {
+            Some(i) => self.held_vehicles[i].direction,
+            None => {
+                let direction = match self.veh_arrival_directions.get(self.veh_arrival_counter) {
+                    Some(&direction) => direction,
+                    None => {
+                        let direction_dist = rand::distributions::WeightedIndex::new([self.up_fraction, 1.0 - self.up_fraction]).unwrap();
+                        if direction_dist.sample(&mut self.rng) == 0 {
+                            Direction::Up
+                        } else {
+                            Direction::Down
                         }
                     }
+                };
+                self.veh_arrival_counter += 1;
+                if let Some(held) = self.held_vehicles.iter_mut().find(|held| held.direction == direction) {
+                    held.count += 1;
+                    return None;
                 }
-                // If no reaction to next vehicle, if vehicle starts accelerating, get reaction time assuming
-                // next vehicle immediately starts braking
-                else if let Some(t_delta) = self.time_to_obstacle_event::<dyn Obstacle>(&**vehicle, obstacle, true, true) {
-                    if min_react_after_switch == None {
-                        min_react_after_switch = Some(t_delta);
-                        // min_dist_to_obs = Some(-vehicle.relative_position(obstacle, &self.road));
-                    } else {
-                        min_react_after_switch = Some(f32::min(min_react_after_switch.unwrap(), t_delta));
-                        // min_dist_to_obs = Some(f32::min(min_dist_to_obs.unwrap(), -vehicle.relative_position(obstacle, &self.road)));
-                    }
-                }
+                direction
+            }
+        };
 
-                // If decelerating and obstacle not, get time until relative speed is slightly negative (-0.01m/s)
-                // and add event to switch to static speed ("follow") (providing no other events logged)
-                if vehicle.get_acceleration() < 0.0 && !(obstacle.get_acceleration() < 0.0) && min_react_after_switch == None {
-                    let mut rel_speed_aim = THRESHOLD_REL_SPEED;
-                    if obstacle.get_speed() < -THRESHOLD_REL_SPEED {
-                        rel_speed_aim = 0.0;
-                    }
-                    let t_delta = self.time_to_rel_speed_aim::<dyn Obstacle>(&**vehicle, obstacle, rel_speed_aim).unwrap();
-                    events.push(Event(curr_time + TimeDelta::floor(t_delta), EventType::StaticSpeedReached(i)));
+        let spec = self.sample_vehicle_spec();
+        let mut vehicle = Car::from_spec(&spec, self.veh_counter, direction, spec.max_speed, Action::StaticSpeed);
+        // let vehicle = Car::new(self.veh_counter, direction, 0.0, Action::Accelerate);
+        vehicle.set_reaction_time(self.sample_reaction_time());
+
+        // Enter the lane with the most room, nearest the kerb if several
+        // are clear.
+        let mut entry: Option<(usize, f32)> = None;
+        for lane in 0..self.road.get_lanes() {
+            vehicle.set_lane(lane);
+            if let Some(speed) = self.entry_speed(&vehicle) {
+                if entry.is_none_or(|(_, best)| speed > best) {
+                    entry = Some((lane, speed));
                 }
             }
-
-            // Debug
-            if self.verbose {
-                println!("Min react time for vehicle {} after switch: {:?}", i, min_react_after_switch);
+        }
+        match entry {
+            Some((lane, speed)) => {
+                vehicle.set_lane(lane);
+                vehicle.set_speed(speed);
             }
-
-            // If switching to accelerate causes no immediate reaction AND not top speed, accelerate
-            if vehicle.get_speed() < MAX_SPEED && vehicle.get_acceleration() != ACCELERATION_VALUE {
-                if min_react_after_switch == None {
-                    // If no obstacles are ahead, then accelerate
-                    if no_ahead_obs {
-                        events.push(Event(curr_time, EventType::VehicleAccelerate(i)));
-                    }
-                }
-                else {
-                    let t_delta = min_react_after_switch.unwrap();
-                    let dist = min_dist_to_obs;
-                    // Arbitrary time larger to ensure no looping between stop/start: THRESHOLD_ACCELERATE
-                    if dist == None || dist.unwrap() > MIN_DIST_TO_OBS {
-                        if t_delta > THRESHOLD_ACCELERATE {
-                            events.push(Event(curr_time, EventType::VehicleAccelerate(i)));
-                        }
-                    }
-                }
+            None => {
+                self.hold_vehicle(direction);
+                return None;
             }
+        }
 
+        // The next vehicle waiting behind it, if any, tries at once.
+        if let Some(i) = held {
+            let held = &mut self.held_vehicles[i];
+            held.count -= 1;
+            held.retry_time = time;
+            if held.count == 0 {
+                self.held_vehicles.remove(i);
+            }
         }
 
-        // Print if verbose
-        if self.verbose {
-            // Sort events
-            events.sort_by(|x, y| std::cmp::PartialOrd::partial_cmp(&x.0, &y.0).unwrap());
-            for (i, event) in events.iter().enumerate() {
-                println!("Event {}: {:?}", i, event);
+        // Increment veh counter
+        self.veh_counter += 1;
+
+        let idx = self.state.push_vehicle(Box::new(vehicle));
+        Some(self.state.get_vehicle(idx))
+    }
+
+    // Hold back a vehicle with no room to enter the road, to try again
+    // shortly, ahead of any others arriving in its direction meanwhile.
+    fn hold_vehicle(&mut self, direction: Direction) {
+        let retry_time = ENTRY_RETRY + *self.state.timestamp();
+        match self.held_vehicles.iter_mut().find(|held| held.direction == direction) {
+            Some(held) => held.retry_time = retry_time,
+            None => self.held_vehicles.push(HeldVehicles { direction, count: 1, retry_time })
+        }
+    }
+    // Highest speed at which a new vehicle can enter the road and still stop,
+    // after reacting, behind the vehicle ahead if that starts braking, and
+    // short of the pedestrians ahead crossing or waiting to cross at a zebra
+    // or informally, and of red lights. None if there is no room for the
+    // vehicle to enter.
+    fn entry_speed(&self, vehicle: &dyn Vehicle) -> Option<f32> {
+        let time = *self.state.timestamp();
+        let direction = vehicle.get_direction();
+        let mut speed = match vehicle.next_vehicle(self.state.get_vehicles()) {
+            Some(leader) => self.safe_speed(vehicle, &**leader)?,
+            None => vehicle.get_max_speed()
+        };
+        let pedestrians = self.state.get_pedestrians()
+            .iter()
+            .filter(|ped| ped.is_active(time) || (ped.is_waiting() && matches!(**ped.location(), Crossing::Zebra {..})))
+            .map(|ped| ped.as_obstacle());
+        let informal_pedestrians = self.state.get_informal_pedestrians()
+            .iter()
+            .filter(|ped| ped.is_active(time) || ped.is_waiting())
+            .map(|ped| ped.as_obstacle());
+        let signals = self.state.get_signals()
+            .iter()
+            .filter(|signal| signal.is_active(time))
+            .map(|signal| signal.as_obstacle());
+        for obstacle in pedestrians.chain(informal_pedestrians).chain(signals) {
+            let position = obstacle.get_position(&self.road, &direction);
+            if position <= vehicle.get_veh_position() {
+                continue;
             }
+            // Stopping short of it as on the road (see `time_to_obstacle_event`)
+            let gap = position - obstacle.get_obstacle_length() - vehicle.get_veh_position() - vehicle.get_buffer_zone();
+            speed = f32::min(speed, stopping_speed(vehicle, gap)?);
         }
+        Some(speed)
+    }
 
-        // Get minimum next event time
-        let min_time = events.iter().min().unwrap().0;
+    // Fastest a vehicle can travel behind a leader and still stop short of
+    // it, after reacting, should the leader brake hard. None if too close.
+    fn safe_speed(&self, vehicle: &dyn Vehicle, leader: &dyn Vehicle) -> Option<f32> {
+        // The leader must be clear of the vehicle (e.g. of the entrance),
+        // whatever its speed.
+        let gap = leader.get_veh_position() - leader.get_length() - vehicle.get_veh_position() - vehicle.get_buffer_zone();
+        if gap <= 0.0 {
+            return None;
+        }
+        // The leader brakes at least as hard as the vehicle can, or harder
+        // if already doing so (e.g. in an emergency stop)
+        let deceleration = -f32::min(leader.get_acceleration(), vehicle.get_max_deceleration());
+        let leader_speed = leader.get_speed();
+        stopping_speed(vehicle, gap + (leader_speed * leader_speed) / (2.0 * deceleration))
+    }
 
-        // Filter for min_time events
-        let mut events: Vec<Event> = events.into_iter().filter(|x| x.0 == min_time).collect();
+    // Positions along the road at which pedestrians cross informally: clear
+    // at each end of the distance a vehicle of any kind in the mix, arriving
+    // at its road speed, needs to stop in after reacting, so that it can
+    // stop for a pedestrian already crossing when it enters.
+    fn informal_range(&self) -> RangeInclusive<Position> {
+        let reaction_secs: f32 = self.reaction_time.into();
+        let entry_zone = self.vehicle_mix
+            .iter()
+            .filter(|(_, weight)| *weight > 0.0)
+            .map(|(spec, _)| stopping_distance(spec.max_speed, reaction_secs, -spec.max_deceleration))
+            .fold(0.0, f32::max);
+        let length = self.road.get_length();
+        let start = f32::min(entry_zone, length / 2.0);
+        start..=length - start
+    }
 
-        // Sort vec of enums so ordering has vec size mut events last:
-        // E.g. ..., VehicleExit, PedestrianExit, VehicleArrival, PedestrianArrival
-        events.sort_by(|x, y| std::cmp::PartialOrd::partial_cmp(&x.1, &y.1).unwrap());
+    // Spec of a new vehicle, drawn from the vehicle mix, with the desired
+    // speed and comfortable deceleration of its driver. No driver wants to go
+    // faster than their vehicle's max speed, itself within the road limit.
+    fn sample_vehicle_spec(&mut self) -> VehicleSpec {
+        let mut spec = if self.vehicle_mix.len() == 1 {
+            self.vehicle_mix[0].0
+        } else {
+            let weights: Vec<f32> = self.vehicle_mix.iter().map(|(_, weight)| *weight).collect();
+            let kind_dist = WeightedIndex::new(&weights).unwrap();
+            self.vehicle_mix[kind_dist.sample(&mut self.rng)].0
+        };
+        spec.max_speed = f32::min(self.sample_about(spec.max_speed, self.desired_speed_sd), spec.max_speed);
+        let deceleration = self.sample_about(spec.max_deceleration, self.deceleration_sd);
+        spec.max_deceleration = deceleration.max(self.deceleration_limit).max(spec.max_emergency_deceleration);
+        spec
+    }
 
-        // Return events
-        events
+    // Normal sample about the mean, kept within half the mean either side.
+    fn sample_about(&mut self, mean: f32, sd: f32) -> f32 {
+        if sd == 0.0 {
+            return mean;
+        }
+        let normal = Normal::<f32>::new(mean, sd).unwrap();
+        let (low, high) = if mean < 0.0 { (1.5 * mean, 0.5 * mean) } else { (0.5 * mean, 1.5 * mean) };
+        normal.sample(&mut self.rng).clamp(low, high)
     }
 
-    // roll state forward by time interval
-    fn roll_forward_by(&mut self, time_delta: TimeDelta) {
-        self.state.update(time_delta);
+    // Critical gap for a new pedestrian about the given mean, truncated at
+    // zero when sampled.
+    fn sample_critical_gap(&mut self, critical_gap: TimeDelta) -> TimeDelta {
+        if self.critical_gap_sd == TimeDelta::new(0) {
+            return critical_gap;
+        }
+        let normal = Normal::<f32>::new(critical_gap.into(), self.critical_gap_sd.into()).unwrap();
+        TimeDelta::from(f32::max(normal.sample(&mut self.rng), 0.0))
     }
 
-    // Update the state instantaneously based on the type of event.
-    fn instantaneous_update(&mut self, event: EventType) {
-        self.handle_event(event)
+    // Kerb a new pedestrian steps out from, either with equal chance. It only
+    // matters on roads with several lanes each way.
+    fn sample_kerb(&mut self) -> Direction {
+        if self.road.get_lanes() == 1 || self.rng.gen_bool(0.5) {
+            Direction::Up
+        } else {
+            Direction::Down
+        }
     }
 
-    fn get_state(&self) -> &Box<dyn State> {
-        &self.state
+    // Reaction time for a new driver, truncated at zero when sampled.
+    fn sample_reaction_time(&mut self) -> TimeDelta {
+        if self.reaction_time_sd == TimeDelta::new(0) {
+            return self.reaction_time;
+        }
+        let normal = Normal::<f32>::new(self.reaction_time.into(), self.reaction_time_sd.into()).unwrap();
+        TimeDelta::from(f32::max(normal.sample(&mut self.rng), 0.0))
     }
 
-    fn handle_event(&mut self, event: EventType) {
-        use EventType::*;
-        match event {
-            VehicleArrival => {
-                self.new_vehicle();
-                // EventResult::NewVehicle(self.new_vehicle())
-            }
-            VehicleAccelerate(idx) => {
-                let vehicle = self.state.get_mut_vehicle(idx);
-                vehicle.action(Action::Accelerate);
-                // self.new_vehicle();
-                // EventResult::NewVehicle(self.new_vehicle())
-            }
-            VehicleExit(id) => {
-                self.remove_vehicle(id);
-                // EventResult::RemoveVehicle
-            }
-            SpeedLimitReached(idx) => {
-                let vehicle = self.state.get_mut_vehicle(idx);
-                vehicle.set_speed(MAX_SPEED);
-                vehicle.action(Action::StaticSpeed);
-                // EventResult::VehicleChange(&*vehicle)
-            }
-            ZeroSpeedReached(idx) => {
-                let vehicle = self.state.get_mut_vehicle(idx);
-                vehicle.set_speed(0.0);
-                vehicle.action(Action::StaticSpeed);
-                // EventResult::VehicleChange(&*vehicle)
-            }
-            StaticSpeedReached(idx) => {
-                let vehicle = self.state.get_mut_vehicle(idx);
-                vehicle.action(Action::StaticSpeed);
+    // fn new_pedestrian(&'a mut self) -> &dyn Person {
+    // fn new_pedestrian<'b>(&'b mut self) -> &dyn Person {
+    // Add an arriving pedestrian at their chosen crossing. Pedestrians
+    // crossing informally are added at the kerb, and None is returned.
+    fn new_pedestrian(&mut self) -> Option<&dyn Person> {
+        let crossings = self.road.get_crossings(&Direction::Up);
+        let route = match self.ped_arrival_crossings.get(self.ped_counter as usize) {
+            Some(&Some(id)) => Route::Crossing(crossings.iter().position(|(crossing, _)| crossing.get_id() == id).unwrap()),
+            _ => self.crossing_choice.choose(crossings, self.road.get_length(), self.informal_range(), &mut self.rng)
+        };
+
+        let id = self.ped_counter;
+        self.ped_counter += 1;
+
+        let (crossing, _) = match route {
+            Route::Crossing(idx) => &crossings[idx],
+            Route::Informal(position) => {
+                self.new_informal_pedestrian(id, position);
+                return None;
             }
-            EmergencyStop(idx) => {
-                let vehicle = self.state.get_mut_vehicle(idx);
-                vehicle.set_speed(0.0);
-                vehicle.action(Action::StaticSpeed);
+        };
+
+        let mut pedestrian = Pedestrian::new(id, Rc::clone(crossing), *self.state.timestamp());
+        if let Some(walking_speed) = &self.walking_speed {
+            pedestrian.set_crossing_time(walking_speed.sample_crossing_time(self.road_width, &mut self.rng));
+        }
+        let critical_gap = match **crossing {
+            Crossing::Zebra {..} => self.sample_critical_gap(self.critical_gap),
+            Crossing::Pelican {..} => TimeDelta::new(0)
+        };
+        pedestrian.wait(critical_gap);
+        pedestrian.set_kerb(self.sample_kerb());
+        let idx =self.state.push_pedestrian(pedestrian);
+        Some(self.state.get_pedestrian(idx))
+    }
+
+    fn new_informal_pedestrian(&mut self, id: ID, position: Position) {
+        let time = *self.state.timestamp();
+        let crossing_time = match &self.walking_speed {
+            Some(walking_speed) => walking_speed.sample_crossing_time(self.road_width, &mut self.rng),
+            None => CROSSING_TIME
+        };
+        let critical_gap = self.sample_critical_gap(self.informal_critical_gap);
+        let mut pedestrian = InformalPedestrian::new(id, position, time, crossing_time, critical_gap);
+        pedestrian.set_kerb(self.sample_kerb());
+        self.state.push_informal_pedestrian(pedestrian);
+        self.informal_crossings.push((time, position));
+    }
+
+    fn remove_vehicle(&mut self, id: ID) {
+        if let Some(idx) = self.state.vehicle_index(id) {
+            self.reschedule(id);
+            self.state.pop_vehicle(idx);
+            self.lane_change_checks.remove(&id);
+            self.schedule_versions.remove(&id);
+            self.perceived_hazards.remove(&id);
+            self.perceived_clear.remove(&id);
+            self.emergency_hazards.remove(&id);
+        }
+    }
+
+    // Position up the road of a pedestrian at a crossing or crossing
+    // informally, if they are still on the road.
+    fn pedestrian_position(&self, id: ID) -> Option<Position> {
+        match self.state.get_pedestrians().iter().find(|ped| ped.get_id() == id) {
+            Some(ped) => Some(self.road.get_crossing_position(&ped.location().get_id(), Direction::Up)),
+            None => self.state.get_informal_pedestrians().iter().find(|ped| ped.get_id() == id).map(|ped| ped.position())
+        }
+    }
+
+    fn remove_pedestrian(&mut self, id: ID) {
+        self.gap_times.remove(&id);
+        for (idx, ped) in self.state.get_pedestrians().iter().enumerate() {
+            if ped.get_id() == id {
+                // Pedestrians at pelican crossings leave when the lights turn
+                // green, so exits are not necessarily in order of arrival.
+                self.state.pop_pedestrian(idx);
+                return;
             }
-            ReactionToObstacle(idx) => {
-                let vehicle = self.state.get_mut_vehicle(idx);
-                vehicle.action(Action::Deccelerate);
-                // EventResult::VehicleChange(&*vehicle)
+        }
+        if let Some(idx) = self.state.get_informal_pedestrians().iter().position(|ped| ped.get_id() == id) {
+            self.state.pop_informal_pedestrian(idx);
+        }
+    }
+
+    // Register the arrival of a pedestrian at the lights of their crossing.
+    fn press_button(&mut self, crossing_id: ID) {
+        let time = *self.state.timestamp();
+        self.state.get_mut_signal(crossing_id as usize).press_button(time);
+    }
+
+    // The waiting pedestrian steps out with a group, having waited since
+    // they arrived.
+    fn start_crossing(&mut self, id: ID, group: ID) {
+        let time = *self.state.timestamp();
+        let idx = self.state.get_pedestrians().iter().position(|ped| ped.get_id() == id).unwrap();
+        let pedestrian = self.state.get_mut_pedestrian(idx);
+        pedestrian.start_crossing(time, group);
+        let delay = pedestrian.delay().unwrap();
+        self.pedestrian_delays.push((id, delay));
+    }
+
+    // A new pedestrian joins the group crossing a zebra if it stepped out no
+    // more than the join time ago, and otherwise joins the queue.
+    fn join_crossing(&mut self, id: ID, crossing_id: ID) {
+        let time = *self.state.timestamp();
+        let signal = &self.state.get_signals()[crossing_id as usize];
+        if let Some((leader, start_time)) = signal.group() {
+            let joinable = signal.phase().is_none() && signal.queue().is_empty();
+            if joinable && TimeDelta::new(time - start_time) <= self.group_join_time {
+                self.start_crossing(id, leader);
+                return;
             }
-            PedestrianArrival => {
-                // EventResult::NewPedestrian(self.new_pedestrian())
-                self.new_pedestrian();
+        }
+        self.state.get_mut_signal(crossing_id as usize).join_queue(id);
+        self.start_queue_if_clear(crossing_id);
+    }
+
+    // The queue at a crossing steps out as a group if it may: at a zebra once
+    // the gap in the traffic is acceptable to the pedestrian at the front, and
+    // at a pelican once the lights are red.
+    fn start_queue_if_clear(&mut self, crossing_id: ID) {
+        let time = *self.state.timestamp();
+        let signal = &self.state.get_signals()[crossing_id as usize];
+        let head = match signal.queue().front() {
+            Some(&head) => head,
+            None => return
+        };
+        let clear = match signal.phase() {
+            Some(phase) => phase == LightPhase::Red,
+            None => {
+                let pedestrian = self.state.get_pedestrians().iter().find(|ped| ped.get_id() == head).unwrap();
+                self.time_gap_accepted(pedestrian) == Some(time)
             }
-            PedestrianExit(id) => {
-                self.remove_pedestrian(id);
-                // EventResult::RemovePedestrian
+        };
+        if clear {
+            let members = self.state.get_mut_signal(crossing_id as usize).start_group(time);
+            for id in members {
+                self.start_crossing(id, head);
             }
-            LightsToRed(idx) => {
-                let (crossing, _) = &self.road.get_crossings(&Direction::Up)[idx];
-                // TODO.
-                // EventResult::CrossingChange(crossing)
+        }
+    }
+
+    // Earliest time, on the current motion of the traffic, at which a
+    // pedestrian waiting at a zebra sees an acceptable gap: every vehicle in
+    // either direction has passed the crossing, is stopped short of it, or is
+    // at least the pedestrian's critical gap away and able to stop short of
+    // them. None if a vehicle is stopped on the crossing.
+    fn time_gap_accepted(&self, pedestrian: &Pedestrian) -> Option<Time> {
+        let position = self.road.get_crossing_position(&pedestrian.location().get_id(), Direction::Up);
+        self.time_gap_accepted_at(position, pedestrian.critical_gap())
+    }
+
+    // Earliest time at which a pedestrian waiting at the given position (in
+    // the up direction) sees a gap of at least `critical_gap`, as above.
+    fn time_gap_accepted_at(&self, position: Position, critical_gap: TimeDelta) -> Option<Time> {
+        let curr_time = *self.state.timestamp();
+        let critical_gap: f32 = critical_gap.into();
+        let up_position = position;
+
+        // Seconds until the last vehicle blocking the crossing has passed it
+        let mut wait: f32 = 0.0;
+        for vehicle in self.state.get_vehicles() {
+            let position = match vehicle.get_direction() {
+                Direction::Up => up_position,
+                Direction::Down => self.road.get_length() - up_position
+            };
+            let rear = vehicle.get_veh_position() - vehicle.get_length();
+            if rear >= position {
+                continue;
             }
-            LightsToGreen(idx) => {
-                let (crossing, _) = &self.road.get_crossings(&Direction::Up)[idx];
-                // TODO.
-                // EventResult::CrossingChange(crossing)
+            // Distance to the near edge of the pedestrian, who takes up 1m
+            // as an obstacle
+            let distance = position - 1.0 - vehicle.get_veh_position();
+            let speed = vehicle.get_speed();
+            let acceleration = vehicle.get_acceleration();
+            // Time to reach them, counting a vehicle pulling away
+            let arrival = if acceleration > 0.0 {
+                2.0 * distance / (speed + f32::sqrt(speed * speed + 2.0 * acceleration * distance))
+            } else {
+                distance / speed
+            };
+            // Whatever their gap, they do not step out in front of a vehicle
+            // that cannot stop short of them, braking normally once the
+            // driver reacts
+            let reaction_secs: f32 = vehicle.get_reaction_time().into();
+            let pull_away = f32::max(acceleration, 0.0);
+            let reaction_speed = f32::min(speed + pull_away * reaction_secs, vehicle.get_max_speed());
+            let stopping = speed * reaction_secs + 0.5 * pull_away * reaction_secs * reaction_secs
+                + (reaction_speed * reaction_speed) / (2.0 * -vehicle.get_max_deceleration());
+            let can_stop = stopping == 0.0 || distance - vehicle.get_buffer_zone() >= stopping;
+            if distance > 0.0 && arrival >= critical_gap && can_stop {
+                continue;
             }
-            StopSimulation => {
-                // EventResult::NoEffect
-                // Nothing to do.
+            if speed == 0.0 && acceleration <= 0.0 {
+                return None;
             }
-            _ => unreachable!()
+            // Time for the rear of the vehicle to pass the crossing, or for
+            // the vehicle to stop if it stops short of it.
+            let to_clear = position - rear;
+            let discriminant = speed * speed + 2.0 * acceleration * to_clear;
+            let secs = if discriminant < 0.0 {
+                -speed / acceleration
+            } else {
+                // Accurate however small the acceleration (or if none)
+                2.0 * to_clear / (speed + f32::sqrt(discriminant))
+            };
+            wait = f32::max(wait, secs);
         }
+        if wait == 0.0 {
+            return Some(curr_time);
+        }
+        // Round up, so that the gap is checked again after the vehicle moves.
+        let wait_ms = f32::ceil(wait * TIME_RESOLUTION as f32) as Time;
+        Some(TimeDelta::new(Time::max(wait_ms, 1)) + curr_time)
     }
 
-    // fn get_state(&self) -> &Box<dyn State> {
-    //     &self.state
-    // }
+    // Earliest time a waiting pedestrian accepts the gap in the traffic, as
+    // last worked out unless a vehicle yet to clear them has since changed
+    // its motion (see `reschedule`).
+    fn gap_time(&mut self, id: ID, position: Position, critical_gap: TimeDelta) -> Option<Time> {
+        let curr_time = *self.state.timestamp();
+        let gap_time = match self.gap_times.get(&id) {
+            Some(&(_, gap_time)) => gap_time,
+            None => {
+                let gap_time = self.time_gap_accepted_at(position, critical_gap);
+                self.gap_times.insert(id, (position, gap_time));
+                gap_time
+            }
+        };
+        gap_time.map(|time| Time::max(time, curr_time))
+    }
 
-    // Moved to instantaneous_update (EventResult appears to be superfluous):
-    // fn handle_event(&mut self, event: Event) -> EventResult<'_> {
-    //     use EventType::*;
-    //     match event.1 {
-    //         VehicleArrival => {
-    //             EventResult::NewVehicle(self.new_vehicle())
-    //         }
-    //         VehicleExit(idx) => {
-    //             self.remove_vehicle(idx);
-    //             EventResult::RemoveVehicle
-    //         }
-    //         SpeedLimitReached(idx) => {
-    //             let vehicle = self.state.get_mut_vehicle(idx);
-    //             vehicle.action(Action::StaticSpeed);
-    //             EventResult::VehicleChange(&*vehicle)
-    //         }
-    //         ZeroSpeedReached(idx) => {
-    //             let vehicle = self.state.get_mut_vehicle(idx);
-    //             vehicle.action(Action::StaticSpeed);
-    //             EventResult::VehicleChange(&*vehicle)
-    //         }
-    //         ReactionToObstacle(idx) => {
-    //             let vehicle = self.state.get_mut_vehicle(idx);
+    // A pedestrian waiting to cross informally steps out if they accept the
+    // gap in the traffic.
+    fn start_informal_if_clear(&mut self, id: ID) {
+        let time = *self.state.timestamp();
+        let idx = match self.state.get_informal_pedestrians().iter().position(|ped| ped.get_id() == id) {
+            Some(idx) => idx,
+            None => return
+        };
+        let pedestrian = &self.state.get_informal_pedestrians()[idx];
+        if !pedestrian.is_waiting() || self.time_gap_accepted_at(pedestrian.position(), pedestrian.critical_gap()) != Some(time) {
+            return;
+        }
+        let pedestrian = self.state.get_mut_informal_pedestrian(idx);
+        pedestrian.start_crossing(time);
+        let delay = pedestrian.delay().unwrap();
+        self.pedestrian_delays.push((id, delay));
+    }
 
-    //             EventResult::VehicleChange(&*vehicle)
-    //         }
-    //         PedestrianArrival => {
-    //             EventResult::NewPedestrian(self.new_pedestrian())
-    //         }
-    //         PedestrianExit(idx) => {
-    //             self.remove_pedestrian(idx);
-    //             EventResult::RemovePedestrian
-    //         }
-    //         LightsToRed(idx) => {
-    //             let (crossing, _) = &self.road.get_crossings(&Direction::Up)[idx];
-    //             EventResult::CrossingChange(crossing)
-    //         }
-    //         LightsToGreen(idx) => {
-    //             let (crossing, _) = &self.road.get_crossings(&Direction::Up)[idx];
-    //             EventResult::CrossingChange(crossing)
-    //         }
-    //         StopSimulation => {
-    //             EventResult::NoEffect
-    //         }
-    //         _ => unreachable!()
-    //     }
-    // }
+    // Place across the road (as in `Road::lane_slot`) reached by a pedestrian
+    // who stepped out from the given kerb, walking steadily across the lanes.
+    fn pedestrian_slot(&self, kerb: Direction, start_time: Time, crossing_time: TimeDelta) -> usize {
+        let n_slots = 2 * self.road.get_lanes();
+        let elapsed = (*self.state.timestamp() - start_time) as f32 / crossing_time.millis() as f32;
+        let slot = usize::min((elapsed * n_slots as f32) as usize, n_slots - 1);
+        match kerb {
+            Direction::Up => slot,
+            Direction::Down => n_slots - 1 - slot
+        }
+    }
 
-    fn get_road(&self) -> &Road {
-        &self.road
+    // Whether a driver's view of a pedestrian ahead, at the given position
+    // and place across the road, is blocked by a vehicle in a lane between
+    // them, just short of the pedestrian. This is the "multiple threat" at
+    // crossings over several lanes: a driver passing a vehicle that has
+    // stopped for a pedestrian does not see them until they step out in
+    // front.
+    fn pedestrian_hidden(&self, vehicle: &dyn Vehicle, position: Position, ped_slot: usize) -> bool {
+        let direction = vehicle.get_direction();
+        let driver_slot = self.road.lane_slot(direction, vehicle.get_lane());
+        if ped_slot == driver_slot {
+            return false;
+        }
+        let (low, high) = (usize::min(ped_slot, driver_slot), usize::max(ped_slot, driver_slot));
+        self.state.get_vehicles().iter().any(|other| {
+            let slot = self.road.lane_slot(other.get_direction(), other.get_lane());
+            let other_position = other.get_veh_position();
+            other.get_direction() == direction
+                && slot != driver_slot && low <= slot && slot <= high
+                && other_position > vehicle.get_veh_position()
+                && other_position < position
+                && position - other_position <= OCCLUSION_DISTANCE
+        })
     }
 
-    // TODO. Move this to the Simulation trait.
-    // Generic event-driven simulation algorithm.
-    fn run(&mut self) -> () {
+    // The nearest pedestrian crossing informally ahead of a vehicle that the
+    // driver must give way to.
+    fn next_informal_pedestrian<'a>(&'a self, vehicle: &'a dyn Vehicle) -> Option<&'a InformalPedestrian> {
+        let direction = vehicle.get_direction();
+        self.informal_pedestrians_ahead(vehicle)
+            .min_by(|x, y| x.get_position(&self.road, &direction).partial_cmp(&y.get_position(&self.road, &direction)).unwrap())
+    }
 
-        let mut file = if self.outfile.is_some() {
-            // Open a file for writing
-            Some(
-                OpenOptions::new()
-                .create(true)
-                .write(true)
-                .truncate(true)
-                .open(self.outfile.as_ref().unwrap())
-                .unwrap()
-            )
+    // The pedestrians crossing informally ahead of a vehicle that the driver
+    // sees. Without priority, they are only an obstacle if the vehicle could
+    // reach them, accelerating from its current speed, before they reach the
+    // far kerb.
+    fn informal_pedestrians_ahead<'a>(&'a self, vehicle: &'a dyn Vehicle) -> impl Iterator<Item = &'a InformalPedestrian> {
+        let curr_time = *self.state.timestamp();
+        let direction = vehicle.get_direction();
+        let speed = vehicle.get_speed();
+        let acceleration = if speed < vehicle.get_max_speed() { vehicle.get_max_acceleration() } else { 0.0 };
+        self.state.get_informal_pedestrians()
+            .iter()
+            .filter(move |ped| ped.is_active(curr_time))
+            .filter(move |ped| {
+                let position = ped.get_position(&self.road, &direction);
+                let slot = self.pedestrian_slot(ped.kerb(), ped.start_time().unwrap(), ped.crossing_time());
+                !self.pedestrian_hidden(vehicle, position, slot)
+            })
+            .filter(move |ped| {
+                let distance = ped.get_position(&self.road, &direction) - vehicle.get_veh_position();
+                if distance <= 0.0 {
+                    return false;
+                }
+                // Reaching them is coming within the buffer zone of their near edge
+                let reach = distance - ped.get_obstacle_length() - vehicle.get_buffer_zone();
+                let remaining: f32 = TimeDelta::new(ped.end_time().unwrap() - curr_time).into();
+                reach < speed * remaining + 0.5 * acceleration * remaining * remaining
+            })
+    }
+
+    // Time at which a driver next considers changing lane, if any. On a road
+    // with several lanes, drivers held up or out of the nearside lane do so
+    // once per interval.
+    pub(crate) fn lane_change_time(&self, vehicle: &dyn Vehicle) -> Option<Time> {
+        if self.road.get_lanes() == 1 || (vehicle.get_lane() == 0 && vehicle.next_vehicle(self.state.get_vehicles()).is_none()) {
+            return None;
         }
-        else {
-            None
+        let curr_time = *self.state.timestamp();
+        Some(match self.lane_change_checks.get(&vehicle.get_id()) {
+            Some(&checked) => Time::max(curr_time, checked + self.lane_change.interval),
+            None => curr_time
+        })
+    }
+
+    // Whether vehicles follow a car-following model rather than the reaction
+    // model.
+    pub(crate) fn is_car_following(&self) -> bool {
+        self.car_following.is_some()
+    }
+
+    // Nearest vehicles ahead of and behind a vehicle in a lane, in its
+    // direction of travel.
+    fn lane_neighbours(&self, vehicle: &dyn Vehicle, lane: usize) -> (Option<&dyn Vehicle>, Option<&dyn Vehicle>) {
+        let position = vehicle.get_veh_position();
+        let in_lane = self.state.get_vehicles().iter().map(|other| &**other).filter(|other| {
+            other.get_direction() == vehicle.get_direction() && other.get_lane() == lane && other.get_id() != vehicle.get_id()
+        });
+        let by_position = |x: &&dyn Vehicle, y: &&dyn Vehicle| {
+            x.get_veh_position().partial_cmp(&y.get_veh_position()).unwrap()
         };
+        let leader = in_lane.clone().filter(|other| other.get_veh_position() >= position).min_by(by_position);
+        let follower = in_lane.filter(|other| other.get_veh_position() < position).max_by(by_position);
+        (leader, follower)
+    }
 
-        let mut t: Time = 0;
-        while t < self.end_time {
-            // Debugging
-            if self.verbose && t > 0  {
-                raw_input();
-            }
+    // Distance from a vehicle to the nearest pedestrian or red light ahead,
+    // which holds up every lane alike.
+    fn obstacle_gap(&self, vehicle: &dyn Vehicle) -> Option<Length> {
+        self.next_obstacle(vehicle).map(|(obstacle, _)| {
+            obstacle.get_position(&self.road, &vehicle.get_direction()) - obstacle.get_obstacle_length() - vehicle.get_veh_position()
+        })
+    }
 
-            // Get vec of events
-            let next_events = self.next_events();
+    // What holds a driver up: the leader given, or an obstacle across the
+    // road if nearer, as the gap to it and its speed.
+    fn leader_gap(&self, vehicle: &dyn Vehicle, leader: Option<&dyn Vehicle>) -> Option<(Length, Speed)> {
+        let obstacle = self.obstacle_gap(vehicle).map(|gap| (gap, 0.0));
+        match (leader.map(|leader| gap_behind(vehicle, leader)), obstacle) {
+            (Some(x), Some(y)) => Some(if x.0 <= y.0 { x } else { y }),
+            (x, None) => x,
+            (None, y) => y
+        }
+    }
 
-            // Get time of events
-            let next_event_time = next_events[0].0;
+    // What holds a driver up under a car-following model, as in leader_gap:
+    // the vehicle ahead or an obstacle across the road, whichever is nearer.
+    fn holding_up(&self, vehicle: &dyn Vehicle) -> Option<(ObstacleKind, ID)> {
+        let leader = vehicle.next_vehicle(self.state.get_vehicles())
+            .map(|leader| (gap_behind(vehicle, &**leader).0, (ObstacleKind::Vehicle, leader.get_id())));
+        let obstacle = self.next_obstacle(vehicle).map(|(obstacle, (kind, id, _))| {
+            let gap = obstacle.get_position(&self.road, &vehicle.get_direction()) - obstacle.get_obstacle_length() - vehicle.get_veh_position();
+            (gap, (kind, id))
+        });
+        match (leader, obstacle) {
+            (Some(x), Some(y)) => Some(if x.0 <= y.0 { x.1 } else { y.1 }),
+            (x, y) => x.or(y).map(|(_, cause)| cause)
+        }
+    }
 
-            // Roll state forward to when events take place
-            self.roll_forward_by(TimeDelta::new(next_event_time - t));
+    // Car-following model driving the vehicles, or the IDM for weighing up
+    // lane changes under the reaction model.
+    fn following_model(&self) -> &dyn CarFollowingModel {
+        self.car_following.as_deref().unwrap_or(&LANE_CHANGE_MODEL)
+    }
 
-            // Loop over next events and apply state changes
-            for (i, next_event) in next_events.into_iter().enumerate() {
-                if self.verbose {
-                    println!("Time: {}; Next event {}: {:?}", t, i, next_event);
+    // Adjacent lane a driver would move to under the lane-change model, if
+    // any: one with room to move across safely, where the driver's gain
+    // outweighs the followers' losses.
+    fn lane_change_target(&self, vehicle: &dyn Vehicle) -> Option<usize> {
+        // Drivers must not overtake on the zig-zag lines approaching a crossing
+        let direction = vehicle.get_direction();
+        if vehicle.next_crossing(&self.road).is_some_and(|(crossing, _)| {
+            crossing.get_position(&self.road, &direction) - vehicle.get_veh_position() < ZIGZAG_LENGTH
+        }) {
+            return None;
+        }
+        let lane = vehicle.get_lane();
+        let model = self.following_model();
+
+        let (leader, old_follower) = self.lane_neighbours(vehicle, lane);
+        let own_before = model.acceleration(vehicle, self.leader_gap(vehicle, leader));
+        let old_follower_accels = old_follower.map_or((0.0, 0.0), |follower| (
+            model.acceleration(follower, Some(gap_behind(follower, vehicle))),
+            model.acceleration(follower, leader.map(|leader| gap_behind(follower, leader)))
+        ));
+
+        let targets = [lane.checked_sub(1), Some(lane + 1).filter(|&target| target < self.road.get_lanes())];
+        targets
+            .into_iter()
+            .flatten()
+            .filter_map(|target| {
+                let (new_leader, new_follower) = self.lane_neighbours(vehicle, target);
+                // Room to move across, as for a vehicle entering the road
+                if new_leader.is_some_and(|leader| self.safe_speed(vehicle, leader).is_none_or(|speed| speed < vehicle.get_speed())) {
+                    return None;
                 }
-                self.instantaneous_update(next_event.1);
-            }
+                if new_follower.is_some_and(|follower| self.safe_speed(follower, vehicle).is_none_or(|speed| speed < follower.get_speed())) {
+                    return None;
+                }
+                let own = (own_before, model.acceleration(vehicle, self.leader_gap(vehicle, new_leader)));
+                let new_follower_accels = new_follower.map_or((0.0, 0.0), |follower| (
+                    model.acceleration(follower, new_leader.map(|leader| gap_behind(follower, leader))),
+                    model.acceleration(follower, Some(gap_behind(follower, vehicle)))
+                ));
+                self.lane_change
+                    .advantage(own, old_follower_accels, new_follower_accels, target < lane)
+                    .map(|advantage| (target, advantage))
+            })
+            .max_by(|x, y| x.1.partial_cmp(&y.1).unwrap())
+            .map(|(target, _)| target)
+    }
 
-            // Change t
-            t = next_event_time;
+    // A driver considers changing lane, and moves across at once if it is
+    // worthwhile.
+    pub(crate) fn consider_lane_change(&mut self, idx: usize) {
+        let time = *self.state.timestamp();
+        let vehicle = self.state.get_vehicle(idx);
+        let id = vehicle.get_id();
+        if let Some(lane) = self.lane_change_target(vehicle) {
+            debug!("Vehicle {} changes to lane {}", id, lane);
+            // The followers in both lanes are affected
+            self.reschedule(id);
+            self.state.get_mut_vehicle(idx).set_lane(lane);
+            self.lane_changes.push((time, id, lane));
+        }
+        self.lane_change_checks.insert(id, time);
+        self.reschedule(id);
+    }
 
-            // Log state to file
-            match file {
-                Some(ref mut x) => {
-                    writeln!(x, "{}", &to_json_flat(self.get_state()).unwrap())
-                    .expect("Tried to write state.")
-                },
-                _ => ()
-            };
+    // Remove the pedestrians that crossed while the lights were red.
+    fn release_pedestrians(&mut self, crossing_id: ID) {
+        let time = *self.state.timestamp();
+        let ids: Vec<ID> = self.state.get_pedestrians()
+            .iter()
+            .filter(|ped| ped.location().get_id() == crossing_id && ped.arrival_time() <= time)
+            .map(|ped| ped.get_id())
+            .collect();
+        for id in ids {
+            self.remove_pedestrian(id);
+        }
+    }
 
-            // Temp prints
-            println!("---");
-            println!("State after update at time: {t}");
-            let as_json = to_json(self.get_state()).unwrap();
-            println!("{}", &as_json);
-            println!("Total vehicles: {}", self.veh_counter+1);
-            println!("Total pedestrians: {}", self.ped_counter+1);
-            println!("Current vehicles: {}", self.state.get_vehicles().len());
-            println!("Current pedestrians: {}", self.state.get_pedestrians().len());
+    // Start emergency braking for a vehicle that reacted too late to an
+    // obstacle, recording it and whether the vehicle will stop short of it.
+    // Without a known cause the record names the nearest obstacle ahead.
+    pub(crate) fn emergency_stop(&mut self, idx: usize, cause: Option<(ObstacleKind, ID)>) {
+        let time = *self.state.timestamp();
+        let vehicle = self.state.get_vehicle(idx);
+
+        let mut ahead: Vec<(ObstacleKind, ID)> = Vec::new();
+        if let Some(ped) = vehicle.next_pedestrian(&self.road, self.state.get_pedestrians(), time) {
+            ahead.push((ObstacleKind::Pedestrian, ped.group()));
+        }
+        if let Some(ped) = self.next_informal_pedestrian(vehicle) {
+            ahead.push((ObstacleKind::Pedestrian, ped.get_id()));
+        }
+        if let Some(signal) = vehicle.next_signal(&self.road, self.state.get_signals(), time) {
+            ahead.push((ObstacleKind::Signal, signal.crossing().get_id()));
+        }
+        if let Some(leader) = vehicle.next_vehicle(self.state.get_vehicles()) {
+            ahead.push((ObstacleKind::Vehicle, leader.get_id()));
         }
+        let with_edge = |(kind, id): (ObstacleKind, ID)| self.obstacle_edge(vehicle, kind, id).map(|position| (kind, id, position));
+        let obstacle = cause
+            .and_then(with_edge)
+            .or_else(|| ahead.into_iter().filter_map(with_edge).min_by(|x, y| x.2.partial_cmp(&y.2).unwrap()));
+
+        let speed = vehicle.get_speed();
+        let stop_position = vehicle.get_veh_position() - (speed * speed) / (2.0 * vehicle.get_max_emergency_deceleration());
+        if let Some((kind, obstacle_id, position)) = obstacle {
+            let record = EmergencyStop::new(vehicle.get_id(), time, speed, kind, obstacle_id, stop_position, position);
+            debug!("Emergency stop: {:?}", record);
+            self.emergency_stops.push(record);
+        }
+
+        self.state.get_mut_vehicle(idx).action(Action::EmergencyStop);
     }
-}
 
+    // Near edge of an obstacle ahead of a vehicle, with pedestrians crossing
+    // together known by their group. A vehicle ahead is taken to start
+    // braking now, as in the reaction model.
+    fn obstacle_edge(&self, vehicle: &dyn Vehicle, kind: ObstacleKind, id: ID) -> Option<Position> {
+        let direction = vehicle.get_direction();
+        let edge = |obstacle: &dyn Obstacle| obstacle.get_position(&self.road, &direction) - obstacle.get_obstacle_length();
+        match kind {
+            ObstacleKind::Pedestrian => self.state.get_pedestrians().iter()
+                .find(|ped| ped.group() == id)
+                .map(|ped| edge(ped.as_obstacle()))
+                .or_else(|| self.state.get_informal_pedestrians().iter()
+                    .find(|ped| ped.get_id() == id)
+                    .map(|ped| edge(ped.as_obstacle()))),
+            ObstacleKind::Signal => self.state.get_signals().iter()
+                .find(|signal| signal.crossing().get_id() == id)
+                .map(|signal| edge(signal.as_obstacle())),
+            ObstacleKind::Vehicle => self.state.get_vehicles().iter()
+                .find(|leader| leader.get_id() == id)
+                .map(|leader| {
+                    let speed = leader.get_speed();
+                    let accel = f32::min(leader.get_acceleration(), vehicle.get_max_deceleration());
+                    leader.get_veh_position() - leader.get_length() - (speed * speed) / (2.0 * accel)
+                })
+        }
+    }
 
-#[cfg(test)]
-mod tests {
-    use std::{collections::VecDeque};
-    use crate::vehicle::{DECCELERATION_VALUE, MAX_SPEED};
-    use super::*;
-    const MY_EPSILON: f32 = 0.001;
+    // Record the time at which an emergency stop brought a vehicle to rest.
+    pub(crate) fn complete_emergency_stop(&mut self, vehicle_id: ID) {
+        let time = *self.state.timestamp();
+        if let Some(record) = self.emergency_stops
+            .iter_mut()
+            .rev()
+            .find(|record| record.vehicle_id == vehicle_id && record.rest_time.is_none())
+        {
+            record.rest_time = Some(time);
+        }
+    }
 
-    fn dummy_sim(state: Box<dyn State >) -> EventDrivenSim  {
-        let road = Road::new(100.0, Vec::new());
-        // let state = Box::new(SimulatorState::new());
-        EventDrivenSim::new(147, 0, 500_000, 0.1, 0.2, state,  road, None, false)
+    // The nearest of two (optional) obstacles ahead of a vehicle.
+    fn nearest_obstacle<'a>(
+        &self, vehicle: &dyn Vehicle,
+        first: Option<(&'a dyn Obstacle, Hazard)>,
+        second: Option<(&'a dyn Obstacle, Hazard)>
+    ) -> Option<(&'a dyn Obstacle, Hazard)> {
+        match (first, second) {
+            (Some(x), Some(y)) => {
+                // Relative positions are negative, so the nearest is the largest.
+                if vehicle.relative_position(x.0, &self.road) >= vehicle.relative_position(y.0, &self.road) {
+                    Some(x)
+                } else {
+                    Some(y)
+                }
+            }
+            (x, None) => x,
+            (None, y) => y
+        }
     }
 
-    fn dummy_no_arrivals_sim(state: Box<dyn State >) -> EventDrivenSim  {
-        let road = Road::new(100.0, Vec::new());
-        // let state = Box::new(SimulatorState::new());
-        EventDrivenSim::new(147, 0, 500_000, 0.0, 0.0, state, road, None, false)
+    // Nearest pedestrian or red light ahead of a vehicle that its driver can
+    // see, with the hazard it presents.
+    pub(crate) fn next_obstacle<'a>(&'a self, vehicle: &'a dyn Vehicle) -> Option<(&'a dyn Obstacle, Hazard)> {
+        let time = *self.state.timestamp();
+        let next_ped = vehicle.next_pedestrian(&self.road, self.state.get_pedestrians(), time)
+            .filter(|ped| {
+                let position = ped.get_position(&self.road, &vehicle.get_direction());
+                let slot = self.pedestrian_slot(ped.kerb(), ped.start_time().unwrap(), ped.crossing_time());
+                !self.pedestrian_hidden(vehicle, position, slot)
+            });
+        let next_signal = vehicle.next_signal(&self.road, self.state.get_signals(), time);
+        let next_informal_ped = self.next_informal_pedestrian(vehicle);
+        self.nearest_obstacle(
+            vehicle,
+            self.nearest_obstacle(
+                vehicle,
+                next_ped.map(|ped| (ped.as_obstacle(), (ObstacleKind::Pedestrian, ped.group(), true))),
+                next_informal_ped.map(|ped| (ped.as_obstacle(), (ObstacleKind::Pedestrian, ped.get_id(), true)))
+            ),
+            next_signal.map(|signal| (signal.as_obstacle(), (ObstacleKind::Signal, signal.crossing().get_id(), true)))
+        )
     }
 
-    #[test]
-    fn test_set_state() {
-        let state = Box::new(SimulatorState::new());
-        let mut sim = dummy_sim(state);
-        assert_eq!(sim.state.timestamp(), &0);
+    // Vehicles approaching a crossing too close to stop comfortably short of
+    // it, after reacting and allowing for any acceleration meanwhile.
+    fn vehicles_unable_to_stop(&self, crossing_id: ID) -> Vec<ID> {
+        let signal = &self.state.get_signals()[crossing_id as usize];
+        self.state.get_vehicles().iter()
+            .filter(|vehicle| {
+                let position = signal.get_position(&self.road, &vehicle.get_direction());
+                if vehicle.get_veh_position() >= position {
+                    return false;
+                }
+                let room = position - signal.get_obstacle_length() - vehicle.get_veh_position() - vehicle.get_buffer_zone();
+                let reaction_secs: f32 = vehicle.get_reaction_time().into();
+                let speed = f32::min(vehicle.get_speed() + f32::max(vehicle.get_acceleration(), 0.0) * reaction_secs, vehicle.get_max_speed());
+                stopping_speed(&***vehicle, room).is_none_or(|max_speed| speed > max_speed)
+            })
+            .map(|vehicle| vehicle.get_id())
+            .collect()
+    }
 
-        // Construct a new state with a non-zero timestamp.
-        let new_timestamp = 10000;
-        let new_state = SimulatorState::dummy(VecDeque::new(), VecDeque::new(), new_timestamp);
+    // Every pedestrian and red light ahead of a vehicle that its driver can
+    // see, not just the nearest: the driver takes in a pedestrian stepping
+    // out behind another one crossing, and so reacts to them in time once
+    // the nearer one leaves.
+    pub(crate) fn hazards_ahead(&self, vehicle: &dyn Vehicle) -> Vec<Hazard> {
+        let time = *self.state.timestamp();
+        let direction = vehicle.get_direction();
+        let ahead = |obstacle: &dyn Obstacle| obstacle.get_position(&self.road, &direction) > vehicle.get_veh_position();
+        let pedestrians = self.state.get_pedestrians()
+            .iter()
+            .filter(|ped| ped.is_active(time) && ahead(ped.as_obstacle()))
+            .filter(|ped| {
+                let position = ped.get_position(&self.road, &direction);
+                let slot = self.pedestrian_slot(ped.kerb(), ped.start_time().unwrap(), ped.crossing_time());
+                !self.pedestrian_hidden(vehicle, position, slot)
+            })
+            .map(|ped| (ObstacleKind::Pedestrian, ped.group(), true));
+        let informal_pedestrians = self.informal_pedestrians_ahead(vehicle)
+            .map(|ped| (ObstacleKind::Pedestrian, ped.get_id(), true));
+        let signals = self.state.get_signals()
+            .iter()
+            .filter(|signal| signal.stops(vehicle.get_id(), time) && ahead(signal.as_obstacle()))
+            .map(|signal| (ObstacleKind::Signal, signal.crossing().get_id(), true));
+        pedestrians.chain(informal_pedestrians).chain(signals).collect()
+    }
 
-        // Set the simulation state.
-        sim.set_state(Box::new(new_state));
-        assert_eq!(sim.state.timestamp(), &new_timestamp);
+    // Set each vehicle's acceleration from the car-following model, to hold
+    // until the next update. Braking harder than the driver would choose is
+    // an emergency stop.
+    pub(crate) fn update_car_following(&mut self) {
+        self.car_following_updated = Some(*self.state.timestamp());
+        self.reschedule_all();
+        let model = self.car_following.as_deref().unwrap();
+        let vehicles = self.state.get_vehicles();
+        let accelerations: Vec<Acceleration> = vehicles
+            .iter()
+            .map(|vehicle| {
+                let leader = vehicle.next_vehicle(vehicles).map(|leader| &**leader);
+                model.acceleration(&**vehicle, self.leader_gap(&**vehicle, leader))
+            })
+            .collect();
+        for (idx, acceleration) in accelerations.into_iter().enumerate() {
+            let vehicle = self.state.get_vehicle(idx);
+            let comfortable = vehicle.get_max_deceleration();
+            let emergency = vehicle.get_max_emergency_deceleration();
+            if acceleration < comfortable && vehicle.get_acceleration() >= comfortable && vehicle.get_speed() > 0.0 {
+                let cause = self.holding_up(vehicle);
+                self.emergency_stop(idx, cause);
+            }
+            self.state.get_mut_vehicle(idx).set_acceleration(f32::max(acceleration, emergency));
+        }
     }
 
-    #[test]
-    fn test_set_arrival_times() {
+    // Time at which a driver perceived an obstacle, given the perceptions
+    // carried over from when the vehicle's events were last computed.
+    fn perceived_at(&self, vehicle_id: ID, hazard: Hazard) -> Time {
+        self.perceived_hazards
+            .get(&vehicle_id)
+            .and_then(|hazards| hazards.iter().find(|(perceived, _)| *perceived == hazard))
+            .map_or(*self.state.timestamp(), |&(_, time)| time)
+    }
 
-        let state = Box::new(SimulatorState::new());
-        let mut sim = dummy_sim(state);
-        assert_ne!(sim.ped_arrival_times, vec!(10000, 20000));
+    // Event for a vehicle to brake for an obstacle, given the latest time from
+    // now at which normal braking can start and the earliest time the driver
+    // can react. A driver who cannot react in time must make an emergency stop.
+    fn braking_event(&self, vehicle: &dyn Vehicle, t_delta: f32, earliest: Time) -> Option<Event> {
+        let curr_time = *self.state.timestamp();
+        // If braking is too late (t_delta < THRESHOLD_REACT), an emergency stop is needed
+        if t_delta >= THRESHOLD_REACT && curr_time + TimeDelta::floor(t_delta) >= earliest {
+            // Round down to avoid rounding up into a danger zone
+            Some(Event(curr_time + TimeDelta::floor(t_delta), EventType::ReactionToObstacle(vehicle.get_id())))
+        } else if vehicle.get_speed() != 0.0 || vehicle.get_acceleration() > 0.0 {
+            // Too late to brake normally: if moving or pulling away, must emergency stop
+            Some(Event(Time::max(curr_time, earliest), EventType::EmergencyStop(vehicle.get_id())))
+        } else {
+            None
+        }
+    }
 
-        // Construct new pedestrian arrival times.
-        let ped_arrival_times = vec!(10000, 20000);
+    // Event for a vehicle to accelerate once the driver has reacted to the
+    // road ahead becoming clear.
+    fn accelerate_event(&self, vehicle: &dyn Vehicle, perceived_clear: &mut Option<Time>) -> Event {
+        let curr_time = *self.state.timestamp();
+        let perceived = self.perceived_clear.get(&vehicle.get_id()).copied().unwrap_or(curr_time);
+        *perceived_clear = Some(perceived);
+        Event(Time::max(curr_time, vehicle.get_reaction_time() + perceived), EventType::VehicleAccelerate(vehicle.get_id()))
+    }
 
-        // Set the simulation pedestrian arrival times.
-        sim.set_ped_arrival_times(ped_arrival_times);
-        assert_eq!(sim.ped_arrival_times, vec!(10000, 20000));
+    fn time_to_exit_event<T:Obstacle + ?Sized>(
+        &self, vehicle: &dyn Vehicle,
+        obstacle: &dyn Obstacle
+    ) -> Option<f32> {
+        let rel_accel = vehicle.relative_acceleration(obstacle);
+        let rel_speed = vehicle.relative_speed(obstacle);
+        let rel_position = vehicle.relative_position(obstacle, self.get_road());
 
-        // Construct new vehicle arrival times.
-        let veh_arrival_times = vec!(12000, 21000);
+        assert!(rel_speed >= 0.0);
 
-        // Set the simulation vehicle arrival times.Car::new(0u64, Direction::Up, speed, Action::Deccelerate))
-        sim.set_veh_arrival_times(veh_arrival_times);
-        assert_eq!(sim.veh_arrival_times, vec!(12000, 21000));
+        // Already past the exit
+        if rel_position > 0.0 {
+            return Some(0.0);
+        }
+
+        // A vehicle pulling away from rest (e.g. at a red light) still exits.
+        if rel_speed == 0.0 && rel_accel <= 0.0 {
+            return None;
+        }
+
+        // Solve rel_position + rel_speed * t + rel_accel * t^2 / 2 = 0, in a
+        // form that stays accurate for the small accelerations of a
+        // car-following model (and covers zero acceleration)
+        let discriminant = rel_speed * rel_speed - 2.0 * rel_position * rel_accel;
+        if discriminant <= 0.0 {
+            return None;
+        }
+        Some(-2.0 * rel_position / (rel_speed + f32::sqrt(discriminant)))
+    }
+
+    fn time_to_rel_speed_aim<T:Obstacle + ?Sized>(
+        &self, vehicle: &dyn Vehicle,
+        obstacle: &dyn Obstacle,
+        rel_speed_aim: f32
+    ) -> Option<f32> {
+        let rel_accel = vehicle.relative_acceleration(obstacle);
+        let rel_speed = vehicle.relative_speed(obstacle);
+
+        assert!(rel_speed >= 0.0);
+        assert!(rel_accel < 0.0);
+
+        Some((rel_speed - rel_speed_aim)  / rel_accel)
+    }
+
+    fn time_to_obstacle_event<T:Obstacle + ?Sized>(
+        &self, vehicle: &dyn Vehicle,
+        obstacle: &dyn Obstacle,
+        veh_acc: bool,
+        obs_dec: bool
+    ) -> Option<f32> {
+
+
+        let mut rel_accel = vehicle.relative_acceleration(obstacle);
+        let mut rel_speed = vehicle.relative_speed(obstacle);
+        let mut rel_position = vehicle.relative_position(obstacle, self.get_road());
+        let buffer = vehicle.get_buffer_zone() + obstacle.get_obstacle_length();
+
+        // If in standard react mode, and:
+        //   vehicle already decelerating
+        //   OR speed and accel less than or equal to obstacle (unless the
+        //   obstacle might brake, as a vehicle keeping pace could still need to)
+        // no time is returned
+        let deceleration = vehicle.get_max_deceleration();
+        if !veh_acc && (vehicle.get_acceleration() <= deceleration || (!obs_dec && rel_speed <= 0.0 && rel_accel <= 0.0)) {
+            return None;
+        }
+
+        // If obs decelerating (normally or in an emergency) or in obs_dec mode
+        if obstacle.get_acceleration() < 0.0 || obs_dec {
+            // Adjust relative values for future stopped obstacle, assuming it
+            // brakes at least as hard as this vehicle can
+            let x2 = obstacle.get_position(&self.road, &vehicle.get_direction());
+            let u2 = obstacle.get_speed();
+            let a2 = f32::min(obstacle.get_acceleration(), deceleration);
+            rel_position = rel_position + x2 - (x2 - ((u2 * u2) / (2.0 * a2)));
+            rel_speed += u2;
+            rel_accel = vehicle.get_acceleration();
+        }
+
+        // If switching to veh_acc mode
+        if veh_acc {
+            rel_accel = rel_accel - vehicle.get_acceleration() + vehicle.get_max_acceleration();
+        }
+
+        // If already near buffer and testing veh_acc switch,
+        // do not acc so return time = 0.
+        if rel_position > -buffer && veh_acc {
+            return Some(0.0);
+        }
+
+        // A vehicle that has already reached the obstacle has hit it (a
+        // collision the detector records), and must brake at once
+        if rel_position > 0.0 {
+            return Some(0.0);
+        }
+
+        // Gamma value for convenience
+        let gamma = 1. - rel_accel / deceleration;
+        
+        // Case 1: rel_accel = 0
+        if rel_accel == 0. {
+            // If no relative speed
+            if rel_speed <= 0.0 {
+                return None;
+            }
+            let t_prime = (1. / rel_speed) * (-buffer + (rel_speed*rel_speed)/(2. * deceleration) - rel_position);
+            Some(f32::round(t_prime * TIME_TO_EVENT_ROUNDING)/TIME_TO_EVENT_ROUNDING)
+        }
+        // Case 2: rel_accel != 0
+        else if rel_accel != 0.0 {
+            let t_prime = (
+                -rel_speed * gamma
+                + f32::sqrt(
+                    (rel_speed * gamma)*(rel_speed * gamma)
+                    - 2. * rel_accel * gamma * (
+                        rel_position - (rel_speed * rel_speed)/(2. * deceleration) + buffer
+                    )
+                )
+            ) / (rel_accel * gamma);
+            Some(f32::round(t_prime * TIME_TO_EVENT_ROUNDING)/TIME_TO_EVENT_ROUNDING)
+            
+        } else {unreachable!()}
+    }
+
+    // Events other than those of vehicles: arrivals, pedestrians, signals,
+    // car-following updates and the end of the simulation.
+    pub(crate) fn global_events(&mut self) -> Vec<Event> {
+
+        // Simulation finished event.
+        let curr_time = *self.state.timestamp();
+        let mut events= vec![Event(self.end_time, EventType::StopSimulation)];
+
+        // Pedestrian arrival events.
+        if let Some(&arrival_time) = self.ped_arrival_times.get((self.ped_counter) as usize) {
+            if arrival_time >= curr_time {
+                events.push(Event(arrival_time, EventType::PedestrianArrival));
+            }
+        }
+
+        // Look over pedestrians to do exits, or to start crossing at a zebra,
+        // with the positions and critical gaps of those waiting to step out
+        let mut waiting = Vec::new();
+        for ped in self.state.get_pedestrians().iter() {
+            // Pedestrians at pelican crossings cross when the lights change
+            if let Crossing::Pelican {..} = **ped.location() {
+                continue;
+            }
+            match ped.start_time() {
+                Some(start_time) => events.push(Event(ped.crossing_time() + start_time, EventType::PedestrianExit(ped.get_id()))),
+                None => {
+                    // The pedestrian at the front of the queue leads the group
+                    let signal = &self.state.get_signals()[ped.location().get_id() as usize];
+                    if signal.queue().front() != Some(&ped.get_id()) {
+                        continue;
+                    }
+                    let position = self.road.get_crossing_position(&ped.location().get_id(), Direction::Up);
+                    waiting.push((ped.get_id(), position, ped.critical_gap()));
+                }
+            }
+        }
+
+        // Pedestrians crossing informally step out once they accept the gap
+        for ped in self.state.get_informal_pedestrians() {
+            match ped.end_time() {
+                Some(end_time) => events.push(Event(end_time, EventType::PedestrianExit(ped.get_id()))),
+                None => waiting.push((ped.get_id(), ped.position(), ped.critical_gap()))
+            }
+        }
+        for (id, position, critical_gap) in waiting {
+            if let Some(start_time) = self.gap_time(id, position, critical_gap) {
+                events.push(Event(start_time, EventType::PedestrianStartCrossing(id)));
+            }
+        }
+
+        // Pelican crossing light changes
+        for (i, signal) in self.state.get_signals().iter().enumerate() {
+            if let Some(change_time) = signal.next_change_time() {
+                match signal.phase() {
+                    Some(LightPhase::Green) => events.push(Event(change_time, EventType::LightsToAmber(i))),
+                    Some(LightPhase::Amber) => events.push(Event(change_time, EventType::LightsToRed(i))),
+                    Some(LightPhase::Red) => events.push(Event(change_time, EventType::LightsToGreen(i))),
+                    None => ()
+                }
+            }
+        }
+
+        // Vehicle arrival events
+        if let Some(arrival_time) = self.next_vehicle_arrival_time() {
+            if arrival_time >= curr_time {
+                events.push(Event(arrival_time, EventType::VehicleArrival));
+            }
+        }
+
+        // Car-following model updates, while there are vehicles to update
+        if self.car_following.is_some() && !self.state.get_vehicles().is_empty() {
+            let time = match self.car_following_updated {
+                Some(updated) => Time::max(curr_time, updated + self.car_following_step),
+                None => curr_time
+            };
+            events.push(Event(time, EventType::CarFollowingUpdate));
+        }
+
+        events
+    }
+
+    // Events of the vehicle at an index, on its current motion and the road
+    // ahead, with what its driver perceives.
+    fn vehicle_events(&self, i: usize) -> (Vec<Event>, Perception) {
+        let curr_time = *self.state.timestamp();
+        let curr_vehicles = self.state.get_vehicles();
+        let vehicle = &curr_vehicles[i];
+        let mut events = Vec::new();
+        let mut perception = Perception::default();
+
+        let accel = vehicle.get_acceleration();
+        if accel > 0.0 {
+            let speed_delta = vehicle.get_max_speed() - vehicle.get_speed();
+            let t_delta = TimeDelta::floor(speed_delta / accel);
+            events.push(Event(curr_time + t_delta, EventType::SpeedLimitReached(vehicle.get_id())));
+        } else if accel < 0.0 {
+            // Immediately for a braking vehicle already at rest
+            let t_delta = TimeDelta::floor(vehicle.get_speed() / -vehicle.get_acceleration());
+            events.push(Event(curr_time + t_delta, EventType::ZeroSpeedReached(vehicle.get_id())));
+        }
+
+        if let Some(time) = self.lane_change_time(&**vehicle) {
+            events.push(Event(time, EventType::LaneChange(vehicle.get_id())));
+        }
+
+        // Logic to check for obstacle-related events.
+        //
+        // Exit time from treating as obstacle
+        if let Some(exit_time) = self.time_to_exit_event::<dyn Obstacle>(&**vehicle, self.road.get_exit()) {
+            let t_delta = TimeDelta::floor(exit_time);
+            events.push(Event(curr_time + t_delta, EventType::VehicleExit(vehicle.get_id())));
+        }
+
+        // A car-following model sets accelerations at each update instead
+        if self.car_following.is_some() {
+            return (events, perception);
+        }
+
+        // Drivers only act on a change ahead once their reaction time has passed
+        let reaction_time = vehicle.get_reaction_time();
+        let reaction_secs: f32 = reaction_time.into();
+
+        // Option for min reaction time across obstacles after a vehicles tries switching to accelerating
+        let mut min_react_after_switch: Option<f32> = None;
+        let mut min_dist_to_obs: Option<f32> = None;
+
+        // Bool for no obstacles
+        let mut no_ahead_obs = true;
+
+        // Pedestrian and red light obstacles:
+        if let Some((obstacle, hazard)) = self.next_obstacle(&**vehicle)
+        {
+            // An obstacle is present
+            no_ahead_obs = false;
+            let perceived = self.perceived_at(vehicle.get_id(), hazard);
+            for other in self.hazards_ahead(&**vehicle) {
+                if other != hazard {
+                    perception.hazards.push((other, self.perceived_at(vehicle.get_id(), other)));
+                }
+            }
+            perception.hazards.push((hazard, perceived));
+
+            // Get time braking is required to stop in time for next pedestrian
+            if let Some(t_delta) = self.time_to_obstacle_event::<dyn Obstacle>(&**vehicle, obstacle, false, false) {
+                let event = self.braking_event(&**vehicle, t_delta, reaction_time + perceived);
+                perception.note_braking(&event, hazard);
+                events.extend(event);
+            }
+            // If no reaction to next pedestrian, if vehicle starts accelerating, get reaction time
+            // for braking to then begin in order to stop in time for pedestrian
+            else if let Some(t_delta) = self.time_to_obstacle_event::<dyn Obstacle>(&**vehicle, obstacle, true, false) {
+                // Braking cannot start until the driver has reacted to it
+                let reacting: f32 = TimeDelta::new(Time::max(reaction_time + perceived, curr_time) - curr_time).into();
+                let t_delta = t_delta - reacting;
+                trace!("Veh {} ped t_delta react after accel switch: {:?}", i, t_delta);
+                if min_react_after_switch.is_none() {
+                    min_react_after_switch = Some(t_delta);
+                    min_dist_to_obs = Some(-vehicle.relative_position(obstacle, &self.road));
+                }
+            }
+        }
+
+        // Vehicle obstacles:
+        if let Some(ref vehicle_obstacle) = vehicle.next_vehicle(curr_vehicles) {
+            // An obstacle is present
+            no_ahead_obs = false;
+
+            if log_enabled!(Level::Trace) {
+                trace!("Vehicle: {}\nhas next Vehicle: {}\n", &to_json(vehicle).unwrap(), &to_json(vehicle_obstacle).unwrap());
+            }
+
+            // Upcast vehicle_obstacle to the Base trait Obstacle.
+            let obstacle: &dyn Obstacle = vehicle_obstacle.as_obstacle();
+
+            // A vehicle ahead that is braking or stopped is a hazard the driver reacts to
+            let stopping = obstacle.get_acceleration() < 0.0 || obstacle.get_speed() == 0.0;
+            let hazard = (ObstacleKind::Vehicle, vehicle_obstacle.get_id(), stopping);
+            let perceived = self.perceived_at(vehicle.get_id(), hazard);
+            perception.hazards.push((hazard, perceived));
+
+            // Otherwise it may start braking at any moment, so keep enough headway to
+            // cover the distance travelled while reacting, braking normally if closer
+            let headway = |t_delta: f32| if stopping {
+                t_delta
+            } else {
+                f32::max(t_delta - reaction_secs, f32::min(t_delta, 0.0))
+            };
+
+            // Get time required to start braking if next vehicle immediately starts braking now
+            if let Some(t_delta) = self.time_to_obstacle_event::<dyn Obstacle>(&**vehicle, obstacle, false, true) {
+                // TODO: consider making t_delta, f32::max(0., t_delta) so always react even if too late.
+                let earliest = if stopping { reaction_time + perceived } else { curr_time };
+                let event = self.braking_event(&**vehicle, headway(t_delta), earliest);
+                perception.note_braking(&event, hazard);
+                events.extend(event);
+            }
+            // If no reaction to next vehicle, if vehicle starts accelerating, get reaction time assuming
+            // next vehicle immediately starts braking
+            else if let Some(t_delta) = self.time_to_obstacle_event::<dyn Obstacle>(&**vehicle, obstacle, true, true) {
+                let t_delta = headway(t_delta);
+                if min_react_after_switch.is_none() {
+                    min_react_after_switch = Some(t_delta);
+                    // min_dist_to_obs = Some(-vehicle.relative_position(obstacle, &self.road));
+                } else {
+                    min_react_after_switch = Some(f32::min(min_react_after_switch.unwrap(), t_delta));
+                    // min_dist_to_obs = Some(f32::min(min_dist_to_obs.unwrap(), -vehicle.relative_position(obstacle, &self.road)));
+                }
+            }
+
+            // If decelerating and obstacle not, get time until relative speed is slightly negative (-0.01m/s)
+            // and add event to switch to static speed ("follow") (providing no other events logged)
+            if vehicle.get_acceleration() < 0.0 && obstacle.get_acceleration() >= 0.0 && min_react_after_switch.is_none() {
+                let mut rel_speed_aim = THRESHOLD_REL_SPEED;
+                if obstacle.get_speed() < -THRESHOLD_REL_SPEED {
+                    rel_speed_aim = 0.0;
+                }
+                let t_delta = self.time_to_rel_speed_aim::<dyn Obstacle>(&**vehicle, obstacle, rel_speed_aim).unwrap();
+                events.push(Event(curr_time + TimeDelta::floor(t_delta), EventType::StaticSpeedReached(vehicle.get_id())));
+            }
+        }
+
+        trace!("Min react time for vehicle {} after switch: {:?}", i, min_react_after_switch);
+
+        // If switching to accelerate causes no immediate reaction AND not top speed, accelerate
+        if vehicle.get_speed() < vehicle.get_max_speed() && vehicle.get_acceleration() != vehicle.get_max_acceleration() {
+            if let Some(t_delta) = min_react_after_switch {
+                let dist = min_dist_to_obs;
+                // Arbitrary time larger to ensure no looping between stop/start: THRESHOLD_ACCELERATE
+                if dist.is_none_or(|dist| dist > MIN_DIST_TO_OBS) && t_delta > THRESHOLD_ACCELERATE {
+                    events.push(self.accelerate_event(&**vehicle, &mut perception.clear));
+                }
+            }
+            // If no obstacles are ahead, then accelerate
+            else if no_ahead_obs {
+                events.push(self.accelerate_event(&**vehicle, &mut perception.clear));
+            }
+        }
+
+        (events, perception)
+    }
+
+    // Recompute the events of a vehicle whose motion changed, and of the
+    // vehicle following it in its lane. Short of a pedestrian crossing ahead,
+    // it may block the view of them from the other lanes, so the vehicles
+    // behind it there are recomputed too. The gaps seen by the pedestrians
+    // waiting ahead of it are worked out again.
+    fn reschedule(&mut self, id: ID) {
+        if let Some(idx) = self.state.vehicle_index(id) {
+            let vehicle = self.state.get_vehicle(idx);
+            let (_, follower) = self.lane_neighbours(vehicle, vehicle.get_lane());
+            let mut ids: Vec<ID> = follower.map(|follower| follower.get_id()).into_iter().collect();
+            let time = *self.state.timestamp();
+            if self.road.get_lanes() > 1 && vehicle.next_pedestrian(&self.road, self.state.get_pedestrians(), time).is_some() {
+                ids.extend(self.state.get_vehicles()
+                    .iter()
+                    .filter(|other| other.get_direction() == vehicle.get_direction()
+                        && other.get_lane() != vehicle.get_lane()
+                        && other.get_veh_position() < vehicle.get_veh_position())
+                    .map(|other| other.get_id()));
+            }
+            let direction = vehicle.get_direction();
+            let rear = vehicle.get_veh_position() - vehicle.get_length();
+            let length = self.road.get_length();
+            self.gap_times.retain(|_, &mut (position, _)| match direction {
+                Direction::Up => rear >= position,
+                Direction::Down => rear >= length - position
+            });
+            self.unscheduled.extend(ids);
+        }
+        self.unscheduled.insert(id);
+    }
+
+    // Recompute the events of the vehicles yet to reach a place on the road,
+    // given by its position up the road, as when a pedestrian steps out or
+    // leaves there or the lights change.
+    fn reschedule_approaching(&mut self, position: Position) {
+        let length = self.road.get_length();
+        let ids: Vec<ID> = self.state.get_vehicles()
+            .iter()
+            .filter(|vehicle| vehicle.get_veh_position() < match vehicle.get_direction() {
+                Direction::Up => position,
+                Direction::Down => length - position
+            })
+            .map(|vehicle| vehicle.get_id())
+            .collect();
+        self.unscheduled.extend(ids);
+    }
+
+    // Recompute the events of every vehicle, as when the road ahead of them
+    // changes. Needed after changing the state other than through events.
+    pub fn reschedule_all(&mut self) {
+        self.unscheduled_all = true;
+        self.unscheduled.clear();
+        self.gap_times.clear();
+    }
+
+    // Recompute the events of the vehicles due to be rescheduled, making
+    // their earlier events out of date.
+    fn update_schedule(&mut self) {
+        let ids: Vec<ID> = if self.unscheduled_all {
+            self.state.get_vehicles().iter().map(|vehicle| vehicle.get_id()).collect()
+        } else {
+            self.unscheduled.iter().copied().collect()
+        };
+        self.unscheduled.clear();
+        self.unscheduled_all = false;
+        for id in ids {
+            self.schedule_vehicle(id);
+        }
+    }
+
+    // Compute the events of a vehicle as a new version of its schedule.
+    fn schedule_vehicle(&mut self, id: ID) {
+        // Vehicles that have left the road have no events
+        let idx = match self.state.vehicle_index(id) {
+            Some(idx) => idx,
+            None => return
+        };
+        let (events, perception) = self.vehicle_events(idx);
+        self.schedule_counter += 1;
+        let version = self.schedule_counter;
+        self.schedule_versions.insert(id, (version, *self.state.timestamp()));
+        self.perceived_hazards.insert(id, perception.hazards);
+        match perception.clear {
+            Some(time) => self.perceived_clear.insert(id, time),
+            None => self.perceived_clear.remove(&id)
+        };
+        match perception.emergency {
+            Some((_, (kind, obstacle_id, _))) => self.emergency_hazards.insert(id, (kind, obstacle_id)),
+            None => self.emergency_hazards.remove(&id)
+        };
+        for Event(time, event) in events {
+            self.schedule.push(Reverse((time, id, version, event)));
+        }
+    }
+
+    // Drop out-of-date events from the front of the schedule. An event due
+    // next that was computed before now is computed afresh, as the driver's
+    // decision (e.g. to accelerate) may depend on how the road ahead has
+    // since developed.
+    fn discard_stale_events(&mut self) {
+        let curr_time = *self.state.timestamp();
+        while let Some(Reverse((_, id, version, _))) = self.schedule.peek() {
+            match self.schedule_versions.get(id) {
+                Some(&(current, computed)) if current == *version => {
+                    if computed == curr_time {
+                        break;
+                    }
+                    let id = *id;
+                    self.schedule_vehicle(id);
+                }
+                _ => {
+                    self.schedule.pop();
+                }
+            }
+        }
+    }
+
+    // Time of the next event, with the schedule brought up to date.
+    fn next_event_time(&mut self) -> Time {
+        self.update_schedule();
+        self.discard_stale_events();
+        let time = self.global_events().iter().min().unwrap().0;
+        match self.schedule.peek() {
+            Some(Reverse((scheduled, ..))) => Time::min(time, *scheduled),
+            None => time
+        }
+    }
+
+    // Take the events falling due before a time, unordered. The events of
+    // vehicles taken from the schedule are recomputed before the next are.
+    fn take_events_before(&mut self, limit: Time) -> Vec<Event> {
+        self.update_schedule();
+        let mut events: Vec<Event> = self.global_events().into_iter().filter(|event| event.0 < limit).collect();
+        loop {
+            self.discard_stale_events();
+            match self.schedule.peek() {
+                Some(Reverse((time, ..))) if *time < limit => (),
+                _ => break
+            }
+            let Reverse((time, id, _, event)) = self.schedule.pop().unwrap();
+            events.push(Event(time, event));
+            self.unscheduled.insert(id);
+        }
+        events
+    }
+}
+
+impl  Simulation  for EventDrivenSim  {
+    // Get the next event, first in the order of events (by time, type and id).
+    // Simultaneous events are handled one at a time: the others are recomputed
+    // once it has been handled, so an event that it makes out of date is
+    // dropped and one that it brings about at the same time is handled too.
+    fn next_events(&mut self) -> Vec<Event> {
+        // Get minimum next event time
+        let min_time = self.next_event_time();
+
+        // Take the events at that time. Those not handled now are recomputed,
+        // as vehicles taken from the schedule are.
+        let mut events = self.take_events_before(min_time + 1);
+        events.sort();
+        events.truncate(1);
+
+        trace!("Next event: {:?}", events[0]);
+
+        // Return events
+        events
+    }
+
+    // roll state forward by time interval
+    fn roll_forward_by(&mut self, time_delta: TimeDelta) {
+        let start_time = *self.state.timestamp();
+        let previous = Kinematics::of_vehicles(&*self.state);
+        self.state.update(time_delta);
+
+        // Record any collisions or near misses during the step
+        let incidents = self.detector.check(&*self.state, &self.road, &previous, start_time);
+        if log_enabled!(Level::Debug) {
+            for incident in incidents {
+                debug!("Incident: {}", &to_json_flat(incident).unwrap());
+            }
+        }
+    }
+
+    // Update the state instantaneously based on the type of event.
+    fn instantaneous_update(&mut self, event: EventType) {
+        self.handle_event(event)
+    }
+
+    fn get_state(&self) -> &Box<dyn State> {
+        &self.state
+    }
+
+    fn handle_event(&mut self, event: EventType) {
+        use EventType::*;
+        // Index of the vehicle the event is for, if any. Events for a vehicle
+        // that has since left the road have no effect.
+        let idx = match event {
+            VehicleAccelerate(id) | SpeedLimitReached(id) | ZeroSpeedReached(id) | StaticSpeedReached(id)
+                | ReactionToObstacle(id) | LaneChange(id) | EmergencyStop(id) => match self.state.vehicle_index(id) {
+                Some(idx) => Some(idx),
+                None => return
+            },
+            _ => None
+        };
+        // Vehicle whose motion the event changes, if any
+        let moved = match event {
+            VehicleAccelerate(id) | SpeedLimitReached(id) | ZeroSpeedReached(id)
+                | StaticSpeedReached(id) | EmergencyStop(id) | ReactionToObstacle(id) => Some(id),
+            _ => None
+        };
+        match event {
+            VehicleArrival => {
+                if let Some(id) = self.new_vehicle().map(|vehicle| vehicle.get_id()) {
+                    self.reschedule(id);
+                }
+                // EventResult::NewVehicle(self.new_vehicle())
+            }
+            VehicleAccelerate(_) => {
+                let vehicle = self.state.get_mut_vehicle(idx.unwrap());
+                vehicle.action(Action::Accelerate);
+                // self.new_vehicle();
+                // EventResult::NewVehicle(self.new_vehicle())
+            }
+            VehicleExit(id) => {
+                self.remove_vehicle(id);
+                // EventResult::RemoveVehicle
+            }
+            SpeedLimitReached(_) => {
+                let vehicle = self.state.get_mut_vehicle(idx.unwrap());
+                vehicle.set_speed(vehicle.get_max_speed());
+                vehicle.action(Action::StaticSpeed);
+                // EventResult::VehicleChange(&*vehicle)
+            }
+            ZeroSpeedReached(_) => {
+                let vehicle = self.state.get_mut_vehicle(idx.unwrap());
+                let emergency = vehicle.is_emergency_stopping();
+                let id = vehicle.get_id();
+                vehicle.set_speed(0.0);
+                vehicle.action(Action::StaticSpeed);
+                if emergency {
+                    self.complete_emergency_stop(id);
+                }
+                // EventResult::VehicleChange(&*vehicle)
+            }
+            StaticSpeedReached(_) => {
+                let vehicle = self.state.get_mut_vehicle(idx.unwrap());
+                vehicle.action(Action::StaticSpeed);
+            }
+            EmergencyStop(id) => {
+                let cause = self.emergency_hazards.get(&id).copied();
+                self.emergency_stop(idx.unwrap(), cause);
+            }
+            ReactionToObstacle(_) => {
+                let vehicle = self.state.get_mut_vehicle(idx.unwrap());
+                vehicle.action(Action::Deccelerate);
+                // EventResult::VehicleChange(&*vehicle)
+            }
+            LaneChange(_) => {
+                self.consider_lane_change(idx.unwrap());
+            }
+            CarFollowingUpdate => {
+                self.update_car_following();
+            }
+            PedestrianArrival => {
+                // EventResult::NewPedestrian(self.new_pedestrian())
+                let id = self.ped_counter;
+                match self.new_pedestrian() {
+                    Some(pedestrian) => {
+                        let crossing_id = pedestrian.location().get_id();
+                        self.press_button(crossing_id);
+                        self.join_crossing(id, crossing_id);
+                    }
+                    None => self.start_informal_if_clear(id)
+                }
+                if let Some(position) = self.pedestrian_position(id) {
+                    self.reschedule_approaching(position);
+                }
+            }
+            PedestrianStartCrossing(id) => {
+                self.gap_times.remove(&id);
+                let pedestrian = self.state.get_pedestrians().iter().find(|ped| ped.get_id() == id);
+                match pedestrian.map(|ped| ped.location().get_id()) {
+                    Some(crossing_id) => self.start_queue_if_clear(crossing_id),
+                    None => self.start_informal_if_clear(id)
+                }
+                if let Some(position) = self.pedestrian_position(id) {
+                    self.reschedule_approaching(position);
+                }
+            }
+            PedestrianExit(id) => {
+                if let Some(position) = self.pedestrian_position(id) {
+                    self.reschedule_approaching(position);
+                }
+                self.remove_pedestrian(id);
+                // EventResult::RemovePedestrian
+            }
+            LightsToAmber(idx) => {
+                let time = *self.state.timestamp();
+                let committed = self.vehicles_unable_to_stop(idx as ID);
+                self.state.get_mut_signal(idx).switch_to_amber(time, committed);
+                self.reschedule_approaching(self.road.get_crossing_position(&(idx as ID), Direction::Up));
+                // EventResult::CrossingChange(crossing)
+            }
+            LightsToRed(idx) => {
+                let time = *self.state.timestamp();
+                self.state.get_mut_signal(idx).switch_to_red(time);
+                self.start_queue_if_clear(idx as ID);
+                self.reschedule_approaching(self.road.get_crossing_position(&(idx as ID), Direction::Up));
+                // EventResult::CrossingChange(crossing)
+            }
+            LightsToGreen(idx) => {
+                let time = *self.state.timestamp();
+                self.state.get_mut_signal(idx).switch_to_green(time);
+                self.release_pedestrians(idx as ID);
+                self.reschedule_approaching(self.road.get_crossing_position(&(idx as ID), Direction::Up));
+                // EventResult::CrossingChange(crossing)
+            }
+            StopSimulation => {
+                // EventResult::NoEffect
+                // Nothing to do.
+            }
+        }
+        if let Some(id) = moved {
+            self.reschedule(id);
+        }
+    }
+
+    // fn get_state(&self) -> &Box<dyn State> {
+    //     &self.state
+    // }
+
+    // Moved to instantaneous_update (EventResult appears to be superfluous):
+    // fn handle_event(&mut self, event: Event) -> EventResult<'_> {
+    //     use EventType::*;
+    //     match event.1 {
+    //         VehicleArrival => {
+    //             EventResult::NewVehicle(self.new_vehicle())
+    //         }
+    //         VehicleExit(idx) => {
+    //             self.remove_vehicle(idx);
+    //             EventResult::RemoveVehicle
+    //         }
+    //         SpeedLimitReached(idx) => {
+    //             let vehicle = self.state.get_mut_vehicle(idx);
+    //             vehicle.action(Action::StaticSpeed);
+    //             EventResult::VehicleChange(&*vehicle)
+    //         }
+    //         ZeroSpeedReached(idx) => {
+    //             let vehicle = self.state.get_mut_vehicle(idx);
+    //             vehicle.action(Action::StaticSpeed);
+    //             EventResult::VehicleChange(&*vehicle)
+    //         }
+    //         ReactionToObstacle(idx) => {
+    //             let vehicle = self.state.get_mut_vehicle(idx);
+
+    //             EventResult::VehicleChange(&*vehicle)
+    //         }
+    //         PedestrianArrival => {
+    //             EventResult::NewPedestrian(self.new_pedestrian())
+    //         }
+    //         PedestrianExit(idx) => {
+    //             self.remove_pedestrian(idx);
+    //             EventResult::RemovePedestrian
+    //         }
+    //         LightsToRed(idx) => {
+    //             let (crossing, _) = &self.road.get_crossings(&Direction::Up)[idx];
+    //             EventResult::CrossingChange(crossing)
+    //         }
+    //         LightsToGreen(idx) => {
+    //             let (crossing, _) = &self.road.get_crossings(&Direction::Up)[idx];
+    //             EventResult::CrossingChange(crossing)
+    //         }
+    //         StopSimulation => {
+    //             EventResult::NoEffect
+    //         }
+    //         _ => unreachable!()
+    //     }
+    // }
+
+    fn get_road(&self) -> &Road {
+        &self.road
+    }
+
+    fn get_end_time(&self) -> Time {
+        self.end_time
+    }
+
+    fn is_stepping(&self) -> bool {
+        self.step
+    }
+
+    fn open_outfile(&self) -> Option<File> {
+        self.outfile.as_ref().map(|outfile| {
+            OpenOptions::new()
+                .create(true)
+                .write(true)
+                .truncate(true)
+                .open(outfile)
+                .unwrap()
+        })
+    }
+
+    fn report(&self, t: Time, file: &mut Option<File>) {
+        // Log state to file
+        if let Some(ref mut x) = file {
+            writeln!(x, "{}", &to_json_flat(self.get_state()).unwrap())
+                .expect("Tried to write state.");
+        }
+
+        // The full state is only serialised for tracing
+        if log_enabled!(Level::Trace) {
+            trace!("State after update at time {t}:\n{}", &to_json(self.get_state()).unwrap());
+        }
+    }
+
+    fn finish(&self) {
+        self.write_records();
+        self.log_summary();
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::VecDeque};
+    use crate::vehicle::{ACCELERATION_VALUE, DECCELERATION_VALUE, EMERGENCY_DECCELERATION_VALUE, MAX_SPEED};
+    use crate::road::{AMBER_TIME, CROSSING_TIME, WAIT_TIME};
+    use super::*;
+    const MY_EPSILON: f32 = 0.001;
+
+    fn dummy_sim(state: Box<dyn State >) -> EventDrivenSim  {
+        let road = Road::new(100.0, Vec::new());
+        // let state = Box::new(SimulatorState::new());
+        EventDrivenSim::new(RunConfig { ped_arrival_rate: 0.1, veh_arrival_rate: 0.2, ..RunConfig::no_arrivals() }, state, road)
+    }
+
+    fn dummy_no_arrivals_sim(state: Box<dyn State >) -> EventDrivenSim  {
+        let road = Road::new(100.0, Vec::new());
+        // let state = Box::new(SimulatorState::new());
+        EventDrivenSim::new(RunConfig::no_arrivals(), state, road)
+    }
+
+    #[test]
+    fn test_set_state() {
+        let state = Box::new(SimulatorState::new());
+        let mut sim = dummy_sim(state);
+        assert_eq!(sim.state.timestamp(), &0);
+
+        // Construct a new state with a non-zero timestamp.
+        let new_timestamp = 10000;
+        let new_state = SimulatorState::dummy(VecDeque::new(), VecDeque::new(), new_timestamp);
+
+        // Set the simulation state.
+        sim.set_state(Box::new(new_state));
+        assert_eq!(sim.state.timestamp(), &new_timestamp);
+    }
+
+    #[test]
+    fn test_set_arrival_times() {
+
+        let state = Box::new(SimulatorState::new());
+        let mut sim = dummy_sim(state);
+        assert_ne!(sim.ped_arrival_times, vec!(10000, 20000));
+
+        // Construct new pedestrian arrival times.
+        let ped_arrival_times = vec!(10000, 20000);
+
+        // Set the simulation pedestrian arrival times.
+        sim.set_ped_arrival_times(ped_arrival_times);
+        assert_eq!(sim.ped_arrival_times, vec!(10000, 20000));
+
+        // Construct new vehicle arrival times.
+        let veh_arrival_times = vec!(12000, 21000);
+
+        // Set the simulation vehicle arrival times.Car::new(0u64, Direction::Up, speed, Action::Deccelerate))
+        sim.set_veh_arrival_times(veh_arrival_times);
+        assert_eq!(sim.veh_arrival_times, vec!(12000, 21000));
+    }
+
+    #[test]
+    fn test_pedestrian_arrival_event() {
+
+        let state = Box::new(SimulatorState::new());
+        let mut sim = dummy_sim(state);
+
+        let ped_arrival_times = vec!(10000, 20000);
+        let veh_arrival_times = vec!(12000, 21000);
+
+        // Set the pedestrian & vehicle arrival times.
+        sim.set_ped_arrival_times(ped_arrival_times);
+        sim.set_veh_arrival_times(veh_arrival_times);
+
+        let next_events = sim.next_events();
+        let actual = next_events.first().unwrap();
+        assert_eq!(actual.0, 10000);
+    }
+
+    #[test]
+    fn test_vehicle_arrival_event() {
+
+        let state = Box::new(SimulatorState::new());
+        let mut sim = dummy_sim(state);
+
+        let ped_arrival_times = vec!(5000, 7000);
+        let veh_arrival_times = vec!(4000, 15000);
+
+        // Set the pedestrian & vehicle arrival times.
+        sim.set_ped_arrival_times(ped_arrival_times);
+        sim.set_veh_arrival_times(veh_arrival_times);
+
+        let next_events = sim.next_events();
+        let actual = next_events.first().unwrap();
+        assert_eq!(actual.0, 4000);
+    }
+
+    #[test]
+    fn test_vehicle_stopping_event() {
+        // Make crossings and road
+        let crossings = vec![
+	        (Crossing::Zebra { id: 0, cross_time: TimeDelta::from_secs(10) }, 170.0),
+	        (Crossing::Zebra { id: 1, cross_time: TimeDelta::from_secs(10) }, 290.0),
+	    ];
+        let road = Road::new(300.0f32, crossings);
+
+        // Set time
+        let timestamp = 22 * TIME_RESOLUTION;
+
+        // Make vehicles
+        let mut vehicles: VecDeque<Box<dyn Vehicle>> = VecDeque::new();
+        let speed = 10.0;    
+        let mut car = Car::new(0 as ID, Direction::Up, speed, Action::Deccelerate);
+
+        // Set car near crossing
+        car.set_position(140.);
+        vehicles.push_back(Box::new(car));
+
+        // Place pedestrian at first crossing
+        let crossing = &road.get_crossings(&Direction::Up)[0].0;
+        let mut peds: VecDeque<Pedestrian> = VecDeque::new();
+        peds.push_back(Pedestrian::new(0, Rc::clone(&crossing), timestamp));
+
+        // Make state
+        let state = Box::new(SimulatorState::dummy(vehicles, peds, timestamp));
+
+        // Make sim from state and road
+        let mut sim = EventDrivenSim::new(RunConfig { seed: 12345, ped_arrival_rate: 0.1, veh_arrival_rate: 0.1, ..RunConfig::no_arrivals() }, state, road);
+
+        // Get next events
+        let next_events = sim.next_events();
+        let actual = next_events.first().unwrap();
+
+        assert_eq!(actual.0, timestamp + TimeDelta::floor((-1.0) * (speed / DECCELERATION_VALUE)));
+    }
+
+    #[test]
+    fn test_case1_reaction_to_obstacle() {
+        let mut v1 = Car::new(0, Direction::Up, 14., Action::StaticSpeed);
+        let mut v2 = Car::new(1, Direction::Up, 0., Action::StaticSpeed);
+        v1.set_position(0.);
+        v2.set_position(100. + v2.get_length());
+
+        let mut vehicles: VecDeque<Box<dyn Vehicle>> = VecDeque::new();
+        vehicles.push_back(Box::new(v1));
+        vehicles.push_back(Box::new(v2));
+        
+        let state = Box::new(SimulatorState::dummy(vehicles, VecDeque::new(), 0));
+        let sim = dummy_sim(state);
+        let mv1 = sim.state.get_vehicle(0);
+        let mv2 = sim.state.get_vehicle(1);
+        let t_p = sim.time_to_obstacle_event::<dyn Obstacle>(mv1, mv2.as_obstacle(), false, false);
+        assert!(f32::abs(t_p.unwrap() - 5.321429) < MY_EPSILON);
+
+    }
+    #[test]
+    fn test_case1b_reaction_to_obstacle() {
+        let mut v1 = Car::new(0, Direction::Up, 14., Action::StaticSpeed);
+        let mut v2 = Car::new(1, Direction::Up, 10., Action::StaticSpeed);
+        v1.set_position(80.);
+        v2.set_position(100. + v2.get_length());
+
+        let mut vehicles: VecDeque<Box<dyn Vehicle>> = VecDeque::new();
+        vehicles.push_back(Box::new(v1));
+        vehicles.push_back(Box::new(v2));
+
+        let state = Box::new(SimulatorState::dummy(vehicles, VecDeque::new(), 0));
+        let sim = dummy_sim(state);
+        let mv1 = sim.state.get_vehicle(0);
+        let mv2 = sim.state.get_vehicle(1);
+        let t_p = sim.time_to_obstacle_event::<dyn Obstacle>(mv1, mv2.as_obstacle(), false, false);
+        assert!(f32::abs(t_p.unwrap() - 4.25) < MY_EPSILON);
+
+    }
+    #[test]
+    fn test_case2_reaction_to_obstacle() {
+        let mut v1 = Car::new(0, Direction::Up, 4., Action::Accelerate);
+        let mut v2 = Car::new(1, Direction::Up, 0., Action::StaticSpeed);
+        v1.set_position(0.);
+        v2.set_position(20. + v2.get_length());
+
+        let mut vehicles: VecDeque<Box<dyn Vehicle>> = VecDeque::new();
+        vehicles.push_back(Box::new(v1));
+        vehicles.push_back(Box::new(v2));
+
+        let state = Box::new(SimulatorState::dummy(vehicles, VecDeque::new(), 0));
+        let sim = dummy_sim(state);
+        let mv1 = sim.state.get_vehicle(0);
+        let mv2 = sim.state.get_vehicle(1);
+        let t_p = sim.time_to_obstacle_event::<dyn Obstacle>(mv1, mv2.as_obstacle(), false, false);
+        
+        assert!(f32::abs(t_p.unwrap() - 1.539638691) < MY_EPSILON);
+
+    }
+    #[test]
+    fn test_case2b_reaction_to_obstacle() {
+        let mut v1 = Car::new(0, Direction::Up, 14., Action::Accelerate);
+        let mut v2 = Car::new(1, Direction::Up, 10., Action::StaticSpeed);
+        v1.set_position(80.);
+        v2.set_position(100. + v2.get_length());
+
+        let mut vehicles: VecDeque<Box<dyn Vehicle>> = VecDeque::new();
+        vehicles.push_back(Box::new(v1));
+        vehicles.push_back(Box::new(v2));
+
+        let state = Box::new(SimulatorState::dummy(vehicles, VecDeque::new(), 0));
+        let sim = dummy_sim(state);
+        let mv1 = sim.state.get_vehicle(0);
+        let mv2 = sim.state.get_vehicle(1);
+        let t_p = sim.time_to_obstacle_event::<dyn Obstacle>(mv1, mv2.as_obstacle(), false, false);
+
+        assert!(f32::abs(t_p.unwrap() - 1.539638691) < MY_EPSILON);
+
+    }
+    
+    #[test]
+    fn test_case3_reaction_to_obstacle() {
+        let mut v1 = Car::new(0, Direction::Up, 14., Action::Accelerate);
+        let mut v2 = Car::new(1, Direction::Up, 10., Action::Deccelerate);
+        v1.set_position(0.);
+        v2.set_position(25. + v2.get_length());
+
+        let mut vehicles: VecDeque<Box<dyn Vehicle>> = VecDeque::new();
+        vehicles.push_back(Box::new(v1));
+        vehicles.push_back(Box::new(v2));
+
+        let state = Box::new(SimulatorState::dummy(vehicles, VecDeque::new(), 0));
+        let sim = dummy_sim(state);
+        let mv1 = sim.state.get_vehicle(0);
+        let mv2 = sim.state.get_vehicle(1);
+        let t_p = sim.time_to_obstacle_event::<dyn Obstacle>(mv1, mv2.as_obstacle(), false, false);
+
+        assert!(f32::abs(t_p.unwrap() - 0.466481135) < MY_EPSILON);
+
+    }
+    #[test]
+    fn test_case3_danger_reaction_to_obstacle() {
+        let mut v1 = Car::new(0, Direction::Up, 14., Action::Accelerate);
+        let mut v2 = Car::new(1, Direction::Up, 10., Action::Deccelerate);
+        v1.set_position(0.);
+        v2.set_position(10. + v2.get_length());
+
+        let mut vehicles: VecDeque<Box<dyn Vehicle>> = VecDeque::new();
+        vehicles.push_back(Box::new(v1));
+        vehicles.push_back(Box::new(v2));
+
+        let state = Box::new(SimulatorState::dummy(vehicles, VecDeque::new(), 0));
+        let sim = dummy_sim(state);
+        let mv1 = sim.state.get_vehicle(0);
+        let mv2 = sim.state.get_vehicle(1);
+        let t_p = sim.time_to_obstacle_event::<dyn Obstacle>(mv1, mv2.as_obstacle(), false, false);
+
+        // Negative time as already will end up in danger zone
+        assert!(f32::abs(t_p.unwrap() - -0.124099041) < MY_EPSILON);
+
+    }
+    #[test]
+    fn test_vehicle_speed_limit_event() {
+
+        let speed = 10.0;
+        let vehicles: Vec<Box<dyn Vehicle>> = vec!(Box::new(Car::new(0u64, Direction::Up, speed, Action::Accelerate)));
+
+        let timestamp = 11 * TIME_RESOLUTION;
+        let state = Box::new(SimulatorState::dummy(vehicles.into(), VecDeque::new(), timestamp));
+
+        let mut sim = dummy_sim(state);
+
+        let next_events = sim.next_events();
+        let actual = next_events.first().unwrap();
+
+        assert_eq!(actual.0, timestamp + TimeDelta::floor((MAX_SPEED - speed) / ACCELERATION_VALUE));
+    }
+    
+    #[test]
+    fn test_pelican_light_events() {
+        let crossings = vec![(Crossing::pelican(0), 50.0)];
+        let road = Road::new(100.0f32, crossings);
+        let state = Box::new(SimulatorState::new());
+        let mut sim = EventDrivenSim::new(RunConfig::no_arrivals(), state, road);
+        sim.set_ped_arrival_times(vec!(10_000));
+        sim.set_veh_arrival_times(Vec::new());
+
+        // Pedestrian presses the button on arrival.
+        let next_events = sim.next_events();
+        assert_eq!(next_events[0].0, 10_000);
+        sim.roll_forward_by(TimeDelta::new(10_000));
+        sim.instantaneous_update(EventType::PedestrianArrival);
+        assert_eq!(sim.state.get_signals()[0].button_pressed(), Some(10_000));
+
+        // Lights go amber after the wait time, then red.
+        let next_events = sim.next_events();
+        assert_eq!(next_events.len(), 1);
+        assert_eq!(next_events[0].0, WAIT_TIME + 10_000);
+        assert!(matches!(next_events[0].1, EventType::LightsToAmber(0)));
+        sim.roll_forward_by(WAIT_TIME);
+        sim.instantaneous_update(EventType::LightsToAmber(0));
+        let next_events = sim.next_events();
+        assert_eq!(next_events[0].0, AMBER_TIME + (WAIT_TIME + 10_000));
+        assert!(matches!(next_events[0].1, EventType::LightsToRed(0)));
+        sim.roll_forward_by(AMBER_TIME);
+        sim.instantaneous_update(EventType::LightsToRed(0));
+
+        // Lights go green after the stop time, releasing the pedestrian.
+        let next_events = sim.next_events();
+        assert_eq!(next_events[0].0, CROSSING_TIME + (AMBER_TIME + (WAIT_TIME + 10_000)));
+        assert!(matches!(next_events[0].1, EventType::LightsToGreen(0)));
+        sim.roll_forward_by(CROSSING_TIME);
+        sim.instantaneous_update(EventType::LightsToGreen(0));
+        assert_eq!(sim.state.get_signals()[0].phase(), Some(LightPhase::Green));
+        assert_eq!(sim.state.get_pedestrians().len(), 0);
+    }
+
+    #[test]
+    fn test_pedestrian_arrives_mid_red() {
+        let crossings = vec![(Crossing::pelican(0), 50.0)];
+        let road = Road::new(100.0f32, crossings);
+        let state = Box::new(SimulatorState::new());
+        let mut sim = EventDrivenSim::new(RunConfig::no_arrivals(), state, road);
+        let red = AMBER_TIME + (WAIT_TIME + 10_000);
+        let mid_red = red + 3_000;
+        sim.set_ped_arrival_times(vec![10_000, mid_red]);
+        sim.set_veh_arrival_times(Vec::new());
+
+        // The first pedestrian's press turns the lights red.
+        while *sim.state.timestamp() < mid_red {
+            let event = sim.next_events().pop().unwrap();
+            sim.roll_forward_by(TimeDelta::new(event.0 - *sim.state.timestamp()));
+            sim.instantaneous_update(event.1);
+        }
+        assert_eq!(sim.state.get_signals()[0].phase(), Some(LightPhase::Red));
+
+        // The second, arriving while they are red, steps out at once rather
+        // than waiting for the next cycle, and their press is ignored.
+        let pedestrian = sim.state.get_pedestrian(1);
+        assert_eq!(pedestrian.start_time(), Some(mid_red));
+        assert_eq!(pedestrian.delay(), Some(TimeDelta::new(0)));
+        assert_eq!(sim.state.get_signals()[0].button_pressed(), None);
+        assert!(sim.state.get_signals()[0].queue().is_empty());
+
+        // Both are released when the lights go green, with no further cycle.
+        let event = sim.next_events().pop().unwrap();
+        assert!(matches!(event, Event(time, EventType::LightsToGreen(0)) if time == CROSSING_TIME + red));
+        sim.roll_forward_by(TimeDelta::new(event.0 - mid_red));
+        sim.instantaneous_update(event.1);
+        assert!(sim.state.get_pedestrians().is_empty());
+        assert_eq!(sim.state.get_signals()[0].next_change_time(), None);
+    }
+
+    #[test]
+    fn test_pedestrians_cross_at_their_walking_speed() {
+        let crossings = vec![(Crossing::zebra(0), 50.0)];
+        let road = Road::new(100.0f32, crossings);
+        let state = Box::new(SimulatorState::new());
+        let mut sim = EventDrivenSim::new(RunConfig::no_arrivals(), state, road);
+        sim.set_ped_arrival_times(vec!(1_000, 2_000));
+        sim.set_veh_arrival_times(Vec::new());
+        let walking_speed = WalkingSpeed { mean: 1.5, sd: 0.0, slow_fraction: 0.5, slow_mean: 0.75, slow_sd: 0.0 };
+        sim.set_walking_speed(6.0, walking_speed);
+
+        sim.roll_forward_by(TimeDelta::new(1_000));
+        sim.instantaneous_update(EventType::PedestrianArrival);
+        sim.roll_forward_by(TimeDelta::new(1_000));
+        sim.instantaneous_update(EventType::PedestrianArrival);
+
+        // Each pedestrian takes 4s or 8s to cross, so they need not exit in order of arrival.
+        let crossing_times: Vec<TimeDelta> = sim.state.get_pedestrians().iter().map(|ped| ped.crossing_time()).collect();
+        for crossing_time in &crossing_times {
+            assert!(*crossing_time == TimeDelta::from_secs(4) || *crossing_time == TimeDelta::from_secs(8));
+        }
+        let exits: Vec<Event> = sim.next_events();
+        let first_exit = Time::min(crossing_times[0] + 1_000, crossing_times[1] + 2_000);
+        assert_eq!(exits[0].0, first_exit);
+        if let EventType::PedestrianExit(id) = exits[0].1 {
+            sim.roll_forward_by(TimeDelta::new(first_exit - 2_000));
+            sim.instantaneous_update(EventType::PedestrianExit(id));
+            assert_eq!(sim.state.get_pedestrians().len(), 1);
+            assert_ne!(sim.state.get_pedestrians()[0].get_id(), id);
+        } else {
+            panic!("Expected a pedestrian exit");
+        }
+    }
+
+    #[test]
+    fn test_pedestrian_gap_acceptance() {
+        let crossings = vec![(Crossing::zebra(0), 50.0)];
+        let road = Road::new(100.0f32, crossings);
+
+        // Car 20m from the crossing at top speed: a 1.5s gap.
+        let mut car = Car::new(0 as ID, Direction::Up, MAX_SPEED, Action::StaticSpeed);
+        car.set_position(30.0);
+        let mut vehicles: VecDeque<Box<dyn Vehicle>> = VecDeque::new();
+        vehicles.push_back(Box::new(car));
+        let state = Box::new(SimulatorState::dummy(vehicles, VecDeque::new(), 0));
+        let mut sim = EventDrivenSim::new(RunConfig::no_arrivals(), state, road);
+        sim.set_ped_arrival_times(vec!(0));
+        sim.set_veh_arrival_times(Vec::new());
+        sim.set_critical_gap(TimeDelta::from_secs(3), TimeDelta::new(0));
+
+        // The pedestrian waits, and the car does not react to them.
+        sim.instantaneous_update(EventType::PedestrianArrival);
+        assert!(sim.state.get_pedestrian(0).is_waiting());
+        let next_events = sim.next_events();
+        assert_eq!(next_events.len(), 1);
+
+        // They step out once the rear of the car has passed the crossing.
+        assert_eq!(next_events[0].0, 1_790);
+        assert!(matches!(next_events[0].1, EventType::PedestrianStartCrossing(0)));
+        sim.roll_forward_by(TimeDelta::new(1_790));
+        sim.instantaneous_update(EventType::PedestrianStartCrossing(0));
+        assert!(!sim.state.get_pedestrian(0).is_waiting());
+        assert_eq!(sim.get_pedestrian_delays(), &[(0, TimeDelta::new(1_790))]);
+        assert_eq!(sim.mean_pedestrian_delay(), 1.79);
+        assert!(sim.state.get_pedestrian(0).is_active(CROSSING_TIME + 1_789));
+        assert!(!sim.state.get_pedestrian(0).is_active(CROSSING_TIME + 1_790));
+    }
+
+    #[test]
+    fn test_pedestrian_group_crossing() {
+        let crossings = vec![(Crossing::zebra(0), 50.0)];
+        let road = Road::new(100.0f32, crossings);
+
+        // Car 20m from the crossing at top speed, passing at 1.79s.
+        let mut car = Car::new(0 as ID, Direction::Up, MAX_SPEED, Action::StaticSpeed);
+        car.set_position(30.0);
+        let mut vehicles: VecDeque<Box<dyn Vehicle>> = VecDeque::new();
+        vehicles.push_back(Box::new(car));
+        let state = Box::new(SimulatorState::dummy(vehicles, VecDeque::new(), 0));
+        let mut sim = EventDrivenSim::new(RunConfig::no_arrivals(), state, road);
+        sim.set_ped_arrival_times(vec!(0, 1_000, 3_000, 4_000));
+        sim.set_veh_arrival_times(Vec::new());
+        sim.set_critical_gap(TimeDelta::from_secs(3), TimeDelta::new(0));
+        sim.set_group_join_time(TimeDelta::from_secs(2));
+
+        // Two pedestrians queue behind the car, and only the first is due to step out.
+        sim.instantaneous_update(EventType::PedestrianArrival);
+        sim.roll_forward_by(TimeDelta::new(1_000));
+        sim.instantaneous_update(EventType::PedestrianArrival);
+        assert_eq!(sim.state.get_signals()[0].queue().len(), 2);
+        let next_events = sim.next_events();
+        assert_eq!(next_events.len(), 1);
+        assert!(matches!(next_events[0].1, EventType::PedestrianStartCrossing(0)));
+
+        // The queue steps out together, as a group led by the first.
+        sim.roll_forward_by(TimeDelta::new(790));
+        sim.instantaneous_update(EventType::PedestrianStartCrossing(0));
+        assert!(sim.state.get_signals()[0].queue().is_empty());
+        assert_eq!(sim.get_pedestrian_delays(), &[(0, TimeDelta::new(1_790)), (1, TimeDelta::new(790))]);
+        assert_eq!(sim.state.get_pedestrian(1).group(), 0);
+
+        // A pedestrian arriving soon after joins the group, extending the time
+        // it occupies the crossing.
+        sim.roll_forward_by(TimeDelta::new(1_210));
+        sim.instantaneous_update(EventType::PedestrianArrival);
+        let joined = sim.state.get_pedestrian(2);
+        assert_eq!((joined.start_time(), joined.group()), (Some(3_000), 0));
+
+        // One arriving later crosses on their own.
+        sim.roll_forward_by(TimeDelta::new(1_000));
+        sim.instantaneous_update(EventType::PedestrianArrival);
+        let alone = sim.state.get_pedestrian(3);
+        assert_eq!((alone.start_time(), alone.group()), (Some(4_000), 3));
+    }
+
+    #[test]
+    fn test_pedestrian_accepts_gap() {
+        let crossings = vec![(Crossing::zebra(0), 50.0)];
+        let road = Road::new(100.0f32, crossings);
+
+        // One car 40m away at 10m/s, and one stopped short of the crossing.
+        let mut car = Car::new(0 as ID, Direction::Up, 10.0, Action::StaticSpeed);
+        car.set_position(10.0);
+        let mut stopped = Car::new(1 as ID, Direction::Down, 0.0, Action::StaticSpeed);
+        stopped.set_position(45.0);
+        let mut vehicles: VecDeque<Box<dyn Vehicle>> = VecDeque::new();
+        vehicles.push_back(Box::new(stopped));
+        vehicles.push_back(Box::new(car));
+        let state = Box::new(SimulatorState::dummy(vehicles, VecDeque::new(), 0));
+        let mut sim = EventDrivenSim::new(RunConfig::no_arrivals(), state, road);
+        sim.set_ped_arrival_times(vec!(0));
+        sim.set_veh_arrival_times(Vec::new());
+        sim.set_critical_gap(TimeDelta::from_secs(3), TimeDelta::new(0));
+
+        sim.instantaneous_update(EventType::PedestrianArrival);
+        assert!(!sim.state.get_pedestrian(0).is_waiting());
+        assert_eq!(sim.get_pedestrian_delays(), &[(0, TimeDelta::new(0))]);
+    }
+
+    #[test]
+    fn test_vehicle_reacts_to_red_light() {
+        let crossings = vec![(Crossing::pelican(0), 170.0)];
+        let road = Road::new(300.0f32, crossings);
+
+        let speed = MAX_SPEED;
+        let mut car = Car::new(0 as ID, Direction::Up, speed, Action::StaticSpeed);
+        car.set_position(100.);
+        let mut vehicles: VecDeque<Box<dyn Vehicle>> = VecDeque::new();
+        vehicles.push_back(Box::new(car));
+
+        let state = Box::new(SimulatorState::dummy(vehicles, VecDeque::new(), 0));
+        let mut sim = EventDrivenSim::new(RunConfig::no_arrivals(), state, road);
+        sim.set_ped_arrival_times(Vec::new());
+        sim.set_veh_arrival_times(Vec::new());
+
+        // On green the vehicle drives on to the exit.
+        let next_events = sim.next_events();
+        assert!(matches!(next_events[0].1, EventType::VehicleExit(0)));
+
+        // On red the vehicle brakes to stop short of the crossing.
+        sim.instantaneous_update(EventType::LightsToAmber(0));
+        sim.instantaneous_update(EventType::LightsToRed(0));
+        let next_events = sim.next_events();
+        assert!(matches!(next_events[0].1, EventType::ReactionToObstacle(0)));
+
+        // Braking at that time stops the vehicle in front of the crossing.
+        let stop_position = 100. + speed * Into::<f32>::into(TimeDelta::new(next_events[0].0))
+            + speed * speed / (-2. * DECCELERATION_VALUE);
+        assert!(stop_position < 170. - 1.0);
+    }
+
+    #[test]
+    fn test_emergency_stop_short_of_pedestrian() {
+        let crossings = vec![(Crossing::zebra(0), 170.0)];
+        let road = Road::new(300.0f32, crossings);
+
+        // Vehicle too close to stop with normal braking when a pedestrian steps out.
+        let mut car = Car::new(0 as ID, Direction::Up, MAX_SPEED, Action::StaticSpeed);
+        car.set_position(155.);
+        let mut vehicles: VecDeque<Box<dyn Vehicle>> = VecDeque::new();
+        vehicles.push_back(Box::new(car));
+        let crossing = Rc::clone(&road.get_crossings(&Direction::Up)[0].0);
+        let mut peds: VecDeque<Pedestrian> = VecDeque::new();
+        peds.push_back(Pedestrian::new(0, crossing, 0));
+
+        let state = Box::new(SimulatorState::dummy(vehicles, peds, 0));
+        let mut sim = EventDrivenSim::new(RunConfig::no_arrivals(), state, road);
+        sim.set_ped_arrival_times(Vec::new());
+        sim.set_veh_arrival_times(Vec::new());
+
+        let next_events = sim.next_events();
+        assert_eq!(next_events[0].0, 0);
+        assert!(matches!(next_events[0].1, EventType::EmergencyStop(0)));
+        sim.instantaneous_update(EventType::EmergencyStop(0));
+        assert_eq!(sim.state.get_vehicle(0).get_acceleration(), EMERGENCY_DECCELERATION_VALUE);
+        assert!(sim.state.get_vehicle(0).is_emergency_stopping());
+
+        // Stopping distance of 11.2m brings the vehicle to rest short of the crossing.
+        let record = &sim.get_emergency_stops()[0];
+        assert_eq!(record.obstacle_kind, ObstacleKind::Pedestrian);
+        assert_eq!(record.obstacle_id, 0);
+        assert!(!record.hit);
+        assert_eq!(record.rest_time, None);
+
+        // No further braking events while stopping, then the vehicle comes to rest.
+        let next_events = sim.next_events();
+        assert!(matches!(next_events[0].1, EventType::ZeroSpeedReached(0)));
+        let rest_time = next_events[0].0;
+        sim.roll_forward_by(TimeDelta::new(rest_time));
+        sim.instantaneous_update(EventType::ZeroSpeedReached(0));
+        assert_eq!(sim.get_emergency_stops()[0].rest_time, Some(rest_time));
+        assert!(sim.state.get_vehicle(0).get_veh_position() < 170.0 - 1.0);
+        assert!(!sim.state.get_vehicle(0).is_emergency_stopping());
+    }
+
+    #[test]
+    fn test_emergency_stop_at_vehicle_deceleration() {
+        let crossings = vec![(Crossing::zebra(0), 170.0)];
+        let road = Road::new(300.0f32, crossings);
+
+        // A bus brakes less hard in an emergency than the car above, which
+        // stopped short from the same position.
+        let bus = Car::of_kind(VehicleKind::Bus, 0, Direction::Up, MAX_SPEED, Action::StaticSpeed);
+        let mut vehicles: VecDeque<Box<dyn Vehicle>> = VecDeque::new();
+        vehicles.push_back(Box::new(bus));
+        vehicles[0].set_position(155.);
+        let crossing = Rc::clone(&road.get_crossings(&Direction::Up)[0].0);
+        let mut peds: VecDeque<Pedestrian> = VecDeque::new();
+        peds.push_back(Pedestrian::new(0, crossing, 0));
+
+        let state = Box::new(SimulatorState::dummy(vehicles, peds, 0));
+        let mut sim = EventDrivenSim::new(RunConfig::no_arrivals(), state, road);
+        sim.set_ped_arrival_times(Vec::new());
+        sim.set_veh_arrival_times(Vec::new());
+        let records_file = std::env::temp_dir().join("zebra_test_emergency_records.json");
+        sim.set_records_file(records_file.to_str().unwrap().to_string());
+        sim.instantaneous_update(EventType::EmergencyStop(0));
+        assert_eq!(sim.state.get_vehicle(0).get_acceleration(), VehicleKind::Bus.spec().max_emergency_deceleration);
+
+        // Stopping distance of 18m takes it over the stop line.
+        let record = &sim.get_emergency_stops()[0];
+        assert!(record.hit);
+        assert!((record.stop_position - 155.0 - MAX_SPEED * MAX_SPEED / 10.0).abs() < 0.01);
+
+        // The record, still hitting, is written out with the run's records.
+        sim.write_records();
+        let records: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&records_file).unwrap()).unwrap();
+        std::fs::remove_file(&records_file).unwrap();
+        assert_eq!(records["emergency_stops"][0]["vehicle_id"], 0);
+        assert_eq!(records["emergency_stops"][0]["obstacle_kind"], "Pedestrian");
+        assert_eq!(records["emergency_stops"][0]["hit"], true);
+    }
+
+    #[test]
+    fn test_emergency_stop_hits_pedestrian() {
+        let crossings = vec![(Crossing::zebra(0), 170.0)];
+        let road = Road::new(300.0f32, crossings);
+
+        let mut car = Car::new(0 as ID, Direction::Up, MAX_SPEED, Action::StaticSpeed);
+        car.set_position(162.);
+        let mut vehicles: VecDeque<Box<dyn Vehicle>> = VecDeque::new();
+        vehicles.push_back(Box::new(car));
+
+        // Pedestrian steps out in front of the vehicle.
+        let crossing = Rc::clone(&road.get_crossings(&Direction::Up)[0].0);
+        let mut peds: VecDeque<Pedestrian> = VecDeque::new();
+        peds.push_back(Pedestrian::new(0, crossing, 0));
+
+        let state = Box::new(SimulatorState::dummy(vehicles, peds, 0));
+        let mut sim = EventDrivenSim::new(RunConfig::no_arrivals(), state, road);
+        sim.set_ped_arrival_times(Vec::new());
+        sim.set_veh_arrival_times(Vec::new());
+
+        let next_events = sim.next_events();
+        assert!(matches!(next_events[0].1, EventType::EmergencyStop(0)));
+        sim.instantaneous_update(EventType::EmergencyStop(0));
+
+        let record = &sim.get_emergency_stops()[0];
+        assert_eq!(record.obstacle_kind, ObstacleKind::Pedestrian);
+        assert!(record.hit);
+
+        // The collision is recorded rather than the simulation panicking.
+        let next_events = sim.next_events();
+        assert!(matches!(next_events[0].1, EventType::ZeroSpeedReached(0)));
+        sim.roll_forward_by(TimeDelta::new(next_events[0].0));
+        let incident = &sim.get_incidents()[0];
+        assert_eq!(incident.kind, IncidentKind::Collision);
+        assert_eq!(incident.vehicle_id, 0);
+        assert_eq!(incident.obstacle_id, 0);
+        assert!(incident.closing_speed > 0.0);
+    }
+
+    #[test]
+    fn test_emergency_stop_names_its_cause() {
+        let mut road = Road::new(300.0f32, vec![(Crossing::zebra(0), 170.0)]);
+        road.set_lanes(2);
+
+        // In the offside lane on the zig-zags, a pedestrian steps out from the
+        // far kerb in front of a car already braking hard, and the car
+        // following it.
+        let mut leader = Car::new(0 as ID, Direction::Up, 10.0, Action::EmergencyStop);
+        leader.set_lane(1);
+        leader.set_position(162.);
+        let mut follower = Car::new(1 as ID, Direction::Up, MAX_SPEED, Action::StaticSpeed);
+        follower.set_lane(1);
+        follower.set_position(152.);
+        let mut vehicles: VecDeque<Box<dyn Vehicle>> = VecDeque::new();
+        vehicles.push_back(Box::new(leader));
+        vehicles.push_back(Box::new(follower));
+        let crossing = Rc::clone(&road.get_crossings(&Direction::Up)[0].0);
+        let mut ped = Pedestrian::new(0, crossing, 0);
+        ped.set_kerb(Direction::Down);
+        let mut peds: VecDeque<Pedestrian> = VecDeque::new();
+        peds.push_back(ped);
+
+        let state = Box::new(SimulatorState::dummy(vehicles, peds, 0));
+        let mut sim = EventDrivenSim::new(RunConfig::no_arrivals(), state, road);
+        sim.set_ped_arrival_times(Vec::new());
+        sim.set_veh_arrival_times(Vec::new());
+
+        // The leader will stop short of the pedestrian, so it is the nearest
+        // obstacle ahead.
+        let follower = sim.state.get_vehicle(1);
+        let leader_edge = sim.obstacle_edge(follower, ObstacleKind::Vehicle, 0).unwrap();
+        assert!(leader_edge < sim.obstacle_edge(follower, ObstacleKind::Pedestrian, 0).unwrap());
+
+        // But the pedestrian stepping out is what makes the follower stop, and
+        // the record says so.
+        while sim.get_emergency_stops().is_empty() {
+            let event = sim.next_events().pop().unwrap();
+            assert_eq!(event.0, 0);
+            sim.instantaneous_update(event.1);
+        }
+        let record = &sim.get_emergency_stops()[0];
+        assert_eq!(record.vehicle_id, 1);
+        assert_eq!((record.obstacle_kind, record.obstacle_id), (ObstacleKind::Pedestrian, 0));
+        assert!(record.obstacle_position > leader_edge);
+    }
+
+    #[test]
+    fn test_vehicle_run_into_leader() {
+        let road = Road::new(300.0f32, Vec::new());
+
+        // The follower's front is already past the rear of the stopped leader.
+        let mut leader = Car::new(0 as ID, Direction::Up, 0.0, Action::StaticSpeed);
+        leader.set_position(30.);
+        let mut follower = Car::new(1 as ID, Direction::Up, 5.0, Action::StaticSpeed);
+        follower.set_position(28.);
+        let mut vehicles: VecDeque<Box<dyn Vehicle>> = VecDeque::new();
+        vehicles.push_back(Box::new(leader));
+        vehicles.push_back(Box::new(follower));
+
+        let state = Box::new(SimulatorState::dummy(vehicles, VecDeque::new(), 0));
+        let mut sim = EventDrivenSim::new(RunConfig::no_arrivals(), state, road);
+        sim.set_ped_arrival_times(Vec::new());
+        sim.set_veh_arrival_times(Vec::new());
+        let records_file = std::env::temp_dir().join("zebra_test_incident_records.json");
+        sim.set_records_file(records_file.to_str().unwrap().to_string());
+
+        // The run goes on: the follower brakes at once, and the collision is
+        // recorded as the simulation rolls forward.
+        let (events, _) = sim.vehicle_events(1);
+        assert!(events.iter().min().is_some_and(|event| matches!(event, Event(0, EventType::EmergencyStop(1)))));
+        sim.instantaneous_update(EventType::EmergencyStop(1));
+        sim.roll_forward_by(TimeDelta::new(100));
+        let incident = &sim.get_incidents()[0];
+        assert_eq!((incident.kind, incident.vehicle_id, incident.obstacle_id), (IncidentKind::Collision, 1, 0));
+        assert_eq!(incident.time, 0);
+
+        // Incidents are written out with the run's records.
+        sim.write_records();
+        let records: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&records_file).unwrap()).unwrap();
+        std::fs::remove_file(&records_file).unwrap();
+        assert_eq!(records["incidents"][0]["kind"], "Collision");
+        assert_eq!(records["incidents"][0]["vehicle_id"], 1);
     }
 
     #[test]
-    fn test_pedestrian_arrival_event() {
+    fn test_reaction_time_at_pedestrian() {
+        // Time braking starts for a vehicle at the given position when a pedestrian steps out
+        fn first_event(position: f32, reaction_time: TimeDelta) -> Event {
+            let crossings = vec![(Crossing::zebra(0), 170.0)];
+            let road = Road::new(300.0f32, crossings);
+
+            let mut car = Car::new(0 as ID, Direction::Up, MAX_SPEED, Action::StaticSpeed);
+            car.set_position(position);
+            car.set_reaction_time(reaction_time);
+            let mut vehicles: VecDeque<Box<dyn Vehicle>> = VecDeque::new();
+            vehicles.push_back(Box::new(car));
+            let crossing = Rc::clone(&road.get_crossings(&Direction::Up)[0].0);
+            let mut peds: VecDeque<Pedestrian> = VecDeque::new();
+            peds.push_back(Pedestrian::new(0, crossing, 0));
+
+            let state = Box::new(SimulatorState::dummy(vehicles, peds, 0));
+            let mut sim = EventDrivenSim::new(RunConfig::no_arrivals(), state, road);
+            sim.set_ped_arrival_times(Vec::new());
+            sim.set_veh_arrival_times(Vec::new());
+            sim.next_events().remove(0)
+        }
 
-        let state = Box::new(SimulatorState::new());
-        let mut sim = dummy_sim(state);
+        // Far enough away to react in time: braking starts as late as without a reaction time.
+        let event = first_event(100., TimeDelta::from_secs(1));
+        assert!(matches!(event.1, EventType::ReactionToObstacle(0)));
+        assert_eq!(event.0, first_event(100., TimeDelta::new(0)).0);
+
+        // Normal braking would have to start within the reaction time.
+        let event = first_event(140., TimeDelta::new(0));
+        assert!(matches!(event.1, EventType::ReactionToObstacle(0)));
+        assert!(event.0 < 1000);
+        let event = first_event(140., TimeDelta::from_secs(1));
+        assert_eq!(event.0, 1000);
+        assert!(matches!(event.1, EventType::EmergencyStop(0)));
+
+        // Too close to stop normally: the emergency stop is also delayed.
+        let event = first_event(155., TimeDelta::from_secs(1));
+        assert_eq!(event.0, 1000);
+        assert!(matches!(event.1, EventType::EmergencyStop(0)));
+    }
 
-        let ped_arrival_times = vec!(10000, 20000);
-        let veh_arrival_times = vec!(12000, 21000);
+    #[test]
+    fn test_no_emergency_stop_at_amber() {
+        // The first vehicle event for a vehicle at the given position when the
+        // lights turn amber, and then red
+        fn first_event(position: f32) -> Event {
+            let road = Road::new(300.0f32, vec![(Crossing::pelican(0), 170.0)]);
+            let mut car = Car::new(0 as ID, Direction::Up, MAX_SPEED, Action::StaticSpeed);
+            car.set_position(position);
+            car.set_reaction_time(TimeDelta::from_secs(1));
+            let mut vehicles: VecDeque<Box<dyn Vehicle>> = VecDeque::new();
+            vehicles.push_back(Box::new(car));
+            let mut sim = EventDrivenSim::new(RunConfig::no_arrivals(), Box::new(SimulatorState::dummy(vehicles, VecDeque::new(), 0)), road);
+            sim.instantaneous_update(EventType::LightsToAmber(0));
+            sim.instantaneous_update(EventType::LightsToRed(0));
+            loop {
+                let event = sim.next_events().remove(0);
+                if !matches!(event.1, EventType::LightsToAmber(_) | EventType::LightsToRed(_) | EventType::LightsToGreen(_)) {
+                    return event;
+                }
+                sim.roll_forward_by(TimeDelta::new(event.0 - *sim.state.timestamp()));
+                sim.instantaneous_update(event.1);
+            }
+        }
 
-        // Set the pedestrian & vehicle arrival times.
-        sim.set_ped_arrival_times(ped_arrival_times);
-        sim.set_veh_arrival_times(veh_arrival_times);
+        // Far enough away to stop comfortably: the driver reacts and brakes.
+        assert!(matches!(first_event(100.).1, EventType::ReactionToObstacle(0)));
 
-        let next_events = sim.next_events();
-        let actual = next_events.first().unwrap();
-        assert_eq!(actual.0, 10000);
+        // Too close to stop without braking hard: the vehicle goes on through.
+        for position in [140., 155., 168.] {
+            assert!(matches!(first_event(position).1, EventType::VehicleExit(0)));
+        }
+
+        // Over a whole run of busy pelican crossings, no vehicle brakes hard for the lights.
+        let road = Road::new(600.0f32, vec![(Crossing::pelican(0), 180.0), (Crossing::pelican(1), 440.0)]);
+        let run = RunConfig { seed: 0, start_time: 0, end_time: 1_800_000, ped_arrival_rate: 0.1, veh_arrival_rate: 0.2 };
+        let mut sim = EventDrivenSim::new(run, Box::new(SimulatorState::new()), road);
+        sim.set_reaction_time(TimeDelta::from_secs(1), TimeDelta::new(0));
+        sim.run();
+        assert!(sim.veh_counter > 300);
+        assert!(sim.get_emergency_stops().iter().all(|record| record.obstacle_kind != ObstacleKind::Signal));
     }
 
     #[test]
-    fn test_vehicle_arrival_event() {
+    fn test_reaction_time_delays_accelerate() {
+        let mut car = Car::new(0 as ID, Direction::Up, 0.0, Action::StaticSpeed);
+        car.set_reaction_time(TimeDelta::new(1200));
+        let mut vehicles: VecDeque<Box<dyn Vehicle>> = VecDeque::new();
+        vehicles.push_back(Box::new(car));
 
-        let state = Box::new(SimulatorState::new());
+        let state = Box::new(SimulatorState::dummy(vehicles, VecDeque::new(), 0));
         let mut sim = dummy_sim(state);
+        sim.set_ped_arrival_times(Vec::new());
+        sim.set_veh_arrival_times(Vec::new());
 
-        let ped_arrival_times = vec!(5000, 7000);
-        let veh_arrival_times = vec!(4000, 15000);
-
-        // Set the pedestrian & vehicle arrival times.
-        sim.set_ped_arrival_times(ped_arrival_times);
-        sim.set_veh_arrival_times(veh_arrival_times);
+        let next_events = sim.next_events();
+        assert_eq!(next_events[0].0, 1200);
+        assert!(matches!(next_events[0].1, EventType::VehicleAccelerate(0)));
 
+        // The driver keeps the time they noticed the road was clear.
+        sim.roll_forward_by(TimeDelta::new(500));
         let next_events = sim.next_events();
-        let actual = next_events.first().unwrap();
-        assert_eq!(actual.0, 4000);
+        assert_eq!(next_events[0].0, 1200);
+        assert!(matches!(next_events[0].1, EventType::VehicleAccelerate(0)));
     }
 
     #[test]
-    fn test_vehicle_stopping_event() {
-        // Make crossings and road
-        let crossings = vec![
-	        (Crossing::Zebra { id: 0, cross_time: TimeDelta::from_secs(10) }, 170.0),
-	        (Crossing::Zebra { id: 1, cross_time: TimeDelta::from_secs(10) }, 290.0),
-	    ];
-        let road = Road::new(300.0f32, crossings);
+    fn test_sample_reaction_time() {
+        let mut sim = dummy_sim(Box::new(SimulatorState::new()));
+
+        sim.set_reaction_time(TimeDelta::from_secs(1), TimeDelta::new(0));
+        assert_eq!(sim.new_vehicle().unwrap().get_reaction_time(), TimeDelta::from_secs(1));
+        sim.state.pop_vehicle(0);
+
+        sim.set_reaction_time(TimeDelta::from_secs(1), TimeDelta::new(300));
+        let mut reaction_times = Vec::new();
+        for _ in 0..20 {
+            reaction_times.push(sim.new_vehicle().unwrap().get_reaction_time());
+            sim.state.pop_vehicle(0);
+        }
+        assert!(reaction_times.iter().all(|&t| t >= TimeDelta::new(0)));
+        assert!(reaction_times.iter().any(|&t| t != reaction_times[0]));
+    }
 
-        // Set time
-        let timestamp = 22 * TIME_RESOLUTION;
+    #[test]
+    fn test_vehicle_mix() {
+        let mut sim = dummy_sim(Box::new(SimulatorState::new()));
+        assert_eq!(sim.new_vehicle().unwrap().get_kind(), VehicleKind::Car);
+        sim.state.pop_vehicle(0);
+
+        // Vehicles arrive at the top speed of their kind.
+        sim.set_vehicle_mix(vec![(VehicleKind::Bicycle.spec(), 1.0)]);
+        let vehicle = sim.new_vehicle().unwrap();
+        assert_eq!(vehicle.get_kind(), VehicleKind::Bicycle);
+        assert_eq!(vehicle.get_speed(), VehicleKind::Bicycle.spec().max_speed);
+        sim.state.pop_vehicle(0);
+
+        sim.set_vehicle_mix(vec![(VehicleKind::Bus.spec(), 1.0), (VehicleKind::Van.spec(), 1.0), (VehicleKind::Hgv.spec(), 0.0)]);
+        let mut kinds = Vec::new();
+        for _ in 0..20 {
+            kinds.push(sim.new_vehicle().unwrap().get_kind());
+            sim.state.pop_vehicle(0);
+        }
+        assert!(kinds.contains(&VehicleKind::Bus));
+        assert!(kinds.contains(&VehicleKind::Van));
+        assert!(!kinds.contains(&VehicleKind::Hgv));
+    }
 
-        // Make vehicles
-        let mut vehicles: VecDeque<Box<dyn Vehicle>> = VecDeque::new();
-        let speed = 10.0;    
-        let mut car = Car::new(0 as ID, Direction::Up, speed, Action::Deccelerate);
+    #[test]
+    fn test_veh_headways_by_direction() {
+        let mut sim = dummy_no_arrivals_sim(Box::new(SimulatorState::new()));
+        let up = HeadwayConfig::ShiftedExponential { rate: 0.1, min_headway: TimeDelta::from_secs(5) };
+        let down = HeadwayConfig::Exponential { rate: 0.0 };
+        sim.set_veh_headways_by_direction(&up, &down);
+
+        // Arrivals are only in the up direction, at least the minimum headway apart.
+        assert!(sim.veh_arrival_times.len() > 10);
+        assert!(sim.veh_arrival_times.windows(2).all(|pair| pair[1] - pair[0] >= 5000));
+        for _ in 0..10 {
+            let vehicle = sim.new_vehicle().unwrap();
+            assert_eq!(vehicle.get_direction(), Direction::Up);
+            sim.state.pop_vehicle(0);
+        }
 
-        // Set car near crossing
-        car.set_position(140.);
-        vehicles.push_back(Box::new(car));
+        // Setting the arrival times directly forgets the directions.
+        sim.set_veh_arrival_times(vec![1000]);
+        assert!(sim.veh_arrival_directions.is_empty());
+    }
 
-        // Place pedestrian at first crossing
-        let crossing = &road.get_crossings(&Direction::Up)[0].0;
-        let mut peds: VecDeque<Pedestrian> = VecDeque::new();
-        peds.push_back(Pedestrian::new(0, Rc::clone(&crossing), timestamp));
+    #[test]
+    fn test_directional_split() {
+        let mut sim = dummy_sim(Box::new(SimulatorState::new()));
+        sim.set_direction_split(1.0);
+        for _ in 0..10 {
+            assert_eq!(sim.new_vehicle().unwrap().get_direction(), Direction::Up);
+            sim.state.pop_vehicle(0);
+        }
 
-        // Make state
-        let state = Box::new(SimulatorState::dummy(vehicles, peds, timestamp));
+        // Independent arrivals in each direction, three times as many up as down.
+        sim.set_veh_arrival_rates_by_direction(0.3, 0.1);
+        let n_up = sim.veh_arrival_directions.iter().filter(|&&direction| direction == Direction::Up).count() as f32;
+        let n_down = sim.veh_arrival_directions.len() as f32 - n_up;
+        assert!((n_up / 500.0 - 0.3).abs() < 0.05);
+        assert!((n_down / 500.0 - 0.1).abs() < 0.05);
+    }
 
-        // Make sim from state and road
-        let mut sim = EventDrivenSim::new(12345, 0, 500_000, 0.1, 0.1, state, road, None, false);
+    #[test]
+    fn test_informal_crossing() {
+        let road = Road::new(100.0f32, vec![(Crossing::zebra(0), 50.0)]);
+        let mut sim = EventDrivenSim::new(RunConfig::no_arrivals(), Box::new(SimulatorState::new()), road);
+        sim.set_ped_arrival_times(vec!(1_000));
+        sim.set_crossing_choice(CrossingChoice::Weighted { crossing_weights: vec![0.0], informal_weight: 1.0 });
+
+        // The pedestrian crossing informally is recorded, but not added at the crossing.
+        sim.roll_forward_by(TimeDelta::new(1_000));
+        sim.instantaneous_update(EventType::PedestrianArrival);
+        assert!(sim.state.get_pedestrians().is_empty());
+        assert_eq!(sim.get_informal_crossings().len(), 1);
+        assert_eq!(sim.get_informal_crossings()[0].0, 1_000);
+
+        // With no traffic, they step straight out.
+        assert!(!sim.state.get_informal_pedestrians()[0].is_waiting());
+        assert_eq!(sim.get_pedestrian_delays(), &[(0, TimeDelta::new(0))]);
+    }
 
-        // Get next events
+    #[test]
+    fn test_informal_pedestrian_gap_acceptance() {
+        // Car 20m from the pedestrian at top speed: a 1.5s gap.
+        let mut car = Car::new(0 as ID, Direction::Up, MAX_SPEED, Action::StaticSpeed);
+        car.set_position(30.0);
+        let mut vehicles: VecDeque<Box<dyn Vehicle>> = VecDeque::new();
+        vehicles.push_back(Box::new(car));
+        let mut state = Box::new(SimulatorState::dummy(vehicles, VecDeque::new(), 0));
+        state.push_informal_pedestrian(InformalPedestrian::new(0, 50.0, 0, TimeDelta::from_secs(5), TimeDelta::from_secs(3)));
+        let mut sim = dummy_no_arrivals_sim(state);
+        sim.set_veh_arrival_times(Vec::new());
+
+        // The pedestrian waits until the rear of the car has passed them.
+        sim.start_informal_if_clear(0);
+        assert!(sim.state.get_informal_pedestrians()[0].is_waiting());
         let next_events = sim.next_events();
-        let actual = next_events.first().unwrap();
-
-        assert_eq!(actual.0, timestamp + TimeDelta::floor((-1.0) * (speed / DECCELERATION_VALUE)));
+        assert_eq!(next_events.len(), 1);
+        assert_eq!(next_events[0].0, 1_790);
+        assert!(matches!(next_events[0].1, EventType::PedestrianStartCrossing(0)));
+
+        sim.roll_forward_by(TimeDelta::new(1_790));
+        sim.instantaneous_update(EventType::PedestrianStartCrossing(0));
+        assert!(sim.state.get_informal_pedestrians()[0].is_active(1_790));
+        assert_eq!(sim.get_pedestrian_delays(), &[(0, TimeDelta::new(1_790))]);
+
+        // They leave the road once across it.
+        sim.roll_forward_by(TimeDelta::from_secs(5));
+        sim.instantaneous_update(EventType::PedestrianExit(0));
+        assert!(sim.state.get_informal_pedestrians().is_empty());
     }
 
     #[test]
-    fn test_case1_reaction_to_obstacle() {
-        let mut v1 = Car::new(0, Direction::Up, 14., Action::StaticSpeed);
-        let mut v2 = Car::new(1, Direction::Up, 0., Action::StaticSpeed);
-        v1.set_position(0.);
-        v2.set_position(100. + v2.get_length());
-
+    fn test_vehicle_enters_clear_lane() {
+        let mut road = Road::new(100.0, Vec::new());
+        road.set_lanes(2);
+        let mut queued = Car::new(100, Direction::Up, 0.0, Action::StaticSpeed);
+        queued.set_position(4.5);
         let mut vehicles: VecDeque<Box<dyn Vehicle>> = VecDeque::new();
-        vehicles.push_back(Box::new(v1));
-        vehicles.push_back(Box::new(v2));
-        
+        vehicles.push_back(Box::new(queued));
         let state = Box::new(SimulatorState::dummy(vehicles, VecDeque::new(), 0));
-        let sim = dummy_sim(state);
-        let mv1 = sim.state.get_vehicle(0);
-        let mv2 = sim.state.get_vehicle(1);
-        let t_p = sim.time_to_obstacle_event::<dyn Obstacle>(mv1, mv2.as_obstacle(), false, false);
-        assert!(f32::abs(t_p.unwrap() - 5.321429) < MY_EPSILON);
-
+        let mut sim = EventDrivenSim::new(RunConfig::no_arrivals(), state, road);
+        sim.set_veh_arrival_times(vec![0]);
+        sim.set_direction_split(1.0);
+
+        // The queue blocks the nearside lane, so the new vehicle takes the other.
+        let vehicle = sim.new_vehicle().unwrap();
+        assert_eq!(vehicle.get_lane(), 1);
+        assert_eq!(vehicle.get_speed(), vehicle.get_max_speed());
     }
-    #[test]
-    fn test_case1b_reaction_to_obstacle() {
-        let mut v1 = Car::new(0, Direction::Up, 14., Action::StaticSpeed);
-        let mut v2 = Car::new(1, Direction::Up, 10., Action::StaticSpeed);
-        v1.set_position(80.);
-        v2.set_position(100. + v2.get_length());
 
+    #[test]
+    fn test_multiple_threat() {
+        let mut road = Road::new(100.0, vec![(Crossing::zebra(0), 50.0)]);
+        road.set_lanes(2);
+
+        // A car stopped at the zebra in the nearside lane, and one
+        // approaching in the offside lane.
+        let mut stopped = Car::new(0, Direction::Up, 0.0, Action::StaticSpeed);
+        stopped.set_position(45.0);
+        let mut passing = Car::new(1, Direction::Up, MAX_SPEED, Action::StaticSpeed);
+        passing.set_lane(1);
+        passing.set_position(20.0);
         let mut vehicles: VecDeque<Box<dyn Vehicle>> = VecDeque::new();
-        vehicles.push_back(Box::new(v1));
-        vehicles.push_back(Box::new(v2));
-
+        vehicles.push_back(Box::new(stopped));
+        vehicles.push_back(Box::new(passing));
         let state = Box::new(SimulatorState::dummy(vehicles, VecDeque::new(), 0));
-        let sim = dummy_sim(state);
-        let mv1 = sim.state.get_vehicle(0);
-        let mv2 = sim.state.get_vehicle(1);
-        let t_p = sim.time_to_obstacle_event::<dyn Obstacle>(mv1, mv2.as_obstacle(), false, false);
-        assert!(f32::abs(t_p.unwrap() - 4.25) < MY_EPSILON);
-
+        let sim = EventDrivenSim::new(RunConfig::no_arrivals(), state, road);
+
+        // A pedestrian from the nearside kerb is hidden behind the stopped
+        // car until they step into the offside lane.
+        let passing = sim.state.get_vehicle(1);
+        let crossing_time = TimeDelta::from_secs(8);
+        assert!(sim.pedestrian_hidden(passing, 50.0, sim.pedestrian_slot(Direction::Up, 0, crossing_time)));
+        assert!(!sim.pedestrian_hidden(passing, 50.0, sim.pedestrian_slot(Direction::Up, -2_000, crossing_time)));
+
+        // Nor does the stopped car hide a pedestrian from the far kerb.
+        assert!(!sim.pedestrian_hidden(passing, 50.0, sim.pedestrian_slot(Direction::Down, 0, crossing_time)));
+        assert!(!sim.pedestrian_hidden(sim.state.get_vehicle(0), 50.0, 0));
     }
+
     #[test]
-    fn test_case2_reaction_to_obstacle() {
-        let mut v1 = Car::new(0, Direction::Up, 4., Action::Accelerate);
-        let mut v2 = Car::new(1, Direction::Up, 0., Action::StaticSpeed);
-        v1.set_position(0.);
-        v2.set_position(20. + v2.get_length());
+    fn test_lane_change_decision() {
+        // A car closing on a bicycle in the nearside lane, with the other lane clear
+        let setup = |crossings: Vec<(Crossing, Position)>| {
+            let mut road = Road::new(200.0, crossings);
+            road.set_lanes(2);
+            let bicycle_spec = VehicleKind::Bicycle.spec();
+            let mut bicycle = Car::from_spec(&bicycle_spec, 0, Direction::Up, bicycle_spec.max_speed, Action::StaticSpeed);
+            bicycle.set_position(40.0);
+            let mut car = Car::new(1, Direction::Up, MAX_SPEED, Action::StaticSpeed);
+            car.set_position(15.0);
+            let mut vehicles: VecDeque<Box<dyn Vehicle>> = VecDeque::new();
+            vehicles.push_back(Box::new(bicycle));
+            vehicles.push_back(Box::new(car));
+            let state = Box::new(SimulatorState::dummy(vehicles, VecDeque::new(), 0));
+            EventDrivenSim::new(RunConfig::no_arrivals(), state, road)
+        };
 
-        let mut vehicles: VecDeque<Box<dyn Vehicle>> = VecDeque::new();
-        vehicles.push_back(Box::new(v1));
-        vehicles.push_back(Box::new(v2));
+        let mut sim = setup(Vec::new());
+        assert_eq!(sim.lane_change_target(sim.state.get_vehicle(1)), Some(1));
 
-        let state = Box::new(SimulatorState::dummy(vehicles, VecDeque::new(), 0));
-        let sim = dummy_sim(state);
-        let mv1 = sim.state.get_vehicle(0);
-        let mv2 = sim.state.get_vehicle(1);
-        let t_p = sim.time_to_obstacle_event::<dyn Obstacle>(mv1, mv2.as_obstacle(), false, false);
-        
-        assert!(f32::abs(t_p.unwrap() - 1.539638691) < MY_EPSILON);
+        // Not with a van alongside in the other lane
+        let mut van = Car::from_spec(&VehicleKind::Van.spec(), 2, Direction::Up, MAX_SPEED, Action::StaticSpeed);
+        van.set_lane(1);
+        van.set_position(12.0);
+        sim.state.push_vehicle(Box::new(van));
+        assert_eq!(sim.lane_change_target(sim.state.get_vehicle(1)), None);
 
+        // Nor on the zig-zag lines approaching a crossing
+        let sim = setup(vec![(Crossing::zebra(0), 30.0)]);
+        assert_eq!(sim.lane_change_target(sim.state.get_vehicle(1)), None);
     }
-    #[test]
-    fn test_case2b_reaction_to_obstacle() {
-        let mut v1 = Car::new(0, Direction::Up, 14., Action::Accelerate);
-        let mut v2 = Car::new(1, Direction::Up, 10., Action::StaticSpeed);
-        v1.set_position(80.);
-        v2.set_position(100. + v2.get_length());
 
+    #[test]
+    fn test_vehicle_overtakes_bicycle() {
+        let mut road = Road::new(300.0, Vec::new());
+        road.set_lanes(2);
+        let bicycle_spec = VehicleKind::Bicycle.spec();
+        let mut bicycle = Car::from_spec(&bicycle_spec, 0, Direction::Up, bicycle_spec.max_speed, Action::StaticSpeed);
+        bicycle.set_position(60.0);
+        let car = Car::new(1, Direction::Up, MAX_SPEED, Action::StaticSpeed);
         let mut vehicles: VecDeque<Box<dyn Vehicle>> = VecDeque::new();
-        vehicles.push_back(Box::new(v1));
-        vehicles.push_back(Box::new(v2));
-
+        vehicles.push_back(Box::new(bicycle));
+        vehicles.push_back(Box::new(car));
         let state = Box::new(SimulatorState::dummy(vehicles, VecDeque::new(), 0));
-        let sim = dummy_sim(state);
-        let mv1 = sim.state.get_vehicle(0);
-        let mv2 = sim.state.get_vehicle(1);
-        let t_p = sim.time_to_obstacle_event::<dyn Obstacle>(mv1, mv2.as_obstacle(), false, false);
-
-        assert!(f32::abs(t_p.unwrap() - 1.539638691) < MY_EPSILON);
+        let mut sim = EventDrivenSim::new(RunConfig::no_arrivals(), state, road);
+
+        let mut exits = Vec::new();
+        while !sim.state.get_vehicles().is_empty() {
+            let events = sim.next_events();
+            let time = events[0].0;
+            assert!(time < 100_000);
+            sim.roll_forward_by(TimeDelta::new(time - *sim.state.timestamp()));
+            for event in events {
+                if let EventType::VehicleExit(id) = event.1 {
+                    exits.push(id);
+                }
+                sim.instantaneous_update(event.1);
+            }
+        }
 
+        // The car pulls out to pass the bicycle, returns to the nearside lane
+        // and leaves the road first, without braking.
+        let lane_changes: Vec<(ID, usize)> = sim.get_lane_changes().iter().map(|&(_, id, lane)| (id, lane)).collect();
+        assert_eq!(lane_changes, vec![(1, 1), (1, 0)]);
+        assert_eq!(exits, vec![1, 0]);
+        assert!(sim.get_emergency_stops().is_empty());
     }
-    
+
     #[test]
-    fn test_case3_reaction_to_obstacle() {
-        let mut v1 = Car::new(0, Direction::Up, 14., Action::Accelerate);
-        let mut v2 = Car::new(1, Direction::Up, 10., Action::Deccelerate);
-        v1.set_position(0.);
-        v2.set_position(25. + v2.get_length());
+    fn test_car_following_stops_at_red_light() {
+        let models = [
+            CarFollowingConfig::Idm { time_headway: TimeDelta::new(1500), exponent: 4.0 },
+            CarFollowingConfig::Gipps
+        ];
+        for model in models {
+            // A long red phase, so the light stays red until the car is at rest.
+            let crossing = Crossing::Pelican {
+                id: 0,
+                stop_time: TimeDelta::from_secs(60),
+                wait_time: TimeDelta::from_secs(5),
+                go_time: TimeDelta::from_secs(5),
+                amber_time: TimeDelta::from_secs(3)
+            };
+            let road = Road::new(300.0f32, vec![(crossing, 170.0)]);
+            let mut car = Car::new(0 as ID, Direction::Up, MAX_SPEED, Action::StaticSpeed);
+            car.set_position(100.);
+            let mut vehicles: VecDeque<Box<dyn Vehicle>> = VecDeque::new();
+            vehicles.push_back(Box::new(car));
+            let state = Box::new(SimulatorState::dummy(vehicles, VecDeque::new(), 0));
+            let mut sim = EventDrivenSim::new(RunConfig::no_arrivals(), state, road);
+            sim.set_ped_arrival_times(Vec::new());
+            sim.set_veh_arrival_times(Vec::new());
+            sim.set_car_following(&model, TimeDelta::new(100));
+            sim.instantaneous_update(EventType::LightsToAmber(0));
+            sim.instantaneous_update(EventType::LightsToRed(0));
+
+            while *sim.state.timestamp() < 40_000 {
+                let events = sim.next_events();
+                let time = events[0].0;
+                sim.roll_forward_by(TimeDelta::new(time - *sim.state.timestamp()));
+                for event in events {
+                    sim.instantaneous_update(event.1);
+                }
+            }
 
-        let mut vehicles: VecDeque<Box<dyn Vehicle>> = VecDeque::new();
-        vehicles.push_back(Box::new(v1));
-        vehicles.push_back(Box::new(v2));
+            // The car brakes smoothly to rest in front of the crossing.
+            let car = sim.state.get_vehicle(0);
+            assert!(car.get_speed() < 0.1, "{:?}", model);
+            assert!(car.get_veh_position() < 170.0 && car.get_veh_position() > 150.0, "{:?}", model);
+            assert!(sim.get_emergency_stops().is_empty(), "{:?}", model);
+            assert!(sim.get_incidents().is_empty(), "{:?}", model);
+        }
+    }
 
-        let state = Box::new(SimulatorState::dummy(vehicles, VecDeque::new(), 0));
-        let sim = dummy_sim(state);
-        let mv1 = sim.state.get_vehicle(0);
-        let mv2 = sim.state.get_vehicle(1);
-        let t_p = sim.time_to_obstacle_event::<dyn Obstacle>(mv1, mv2.as_obstacle(), false, false);
+    #[test]
+    fn test_informal_pedestrian_obstacle() {
+        // One second before the pedestrian reaches the far kerb, a car 10m
+        // away in one direction and one 50m away in the other.
+        let mut near = Car::new(0 as ID, Direction::Up, MAX_SPEED, Action::StaticSpeed);
+        near.set_position(40.0);
+        let far = Car::new(1 as ID, Direction::Down, MAX_SPEED, Action::StaticSpeed);
+        let mut vehicles: VecDeque<Box<dyn Vehicle>> = VecDeque::new();
+        vehicles.push_back(Box::new(near));
+        vehicles.push_back(Box::new(far));
+        let mut state = Box::new(SimulatorState::dummy(vehicles, VecDeque::new(), 4_000));
+        let mut ped = InformalPedestrian::new(2, 50.0, 0, TimeDelta::from_secs(5), TimeDelta::new(0));
+        ped.start_crossing(0);
+        state.push_informal_pedestrian(ped);
+        let sim = dummy_no_arrivals_sim(state);
+
+        // Only the near car could reach them while they are still in the road.
+        let ped = sim.next_informal_pedestrian(sim.state.get_vehicle(0));
+        assert_eq!(ped.map(|ped| ped.get_id()), Some(2));
+        assert!(sim.next_informal_pedestrian(sim.state.get_vehicle(1)).is_none());
+    }
 
-        assert!(f32::abs(t_p.unwrap() - 0.466481135) < MY_EPSILON);
+    #[test]
+    fn test_replay_trace() {
+        let crossings = vec![(Crossing::zebra(0), 30.0), (Crossing::pelican(1), 70.0)];
+        let road = Road::new(100.0f32, crossings);
+        let mut sim = EventDrivenSim::new(RunConfig { ped_arrival_rate: 0.1, veh_arrival_rate: 0.2, ..RunConfig::no_arrivals() }, Box::new(SimulatorState::new()), road);
+        let trace = Trace::from_csv("time,agent,direction,crossing\n\
+            1000,Pedestrian,,1\n\
+            2000,Vehicle,Down,\n\
+            3000,Pedestrian,,0\n").unwrap();
+        sim.set_trace(&trace);
+
+        // The recorded arrivals replace the generated ones.
+        assert_eq!(sim.ped_arrival_times, vec![1000, 3000]);
+        assert_eq!(sim.veh_arrival_times, vec![2000]);
+
+        // Pedestrians go to their recorded crossing, and vehicles travel in
+        // their recorded direction.
+        assert_eq!(sim.new_pedestrian().unwrap().location().get_id(), 1);
+        assert_eq!(sim.new_pedestrian().unwrap().location().get_id(), 0);
+        assert_eq!(sim.new_vehicle().unwrap().get_direction(), Direction::Down);
+    }
 
+    #[test]
+    fn test_driver_variability() {
+        let mut sim = dummy_sim(Box::new(SimulatorState::new()));
+        let spec = VehicleSpec { max_speed: 10.0, max_deceleration: -3.0, ..VehicleKind::Car.spec() };
+        sim.set_vehicle_mix(vec![(spec, 1.0)]);
+        sim.set_driver_variability(2.0, 1.0, -4.0);
+
+        // Drivers vary about the spec of their vehicle, within half of it,
+        // but none want to go faster than it, or brake harder than allowed.
+        let mut speeds = Vec::new();
+        for _ in 0..20 {
+            let vehicle = sim.new_vehicle().unwrap();
+            assert!(vehicle.get_max_speed() >= 5.0 && vehicle.get_max_speed() <= 10.0);
+            assert!(vehicle.get_max_deceleration() >= -4.0 && vehicle.get_max_deceleration() <= -1.5);
+            // New vehicles enter at their driver's desired speed on an empty road.
+            assert_eq!(vehicle.get_speed(), vehicle.get_max_speed());
+            speeds.push(vehicle.get_max_speed());
+            sim.state.pop_vehicle(0);
+        }
+        assert!(speeds.iter().any(|speed| *speed != speeds[0]));
+
+        // However widely drivers vary, none brake harder than allowed, nor
+        // harder than their vehicle can in an emergency.
+        let mut sim = dummy_sim(Box::new(SimulatorState::new()));
+        let strong = VehicleSpec { max_deceleration: -3.0, max_emergency_deceleration: -3.5, ..spec };
+        sim.set_vehicle_mix(vec![(spec, 1.0), (strong, 1.0)]);
+        sim.set_driver_variability(0.0, 20.0, -4.0);
+        let mut decelerations = Vec::new();
+        for _ in 0..50 {
+            let vehicle = sim.new_vehicle().unwrap();
+            assert!(vehicle.get_max_deceleration() >= -4.0);
+            assert!(vehicle.get_max_deceleration() >= vehicle.get_max_emergency_deceleration());
+            decelerations.push(vehicle.get_max_deceleration());
+            sim.state.pop_vehicle(0);
+        }
+        assert!(decelerations.contains(&-4.0) && decelerations.contains(&-3.5));
+
+        // A driver's desired speed is the limit of their acceleration.
+        let mut sim = dummy_sim(Box::new(SimulatorState::new()));
+        let mut state = SimulatorState::new();
+        state.push_vehicle(Box::new(Car::from_spec(&spec, 0, Direction::Up, 8.0, Action::Accelerate)));
+        sim.set_state(Box::new(state));
+        let events = sim.next_events();
+        assert!(matches!(events[0].1, EventType::SpeedLimitReached(0)));
+        assert_eq!(events[0].0, 666);
     }
+
     #[test]
-    fn test_case3_danger_reaction_to_obstacle() {
-        let mut v1 = Car::new(0, Direction::Up, 14., Action::Accelerate);
-        let mut v2 = Car::new(1, Direction::Up, 10., Action::Deccelerate);
-        v1.set_position(0.);
-        v2.set_position(10. + v2.get_length());
+    fn test_vehicle_entry() {
+        let mut leader = Car::new(0, Direction::Up, 0.0, Action::StaticSpeed);
+        leader.set_position(20.0);
+        let mut vehicles: VecDeque<Box<dyn Vehicle>> = VecDeque::new();
+        vehicles.push_back(Box::new(leader));
+        let mut sim = dummy_sim(Box::new(SimulatorState::dummy(vehicles, VecDeque::new(), 1000)));
+
+        // A vehicle entering behind a stopped one enters slowly enough to stop behind it.
+        let car = Car::new(1, Direction::Up, MAX_SPEED, Action::StaticSpeed);
+        let speed = sim.entry_speed(&car).unwrap();
+        assert!((speed * speed / (-2.0 * DECCELERATION_VALUE) - (20.0 - 4.0 - 1.0)).abs() < 0.01);
+
+        // Vehicles in the other direction enter at full speed.
+        let car = Car::new(1, Direction::Down, MAX_SPEED, Action::StaticSpeed);
+        assert_eq!(sim.entry_speed(&car), Some(MAX_SPEED));
+
+        // No room for a vehicle to enter behind one at the start of the road.
+        sim.state.get_mut_vehicle(0).set_position(4.5);
+        let car = Car::new(1, Direction::Up, MAX_SPEED, Action::StaticSpeed);
+        assert_eq!(sim.entry_speed(&car), None);
+
+        // Nor behind one that has just entered, however fast it is going.
+        sim.state.get_mut_vehicle(0).set_position(0.0);
+        sim.state.get_mut_vehicle(0).set_speed(MAX_SPEED);
+        assert_eq!(sim.entry_speed(&car), None);
+
+        // It waits to try again, and a vehicle arriving behind it waits too.
+        sim.set_veh_arrival_times(vec![1000, 1500, 5000]);
+        sim.set_direction_split(1.0);
+        sim.veh_counter = 1;
+        assert!(sim.new_vehicle().is_none());
+        assert_eq!(sim.held_vehicles, vec![HeldVehicles { direction: Direction::Up, count: 1, retry_time: 2000 }]);
+        assert_eq!(sim.next_vehicle_arrival_time(), Some(1500));
+        assert!(sim.new_vehicle().is_none());
+        assert_eq!(sim.held_vehicles[0].count, 2);
+        assert_eq!(sim.next_vehicle_arrival_time(), Some(2000));
+    }
 
+    #[test]
+    fn test_vehicle_entry_by_direction() {
+        // A vehicle stopped at the start of the road blocks entry upwards.
+        let blocker = Car::new(0, Direction::Up, 0.0, Action::StaticSpeed);
         let mut vehicles: VecDeque<Box<dyn Vehicle>> = VecDeque::new();
-        vehicles.push_back(Box::new(v1));
-        vehicles.push_back(Box::new(v2));
+        vehicles.push_back(Box::new(blocker));
+        let mut sim = dummy_no_arrivals_sim(Box::new(SimulatorState::dummy(vehicles, VecDeque::new(), 0)));
+        sim.set_veh_arrival_rates_by_direction(0.4, 0.1);
+        let arrivals = |direction: Direction| sim.veh_arrival_times.iter()
+            .zip(&sim.veh_arrival_directions)
+            .filter(|&(&time, &arrival_direction)| time < 300_000 && arrival_direction == direction)
+            .count();
+        let (n_up, n_down) = (arrivals(Direction::Up), arrivals(Direction::Down));
+
+        while let Some(time) = sim.next_vehicle_arrival_time().filter(|&time| time < 300_000) {
+            sim.roll_forward_by(TimeDelta::new(time - *sim.state.timestamp()));
+            sim.instantaneous_update(EventType::VehicleArrival);
+        }
 
-        let state = Box::new(SimulatorState::dummy(vehicles, VecDeque::new(), 0));
-        let sim = dummy_sim(state);
-        let mv1 = sim.state.get_vehicle(0);
-        let mv2 = sim.state.get_vehicle(1);
-        let t_p = sim.time_to_obstacle_event::<dyn Obstacle>(mv1, mv2.as_obstacle(), false, false);
+        // The vehicles going up all wait, while those going down keep
+        // entering as they arrive.
+        let entered = |direction: Direction| sim.state.get_vehicles().iter()
+            .filter(|vehicle| vehicle.get_direction() == direction)
+            .count();
+        assert_eq!(entered(Direction::Up), 1);
+        assert_eq!(sim.held_vehicles.len(), 1);
+        assert_eq!((sim.held_vehicles[0].direction, sim.held_vehicles[0].count), (Direction::Up, n_up));
+        assert!(n_down > 20);
+        assert_eq!(entered(Direction::Down), n_down);
+    }
 
-        // Negative time as already will end up in danger zone
-        assert!(f32::abs(t_p.unwrap() - -0.124099041) < MY_EPSILON);
+    #[test]
+    fn test_vehicle_entry_short_of_pedestrians_and_red_lights() {
+        let car = Car::new(0, Direction::Up, MAX_SPEED, Action::StaticSpeed);
+        let reaction_secs: f32 = car.get_reaction_time().into();
+        let stops_within = |speed: f32, distance: f32| (stopping_distance(speed, reaction_secs, -DECCELERATION_VALUE) - distance).abs() < 0.01;
+
+        // A vehicle enters slowly enough to stop short of a pedestrian on a zebra.
+        let road = Road::new(100.0, vec![(Crossing::Zebra { id: 0, cross_time: TimeDelta::from_secs(10) }, 20.0)]);
+        let mut peds = VecDeque::new();
+        peds.push_back(Pedestrian::new(0, Rc::clone(&road.get_crossings(&Direction::Up)[0].0), 0));
+        let sim = EventDrivenSim::new(RunConfig::no_arrivals(), Box::new(SimulatorState::dummy(VecDeque::new(), peds, 0)), road);
+        assert!(stops_within(sim.entry_speed(&car).unwrap(), 20.0 - 1.0 - 1.0));
+
+        // Or of one waiting to cross informally.
+        let mut state = Box::new(SimulatorState::dummy(VecDeque::new(), VecDeque::new(), 0));
+        state.push_informal_pedestrian(InformalPedestrian::new(0, 15.0, 0, TimeDelta::from_secs(5), TimeDelta::from_secs(3)));
+        let sim = dummy_no_arrivals_sim(state);
+        assert!(stops_within(sim.entry_speed(&car).unwrap(), 15.0 - 1.0 - 1.0));
+
+        // Or of a red light, but not a green one.
+        let road = Road::new(100.0, vec![(Crossing::pelican(0), 20.0)]);
+        let mut sim = EventDrivenSim::new(RunConfig::no_arrivals(), Box::new(SimulatorState::new()), road);
+        assert_eq!(sim.entry_speed(&car), Some(MAX_SPEED));
+        sim.instantaneous_update(EventType::LightsToAmber(0));
+        sim.instantaneous_update(EventType::LightsToRed(0));
+        assert!(stops_within(sim.entry_speed(&car).unwrap(), 20.0 - 1.0 - 1.0));
+
+        // Informal pedestrians cross clear of where vehicles enter, at either end.
+        let sim = dummy_no_arrivals_sim(Box::new(SimulatorState::new()));
+        let entry_zone = stopping_distance(MAX_SPEED, reaction_secs, -DECCELERATION_VALUE);
+        let range = sim.informal_range();
+        assert!((range.start() - entry_zone).abs() < 0.01);
+        assert!((100.0 - range.end() - entry_zone).abs() < 0.01);
+    }
 
+    #[test]
+    fn test_schedule_recomputes_affected_vehicles() {
+        // Three cars in line and one in the other direction.
+        let mut vehicles: VecDeque<Box<dyn Vehicle>> = VecDeque::new();
+        for (id, position) in [(0, 500.0), (1, 300.0), (2, 100.0)] {
+            let mut car = Car::new(id, Direction::Up, MAX_SPEED, Action::StaticSpeed);
+            car.set_position(position);
+            vehicles.push_back(Box::new(car));
+        }
+        let mut oncoming = Car::new(3, Direction::Down, MAX_SPEED, Action::StaticSpeed);
+        oncoming.set_position(200.0);
+        vehicles.push_back(Box::new(oncoming));
+        let state = Box::new(SimulatorState::dummy(vehicles, VecDeque::new(), 0));
+        let mut sim = EventDrivenSim::new(RunConfig::no_arrivals(), state, Road::new(1000.0, Vec::new()));
+        sim.set_ped_arrival_times(Vec::new());
+        sim.set_veh_arrival_times(Vec::new());
+        let first = sim.next_event_time();
+        let versions = sim.schedule_versions.clone();
+        assert_eq!(versions.len(), 4);
+
+        // Only the middle car and the one behind it are recomputed, making
+        // their earlier events out of date.
+        sim.reschedule(1);
+        sim.update_schedule();
+        for id in 0..4 {
+            assert_eq!(sim.schedule_versions[&id] != versions[&id], id == 1 || id == 2);
+        }
+        let stale = sim.schedule.iter()
+            .filter(|Reverse((_, id, version, _))| sim.schedule_versions[id].0 != *version)
+            .count();
+        assert!(stale > 0);
+
+        // Out-of-date events are skipped, leaving the same next event.
+        assert_eq!(sim.next_event_time(), first);
+        let events = sim.next_events();
+        assert!(events.iter().all(|event| event.0 == first));
     }
+
     #[test]
-    fn test_vehicle_speed_limit_event() {
+    fn test_pedestrian_recomputes_approaching_vehicles() {
+        // Stopped cars short of a zebra and past it, in both directions.
+        let road = Road::new(100.0, vec![(Crossing::zebra(0), 50.0)]);
+        let mut vehicles: VecDeque<Box<dyn Vehicle>> = VecDeque::new();
+        for (id, direction, position) in [(0, Direction::Up, 60.0), (1, Direction::Up, 30.0), (2, Direction::Down, 40.0), (3, Direction::Down, 70.0)] {
+            let mut car = Car::new(id, direction, 0.0, Action::StaticSpeed);
+            car.set_position(position);
+            vehicles.push_back(Box::new(car));
+        }
+        let state = Box::new(SimulatorState::dummy(vehicles, VecDeque::new(), 0));
+        let mut sim = EventDrivenSim::new(RunConfig::no_arrivals(), state, road);
+        sim.set_ped_arrival_times(vec![1000]);
+        sim.set_veh_arrival_times(Vec::new());
+        sim.update_schedule();
+
+        // A pedestrian stepping out only changes the road ahead of the cars
+        // yet to reach the crossing.
+        sim.roll_forward_by(TimeDelta::new(1000));
+        sim.instantaneous_update(EventType::PedestrianArrival);
+        assert!(sim.state.get_pedestrian(0).is_active(1000));
+        assert!(!sim.unscheduled_all);
+        assert_eq!(sim.unscheduled.iter().copied().collect::<Vec<ID>>(), vec![1, 2]);
+    }
 
-        let speed = 10.0;
-        let vehicles: Vec<Box<dyn Vehicle>> = vec!(Box::new(Car::new(0u64, Direction::Up, speed, Action::Accelerate)));
+    #[test]
+    fn test_gap_recomputed_for_vehicles_yet_to_clear() {
+        // A car approaching a zebra, and one that has passed it.
+        let road = Road::new(200.0, vec![(Crossing::zebra(0), 100.0)]);
+        let mut vehicles: VecDeque<Box<dyn Vehicle>> = VecDeque::new();
+        for (id, position) in [(0, 150.0), (1, 90.0)] {
+            let mut car = Car::new(id, Direction::Up, MAX_SPEED, Action::StaticSpeed);
+            car.set_position(position);
+            vehicles.push_back(Box::new(car));
+        }
+        let state = Box::new(SimulatorState::dummy(vehicles, VecDeque::new(), 0));
+        let mut sim = EventDrivenSim::new(RunConfig::no_arrivals(), state, road);
+        sim.set_ped_arrival_times(vec![0]);
+        sim.set_veh_arrival_times(Vec::new());
+        sim.set_critical_gap(TimeDelta::from_secs(3), TimeDelta::new(0));
+
+        // A pedestrian waits for the approaching car to pass, and the time
+        // they step out is kept.
+        sim.instantaneous_update(EventType::PedestrianArrival);
+        assert!(sim.state.get_pedestrian(0).start_time().is_none());
+        let start_time = sim.global_events().into_iter()
+            .find(|event| event.1 == EventType::PedestrianStartCrossing(0))
+            .unwrap().0;
+        assert!(start_time > 0);
+        assert_eq!(sim.gap_times[&0], (100.0, Some(start_time)));
+
+        // The car that has passed does not change it, but the one approaching
+        // does.
+        sim.instantaneous_update(EventType::ReactionToObstacle(0));
+        assert!(sim.gap_times.contains_key(&0));
+        sim.instantaneous_update(EventType::ReactionToObstacle(1));
+        assert!(!sim.gap_times.contains_key(&0));
+        assert!(sim.global_events().iter().all(|event| event.1 != EventType::PedestrianStartCrossing(0) || event.0 != start_time));
+    }
 
-        let timestamp = 11 * TIME_RESOLUTION;
-        let state = Box::new(SimulatorState::dummy(vehicles.into(), VecDeque::new(), timestamp));
+    #[test]
+    fn test_busy_road_recomputes_few_vehicles_per_event() {
+        // Half an hour busy enough for about 500 vehicles, on a road of two
+        // lanes each way with a zebra and a pelican.
+        let mut road = Road::new(600.0, vec![(Crossing::zebra(0), 180.0), (Crossing::pelican(1), 440.0)]);
+        road.set_lanes(2);
+        let run = RunConfig { end_time: 1_800_000, ped_arrival_rate: 0.2, veh_arrival_rate: 0.28, ..RunConfig::no_arrivals() };
+        let mut sim = EventDrivenSim::new(run, Box::new(SimulatorState::new()), road);
+        let mut n_events = 0;
+        while *sim.state.timestamp() < sim.get_end_time() {
+            let event = sim.next_events().pop().unwrap();
+            sim.roll_forward_by(TimeDelta::new(event.0 - *sim.state.timestamp()));
+            sim.instantaneous_update(event.1);
+            n_events += 1;
+        }
+        assert!(sim.veh_counter > 450);
 
-        let mut sim = dummy_sim(state);
+        // Each event recomputes the schedules of a few vehicles, however
+        // many are on the road.
+        assert!(sim.schedule_counter < 6 * n_events);
+    }
 
-        let next_events = sim.next_events();
-        let actual = next_events.first().unwrap();
+    #[test]
+    fn test_events_refer_to_vehicles_by_id() {
+        // A car about to leave the road, with another behind it.
+        let mut vehicles: VecDeque<Box<dyn Vehicle>> = VecDeque::new();
+        for (id, position) in [(7, 99.0), (9, 50.0)] {
+            let mut car = Car::new(id, Direction::Up, MAX_SPEED, Action::StaticSpeed);
+            car.set_position(position);
+            vehicles.push_back(Box::new(car));
+        }
+        let mut sim = dummy_no_arrivals_sim(Box::new(SimulatorState::dummy(vehicles, VecDeque::new(), 0)));
+
+        // Once the first has left, an event for the second still reaches it,
+        // and one for the first has no effect.
+        sim.instantaneous_update(EventType::VehicleExit(7));
+        sim.instantaneous_update(EventType::ReactionToObstacle(9));
+        sim.instantaneous_update(EventType::EmergencyStop(7));
+        assert_eq!(sim.state.get_vehicles().len(), 1);
+        let car = sim.state.get_vehicle(0);
+        assert_eq!(car.get_id(), 9);
+        assert_eq!(car.get_acceleration(), DECCELERATION_VALUE);
+    }
 
-        assert_eq!(actual.0, timestamp + TimeDelta::floor((MAX_SPEED - speed) / ACCELERATION_VALUE));
+    #[test]
+    fn test_simultaneous_events() {
+        // Events at the same time are ordered by type, then by id.
+        assert!(Event(5, EventType::VehicleAccelerate(9)) < Event(5, EventType::SpeedLimitReached(1)));
+        assert!(Event(5, EventType::SpeedLimitReached(1)) < Event(5, EventType::SpeedLimitReached(3)));
+        assert!(Event(5, EventType::StopSimulation) < Event(6, EventType::VehicleAccelerate(0)));
+
+        // Two cars reaching the speed limit at the same time, the one with
+        // the higher id first in the list of vehicles.
+        let mut vehicles: VecDeque<Box<dyn Vehicle>> = VecDeque::new();
+        for (id, position) in [(5, 300.0), (3, 100.0)] {
+            let mut car = Car::new(id, Direction::Up, 10.0, Action::Accelerate);
+            car.set_position(position);
+            vehicles.push_back(Box::new(car));
+        }
+        let state = Box::new(SimulatorState::dummy(vehicles, VecDeque::new(), 0));
+        let mut sim = EventDrivenSim::new(RunConfig::no_arrivals(), state, Road::new(1000.0, Vec::new()));
+        sim.set_ped_arrival_times(Vec::new());
+        sim.set_veh_arrival_times(Vec::new());
+
+        // They are handled one at a time, the other being recomputed once
+        // the first has been handled.
+        let time = TimeDelta::floor((MAX_SPEED - 10.0) / ACCELERATION_VALUE).millis();
+        for id in [3, 5] {
+            let mut events = sim.next_events();
+            assert_eq!(events.len(), 1);
+            let event = events.pop().unwrap();
+            assert!(matches!(event, Event(t, EventType::SpeedLimitReached(i)) if t == time && i == id));
+            sim.roll_forward_by(TimeDelta::new(time - *sim.state.timestamp()));
+            sim.instantaneous_update(event.1);
+        }
+        assert!(sim.state.get_vehicles().iter().all(|vehicle| vehicle.get_speed() == MAX_SPEED));
+        assert!(sim.next_events()[0].0 > time);
     }
-    
+
     #[test]
     fn test_integration_two_zebras() {
 
@@ -997,12 +3800,50 @@ mod tests {
 
         let road = Road::new(300.0f32, crossings);
         let state = Box::new(SimulatorState::new());
-        let mut sim = EventDrivenSim::new(12345, 0, 500_000, 0.1, 0.1, state, road, None, false);
+        let mut sim = EventDrivenSim::new(RunConfig { seed: 12345, ped_arrival_rate: 0.1, veh_arrival_rate: 0.1, ..RunConfig::no_arrivals() }, state, road);
 
         sim.run();
     
     
     }
+
+    #[test]
+    fn test_integration_zebra_and_pelican() {
+
+        let crossings = vec![
+	        (Crossing::Zebra { id: 0, cross_time: TimeDelta::from_secs(10) }, 170.0),
+	        (Crossing::pelican(1), 290.0),
+	    ];
+
+        let road = Road::new(300.0f32, crossings);
+        let state = Box::new(SimulatorState::new());
+        let mut sim = EventDrivenSim::new(RunConfig { seed: 12345, ped_arrival_rate: 0.1, veh_arrival_rate: 0.1, ..RunConfig::no_arrivals() }, state, road);
+
+        sim.run();
+    }
+
+    #[test]
+    fn test_integration_default_config_without_collisions() {
+        // The default config, and the example with every behaviour model on,
+        // run as from the command line
+        let full = crate::get_zebra_config_option(Some(&"examples/full.toml".to_string()));
+        for config in [crate::get_zebra_config(), &full] {
+            let run = RunConfig {
+                seed: 0,
+                start_time: 0,
+                end_time: config.simulation.run_time,
+                ped_arrival_rate: config.simulation.pedestrian_arrival_rate,
+                veh_arrival_rate: config.simulation.vehicle_arrival_rate
+            };
+            let mut sim = EventDrivenSim::new(run, Box::new(SimulatorState::new()), Road::from_config(config));
+            sim.configure(config);
+
+            sim.run();
+
+            assert!(sim.veh_counter > 300);
+            assert!(sim.get_incidents().iter().all(|incident| incident.kind != IncidentKind::Collision));
+        }
+    }
 }
 // TODO: uncomment new tests below based on config when ready
 //     #[test]
@@ -1023,7 +3864,7 @@ mod tests {
 // #[cfg(test)]
 // mod tests {
 //     use std::collections::VecDeque;
-//     use crate::vehicle::{DECCELERATION_VALUE, MAX_SPEED};
+//     use crate::vehicle::{ACCELERATION_VALUE, DECCELERATION_VALUE, MAX_SPEED};
 //     use super::*;
 
 // // <<<<<<< HEAD
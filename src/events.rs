@@ -2,23 +2,29 @@ use crate::pedestrian::Person;
 use crate::{ID, Crossing, Time};
 use crate::vehicle::{Vehicle};
 
-use std::cmp::{Ord, Eq, Ordering};
-
 #[derive(Debug, Ord, PartialOrd, Eq, PartialEq)]
 #[non_exhaustive]
 pub enum EventType {
-    // Ordered according to the order they should be handled
-    VehicleAccelerate(usize),
-    SpeedLimitReached(usize),
-    ZeroSpeedReached(usize),
-    StaticSpeedReached(usize),
-    ReactionToObstacle(usize),
-
-    EmergencyStop(usize),
-
+    // Ordered according to the order they should be handled. Events refer to
+    // vehicles and pedestrians by id, which is unaffected by others leaving.
+    VehicleAccelerate(ID),
+    SpeedLimitReached(ID),
+    ZeroSpeedReached(ID),
+    StaticSpeedReached(ID),
+    ReactionToObstacle(ID),
+    LaneChange(ID),
+
+    EmergencyStop(ID),
+
+    LightsToAmber(usize),
     LightsToRed(usize),
     LightsToGreen(usize),
 
+    PedestrianStartCrossing(ID),
+
+    // Handled once the obstacles ahead of vehicles are up to date
+    CarFollowingUpdate,
+
     // Events placed last so can be sorted to handle these last
     VehicleExit(ID),
     PedestrianExit(ID),
@@ -40,38 +46,14 @@ pub enum EventResult<'a> {
     NoEffect
 }
 
-#[derive(Debug)]
+// Events are ordered by time, then by type in the order they are handled,
+// then by the vehicle, pedestrian or crossing they are for. The order is
+// total, so simultaneous events are always handled in the same order.
+//
+// The event-driven simulation handles one event per iteration, the first in
+// this order, and recomputes the others before taking the next, so each of
+// several simultaneous events is checked against the state left by those
+// before it. The time-stepped simulation instead takes all the events of a
+// step together, in this order.
+#[derive(Debug, Ord, PartialOrd, Eq, PartialEq)]
 pub struct Event(pub Time, pub EventType);
-
-impl PartialEq for Event {
-    fn eq(&self, other: &Self) -> bool {
-        self.0 == other.0
-    }
-}
-impl Eq for Event {}
-
-impl PartialOrd for Event {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        use Ordering::*;
-        if self.0 > other.0 {
-            Some(Greater)
-        } else if self.0 < other.0 {
-            Some(Less)
-        } else {
-            Some(Equal)
-        }
-    }
-}
-
-impl Ord for Event {
-    fn cmp(&self, other: &Self) -> Ordering {
-        use Ordering::*;
-        if self.0 > other.0 {
-            Greater
-        } else if self.0 < other.0 {
-            Less
-        } else {
-            Equal
-        }
-    }
-}
@@ -0,0 +1,284 @@
+use rand::Rng;
+use rand::rngs::StdRng;
+use rand_distr::{Distribution, Exp, Gamma, LogNormal};
+use serde::{Serialize, Deserialize};
+
+use crate::Time;
+use crate::config::ArrivalRate;
+use crate::simulation::interarrival_time;
+use crate::time::{TimeDelta, TIME_RESOLUTION};
+
+// Distribution of the time between successive arrivals.
+pub trait Headway {
+    // Time (in ms) from an arrival at `time` until the next arrival.
+    fn next_headway(&mut self, time: Time, rng: &mut StdRng) -> Time;
+}
+
+fn to_millis(secs: f32) -> Time {
+    f32::round(secs * (TIME_RESOLUTION as f32)) as Time
+}
+
+// Poisson arrivals.
+pub struct Exponential {
+    rate: ArrivalRate
+}
+
+impl Exponential {
+    pub fn new(rate: ArrivalRate) -> Exponential {
+        assert!(rate >= 0.0);
+        Exponential { rate }
+    }
+}
+
+impl Headway for Exponential {
+    fn next_headway(&mut self, _: Time, rng: &mut StdRng) -> Time {
+        interarrival_time(self.rate, rng)
+    }
+}
+
+// Exponential headways of the given mean rate, but never shorter than the
+// minimum headway.
+pub struct ShiftedExponential {
+    min_headway: Time,
+    exp: Exp<f32>
+}
+
+impl ShiftedExponential {
+    pub fn new(rate: ArrivalRate, min_headway: TimeDelta) -> ShiftedExponential {
+        let min_secs: f32 = min_headway.into();
+        assert!(rate > 0.0 && 1.0 / rate > min_secs);
+        let exp = Exp::new(1.0 / (1.0 / rate - min_secs)).unwrap();
+        ShiftedExponential { min_headway: min_headway.millis(), exp }
+    }
+}
+
+impl Headway for ShiftedExponential {
+    fn next_headway(&mut self, _: Time, rng: &mut StdRng) -> Time {
+        self.min_headway + to_millis(self.exp.sample(rng))
+    }
+}
+
+// Sum of `shape` exponential phases: more regular headways than Poisson
+// arrivals of the same rate.
+pub struct Erlang {
+    gamma: Gamma<f32>
+}
+
+impl Erlang {
+    pub fn new(rate: ArrivalRate, shape: u32) -> Erlang {
+        assert!(rate > 0.0 && shape > 0);
+        Erlang { gamma: Gamma::new(shape as f32, 1.0 / (shape as f32 * rate)).unwrap() }
+    }
+}
+
+impl Headway for Erlang {
+    fn next_headway(&mut self, _: Time, rng: &mut StdRng) -> Time {
+        to_millis(self.gamma.sample(rng))
+    }
+}
+
+// Lognormal headways with the given mean rate and standard deviation.
+pub struct Lognormal {
+    lognormal: LogNormal<f32>
+}
+
+impl Lognormal {
+    pub fn new(rate: ArrivalRate, sd: TimeDelta) -> Lognormal {
+        assert!(rate > 0.0);
+        let mean = 1.0 / rate;
+        let sd: f32 = sd.into();
+        let sigma_squared = f32::ln(1.0 + (sd * sd) / (mean * mean));
+        let mu = f32::ln(mean) - sigma_squared / 2.0;
+        Lognormal { lognormal: LogNormal::new(mu, f32::sqrt(sigma_squared)).unwrap() }
+    }
+}
+
+impl Headway for Lognormal {
+    fn next_headway(&mut self, _: Time, rng: &mut StdRng) -> Time {
+        to_millis(self.lognormal.sample(rng))
+    }
+}
+
+// Cowan's M3 model of bunched traffic: a fraction of vehicles travel freely,
+// with shifted exponential headways, and the rest follow in bunches at the
+// minimum headway.
+pub struct CowanM3 {
+    min_headway: Time,
+    free_fraction: f32,
+    exp: Exp<f32>
+}
+
+impl CowanM3 {
+    pub fn new(rate: ArrivalRate, min_headway: TimeDelta, free_fraction: f32) -> CowanM3 {
+        let min_secs: f32 = min_headway.into();
+        assert!(rate > 0.0 && 1.0 / rate > min_secs);
+        assert!(free_fraction > 0.0 && free_fraction <= 1.0);
+        let exp = Exp::new(free_fraction / (1.0 / rate - min_secs)).unwrap();
+        CowanM3 { min_headway: min_headway.millis(), free_fraction, exp }
+    }
+}
+
+impl Headway for CowanM3 {
+    fn next_headway(&mut self, _: Time, rng: &mut StdRng) -> Time {
+        if rng.gen::<f32>() >= self.free_fraction {
+            return self.min_headway;
+        }
+        self.min_headway + to_millis(self.exp.sample(rng))
+    }
+}
+
+// Platoons released from an upstream signal. Vehicles reach the signal as
+// Poisson arrivals and queue while it is red. When it turns green the queue
+// discharges at the saturation headway. The signal cycle starts on green at
+// time zero, and travel time from the signal is ignored.
+pub struct Platoon {
+    rate: ArrivalRate,
+    cycle_time: Time,
+    green_time: Time,
+    saturation_headway: Time,
+    upstream: Option<Time>,
+    last_departure: Option<Time>
+}
+
+impl Platoon {
+    pub fn new(rate: ArrivalRate, cycle_time: TimeDelta, green_time: TimeDelta, saturation_headway: TimeDelta) -> Platoon {
+        assert!(TimeDelta::new(0) < green_time && green_time <= cycle_time);
+        Platoon {
+            rate,
+            cycle_time: cycle_time.millis(),
+            green_time: green_time.millis(),
+            saturation_headway: saturation_headway.millis(),
+            upstream: None,
+            last_departure: None
+        }
+    }
+}
+
+impl Headway for Platoon {
+    fn next_headway(&mut self, time: Time, rng: &mut StdRng) -> Time {
+        let upstream = self.upstream.unwrap_or(time).saturating_add(interarrival_time(self.rate, rng));
+        self.upstream = Some(upstream);
+
+        // Arrivals on red wait for the next green, and a queue discharges at
+        // the saturation headway.
+        let in_cycle = upstream.rem_euclid(self.cycle_time);
+        let release = if in_cycle < self.green_time {
+            upstream
+        } else {
+            upstream - in_cycle + self.cycle_time
+        };
+        let departure = match self.last_departure {
+            Some(last) => Time::max(release, last + self.saturation_headway),
+            None => release
+        };
+        self.last_departure = Some(departure);
+        Time::max(departure - time, 0)
+    }
+}
+
+// A headway distribution, as selected in the config by `distribution`.
+// Rates are per second and times in ms.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "distribution", rename_all = "snake_case")]
+pub enum HeadwayConfig {
+    Exponential { rate: ArrivalRate },
+    ShiftedExponential { rate: ArrivalRate, min_headway: TimeDelta },
+    Erlang { rate: ArrivalRate, shape: u32 },
+    Lognormal { rate: ArrivalRate, sd: TimeDelta },
+    CowanM3 { rate: ArrivalRate, min_headway: TimeDelta, free_fraction: f32 },
+    Platoon { rate: ArrivalRate, cycle_time: TimeDelta, green_time: TimeDelta, saturation_headway: TimeDelta }
+}
+
+impl HeadwayConfig {
+    pub fn build(&self) -> Box<dyn Headway> {
+        match *self {
+            HeadwayConfig::Exponential { rate } => Box::new(Exponential::new(rate)),
+            HeadwayConfig::ShiftedExponential { rate, min_headway } => Box::new(ShiftedExponential::new(rate, min_headway)),
+            HeadwayConfig::Erlang { rate, shape } => Box::new(Erlang::new(rate, shape)),
+            HeadwayConfig::Lognormal { rate, sd } => Box::new(Lognormal::new(rate, sd)),
+            HeadwayConfig::CowanM3 { rate, min_headway, free_fraction } => Box::new(CowanM3::new(rate, min_headway, free_fraction)),
+            HeadwayConfig::Platoon { rate, cycle_time, green_time, saturation_headway } => {
+                Box::new(Platoon::new(rate, cycle_time, green_time, saturation_headway))
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+
+    fn sample(headway: &mut dyn Headway, n: usize) -> Vec<Time> {
+        let mut rng = StdRng::seed_from_u64(147);
+        let mut time = 0;
+        let mut headways = Vec::new();
+        for _ in 0..n {
+            let h = headway.next_headway(time, &mut rng);
+            headways.push(h);
+            time += h;
+        }
+        headways
+    }
+
+    fn mean(headways: &[Time]) -> f32 {
+        headways.iter().sum::<Time>() as f32 / headways.len() as f32
+    }
+
+    #[test]
+    fn test_shifted_exponential() {
+        let headways = sample(&mut ShiftedExponential::new(0.25, TimeDelta::from_secs(2)), 2000);
+        assert!(headways.iter().all(|&h| h >= 2000));
+        assert!((mean(&headways) - 4000.0).abs() < 200.0);
+    }
+
+    #[test]
+    fn test_erlang() {
+        let headways = sample(&mut Erlang::new(0.25, 4), 2000);
+        assert!((mean(&headways) - 4000.0).abs() < 200.0);
+    }
+
+    #[test]
+    fn test_lognormal() {
+        let headways = sample(&mut Lognormal::new(0.25, TimeDelta::from_secs(2)), 2000);
+        assert!(headways.iter().all(|&h| h > 0));
+        assert!((mean(&headways) - 4000.0).abs() < 200.0);
+    }
+
+    #[test]
+    fn test_cowan_m3() {
+        let headways = sample(&mut CowanM3::new(0.25, TimeDelta::from_secs(1), 0.4), 5000);
+        assert!(headways.iter().all(|&h| h >= 1000));
+        let bunched = headways.iter().filter(|&&h| h == 1000).count() as f32 / 5000.0;
+        assert!((bunched - 0.6).abs() < 0.05);
+        assert!((mean(&headways) - 4000.0).abs() < 300.0);
+    }
+
+    #[test]
+    fn test_platoon() {
+        let cycle_time = TimeDelta::from_secs(60);
+        let mut platoon = Platoon::new(0.2, cycle_time, TimeDelta::from_secs(20), TimeDelta::from_secs(2));
+        let headways = sample(&mut platoon, 200);
+
+        // Nothing passes while the upstream signal is red, and vehicles are
+        // never closer than the saturation headway.
+        let mut time = 0;
+        for h in headways {
+            time += h;
+            assert!(time.rem_euclid(60_000) < 20_000 || h == 2000);
+            assert!(h >= 2000 || time == h);
+        }
+    }
+
+    #[test]
+    fn test_deserialize_headway_config() {
+        let config: HeadwayConfig = toml::from_str(r#"
+        distribution = "cowan_m3"
+        rate = 0.2
+        min_headway = 1500
+        free_fraction = 0.5
+        "#).unwrap();
+        assert_eq!(config, HeadwayConfig::CowanM3 { rate: 0.2, min_headway: TimeDelta::new(1500), free_fraction: 0.5 });
+    }
+}
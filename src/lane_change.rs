@@ -0,0 +1,86 @@
+use serde::{Serialize, Deserialize};
+
+use crate::Acceleration;
+use crate::time::TimeDelta;
+
+// Parameters of the MOBIL lane-change model (Kesting, Treiber & Helbing,
+// "General lane-changing model MOBIL for car-following models", 2007).
+// Accelerations are those of the car-following model.
+#[derive(Copy, Clone, Serialize, Deserialize, Debug, PartialEq)]
+#[serde(default)]
+pub struct LaneChange {
+    // Weight a driver gives to the gain or loss of the followers
+    pub politeness: f32,
+    // Net gain in acceleration needed to change lane
+    pub threshold: Acceleration,
+    // Hardest braking (negative) a change may force on the new follower
+    pub safe_deceleration: Acceleration,
+    // Extra gain needed to leave the nearside lane, and given up to return
+    // to it (keep to the nearside lane except to overtake)
+    pub nearside_bias: Acceleration,
+    // Time between a driver's lane-change decisions
+    pub interval: TimeDelta
+}
+
+impl Default for LaneChange {
+    fn default() -> Self {
+        LaneChange {
+            politeness: 0.5,
+            threshold: 0.2,
+            safe_deceleration: -4.0,
+            nearside_bias: 0.3,
+            interval: TimeDelta::from_secs(2)
+        }
+    }
+}
+
+impl LaneChange {
+    // Net advantage of a lane change, if the new follower can brake safely
+    // and the gain outweighs the threshold. Each pair is the acceleration of
+    // a driver before and after the change: the driver changing lane, the
+    // follower in the old lane and the follower in the new lane.
+    pub fn advantage(
+        &self,
+        own: (Acceleration, Acceleration),
+        old_follower: (Acceleration, Acceleration),
+        new_follower: (Acceleration, Acceleration),
+        to_nearside: bool
+    ) -> Option<Acceleration> {
+        if new_follower.1 < self.safe_deceleration {
+            return None;
+        }
+        let gain = own.1 - own.0
+            + self.politeness * (old_follower.1 - old_follower.0 + new_follower.1 - new_follower.0);
+        let bias = if to_nearside { -self.nearside_bias } else { self.nearside_bias };
+        let advantage = gain - self.threshold - bias;
+        if advantage > 0.0 {
+            Some(advantage)
+        } else {
+            None
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lane_change_advantage() {
+        let model = LaneChange::default();
+
+        // A driver gaining 1 m/s^2 changes lane, less the bias away from the nearside
+        assert!((model.advantage((-1.0, 0.0), (0.0, 0.0), (0.0, 0.0), false).unwrap() - 0.5).abs() < 1e-6);
+        // but not if it costs the new follower as much
+        assert_eq!(model.advantage((-1.0, 0.0), (0.0, 0.0), (0.0, -1.0), false), None);
+        // or the new follower would need to brake too hard
+        assert_eq!(model.advantage((-3.0, 0.0), (0.0, 0.0), (0.0, -4.5), false), None);
+
+        // Drivers return to the nearside lane unless it costs them, but only
+        // leave it for a clear gain
+        assert!(model.advantage((0.0, 0.0), (0.0, 0.0), (0.0, 0.0), true).is_some());
+        assert!(model.advantage((0.0, -0.15), (0.0, 0.0), (0.0, 0.0), true).is_none());
+        assert!(model.advantage((0.0, 0.15), (0.0, 0.0), (0.0, 0.0), false).is_none());
+    }
+}
@@ -3,12 +3,19 @@ mod time;
 mod road;
 pub mod state;
 mod pedestrian;
+mod signal;
 mod vehicle;
 mod obstacle;
+mod safety;
 mod simulation;
+mod headway;
+mod lane_change;
+mod car_following;
+pub mod trace;
 mod config;
 mod events;
 pub mod event_driven_sim;
+pub mod time_stepped_sim;
 
 pub use time::TimeDelta;
 
@@ -1,8 +1,12 @@
 use zebra::*;
 
-use zebra::event_driven_sim::EventDrivenSim;
+use zebra::event_driven_sim::{EventDrivenSim, RunConfig};
+use zebra::time_stepped_sim::TimeSteppedSim;
 use zebra::state::SimulatorState;
+use zebra::trace::Trace;
 use clap::{arg, Arg, command, ArgAction, value_parser};
+use log::{Level, LevelFilter};
+use std::io::Write;
 
 // #[derive(Debug, Parser)]
 // struct CLIOptions {}
@@ -13,6 +17,21 @@ fn main() {
             Arg::new("verbose")
                 .short('v')
                 .long("verbose")
+                .help("Log events (-v) and the full state after each update (-vv)")
+                .action(ArgAction::Count),
+        )
+        .arg(
+            Arg::new("quiet")
+                .short('q')
+                .long("quiet")
+                .help("Log only warnings and errors, with no summary")
+                .conflicts_with("verbose")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("step")
+                .long("step")
+                .help("Wait for Enter before each event")
                 .action(ArgAction::SetTrue),
         )
         .arg(
@@ -20,6 +39,12 @@ fn main() {
             .default_value("sim_states.json")
             .required(false)
         )
+        .arg(
+            arg!(-r --records <RECORDS>)
+            .help("File the emergency stops, collisions and near misses are written to, as JSON")
+            .default_value("sim_records.json")
+            .required(false)
+        )
         .arg(
             arg!(-c --config_file <CONFIG_FILE>)
             .default_value("zebra.toml")
@@ -31,27 +56,69 @@ fn main() {
             .required(false)
             .value_parser(value_parser!(u64))
         )
+        .arg(
+            arg!(-t --trace <TRACE_FILE> "Replay the arrivals recorded in a CSV or JSON trace")
+            .required(false)
+        )
         .get_matches();    
 
+    // Log at the level given, unless overridden by RUST_LOG. Normal runs
+    // print only a summary at the end.
+    let level = if *matches.get_one::<bool>("quiet").expect("defaulted by clap") {
+        LevelFilter::Warn
+    } else {
+        match matches.get_count("verbose") {
+            0 => LevelFilter::Info,
+            1 => LevelFilter::Debug,
+            _ => LevelFilter::Trace
+        }
+    };
+    env_logger::Builder::new()
+        .filter_level(level)
+        .parse_default_env()
+        .target(env_logger::Target::Stdout)
+        .format(|buf, record| match record.level() {
+            Level::Info => writeln!(buf, "{}", record.args()),
+            level => writeln!(buf, "[{}] {}", level, record.args())
+        })
+        .init();
+
     // Get configs
     let zebra_config = get_zebra_config_option(matches.get_one::<String>("config_file"));
+    if let Err(message) = zebra_config.validate() {
+        eprintln!("Invalid config: {}", message);
+        std::process::exit(1);
+    }
 
     // Load road from config
     let road = Road::config_new(matches.get_one::<String>("config_file"));
 
     // Make simulation
-    let mut simulation = EventDrivenSim::new(
-        *matches.get_one::<u64>("seed").unwrap(),
-        0,
-        zebra_config.simulation.run_time,
-        zebra_config.simulation.pedestrian_arrival_rate,
-        zebra_config.simulation.vehicle_arrival_rate,
-        Box::new(SimulatorState::new()), 
-        road,
-        Some(matches.get_one::<String>("outfile").unwrap().clone()),
-        *matches.get_one::<bool>("verbose").expect("defaulted by clap")
-    );
-
-    // Run simulation
-    simulation.run();
+    let run = RunConfig {
+        seed: *matches.get_one::<u64>("seed").unwrap(),
+        start_time: 0,
+        end_time: zebra_config.simulation.run_time,
+        ped_arrival_rate: zebra_config.simulation.pedestrian_arrival_rate,
+        veh_arrival_rate: zebra_config.simulation.vehicle_arrival_rate
+    };
+    let mut simulation = EventDrivenSim::new(run, Box::new(SimulatorState::new()), road);
+    simulation.set_outfile(matches.get_one::<String>("outfile").unwrap().clone());
+    simulation.set_stepping(*matches.get_one::<bool>("step").expect("defaulted by clap"));
+    simulation.configure(&zebra_config);
+    if let Some(trace_file) = matches.get_one::<String>("trace") {
+        match Trace::from_file(trace_file) {
+            Ok(trace) => simulation.set_trace(&trace),
+            Err(err) => {
+                eprintln!("Invalid trace {}: {}", trace_file, err);
+                std::process::exit(1);
+            }
+        }
+    }
+    simulation.set_records_file(matches.get_one::<String>("records").unwrap().clone());
+
+    // Run simulation, stepping at a fixed time step if one is given
+    match zebra_config.simulation.time_step {
+        Some(time_step) => TimeSteppedSim::new(simulation, time_step).run(),
+        None => simulation.run()
+    }
 }
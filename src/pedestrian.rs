@@ -1,15 +1,115 @@
 use crate::obstacle::Obstacle;
-use crate::{Time, ID};
+use crate::{Time, ID, TimeDelta, Length, Speed, Position};
 use crate::road::{Crossing, CROSSING_TIME, Direction, Road};
-use serde::ser::{Serialize, Serializer, SerializeStruct};
+use rand::Rng;
+use rand::distributions::WeightedIndex;
+use rand_distr::{Distribution, Normal};
+use serde::{Serialize, Deserialize};
+use serde::ser::{Serializer, SerializeStruct};
 use serde_json::to_string as to_json;
+use std::ops::RangeInclusive;
 use std::rc::Rc;
 
+// Slowest walking speed sampled, to keep crossing times finite.
+pub const MIN_WALKING_SPEED: Speed = 0.5;
+
+// Distribution of pedestrian walking speeds: normal, or a mixture of two
+// normals when part of the population (e.g. elderly people or children)
+// walks more slowly.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct WalkingSpeed {
+    pub mean: Speed,
+    pub sd: Speed,
+    #[serde(default)]
+    pub slow_fraction: f32,
+    #[serde(default)]
+    pub slow_mean: Speed,
+    #[serde(default)]
+    pub slow_sd: Speed
+}
+
+impl WalkingSpeed {
+    pub fn sample<R: Rng>(&self, rng: &mut R) -> Speed {
+        let slow = self.slow_fraction > 0.0 && rng.gen_bool(self.slow_fraction as f64);
+        let (mean, sd) = if slow { (self.slow_mean, self.slow_sd) } else { (self.mean, self.sd) };
+        let speed = if sd > 0.0 { Normal::new(mean, sd).unwrap().sample(rng) } else { mean };
+        f32::max(speed, MIN_WALKING_SPEED)
+    }
+
+    // Time for a pedestrian walking at a sampled speed to cross the road.
+    pub fn sample_crossing_time<R: Rng>(&self, road_width: Length, rng: &mut R) -> TimeDelta {
+        TimeDelta::from(road_width / self.sample(rng))
+    }
+}
+
+// Where a pedestrian crosses: at one of the crossings (by its index along
+// the road) or informally, away from any crossing.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Route {
+    Crossing(usize),
+    Informal(Position)
+}
+
+// How pedestrians choose where to cross.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CrossingChoice {
+    // At random, in proportion to the demand weight of each crossing, or
+    // informally at a random point along the part of the road open to them.
+    Weighted { crossing_weights: Vec<f32>, informal_weight: f32 },
+    // Walking between random points along the road either side of it, at
+    // the crossing with the shortest detour. Pedestrians cross informally,
+    // directly between the two points (or as near as they may), rather than
+    // detour further than `max_detour`.
+    Nearest { max_detour: Length }
+}
+
+impl CrossingChoice {
+    // Equal demand at each of `n_crossings` crossings.
+    pub fn uniform(n_crossings: usize) -> CrossingChoice {
+        CrossingChoice::Weighted { crossing_weights: vec![1.0; n_crossings], informal_weight: 0.0 }
+    }
+
+    // Choose where a pedestrian crosses. Informal crossings are kept to the
+    // positions given, e.g. away from where vehicles enter the road.
+    pub fn choose<R: Rng>(&self, crossings: &[(Rc<Crossing>, Position)], road_length: Length, informal: RangeInclusive<Position>, rng: &mut R) -> Route {
+        match self {
+            CrossingChoice::Weighted { crossing_weights, informal_weight } => {
+                assert_eq!(crossing_weights.len(), crossings.len());
+                let weights = crossing_weights.iter().chain(std::iter::once(informal_weight));
+                let idx = WeightedIndex::new(weights).unwrap().sample(rng);
+                if idx < crossings.len() {
+                    Route::Crossing(idx)
+                } else {
+                    Route::Informal(rng.gen_range(informal))
+                }
+            }
+            CrossingChoice::Nearest { max_detour } => {
+                let origin = rng.gen_range(0.0..road_length);
+                let destination = rng.gen_range(0.0..road_length);
+                // Extra distance walked along the road to cross at a position.
+                let detour = |position: Position| {
+                    (origin - position).abs() + (position - destination).abs() - (origin - destination).abs()
+                };
+                let nearest = crossings.iter().enumerate()
+                    .map(|(idx, (_, position))| (idx, detour(*position)))
+                    .min_by(|x, y| x.1.partial_cmp(&y.1).unwrap());
+                match nearest {
+                    Some((idx, detour)) if detour <= *max_detour => Route::Crossing(idx),
+                    _ => Route::Informal(f32::clamp((origin + destination) / 2.0, *informal.start(), *informal.end()))
+                }
+            }
+        }
+    }
+}
+
 pub trait Person {
     fn set_id(&mut self, id: ID);
     fn get_id(&self) -> ID;
     fn location(&self) -> &Rc<Crossing>;
     fn arrival_time(&self) -> Time;
+    fn crossing_time(&self) -> TimeDelta;
+    fn start_time(&self) -> Option<Time>;
+    fn critical_gap(&self) -> TimeDelta;
 }
 
 #[derive(Debug)]
@@ -17,6 +117,15 @@ pub struct Pedestrian {
     id: ID,
     location: Rc<Crossing>,
     arrival_time: Time,
+    crossing_time: TimeDelta,
+    // Time the pedestrian stepped out, None while waiting to cross
+    start_time: Option<Time>,
+    // Shortest time gap to approaching traffic the pedestrian will cross in
+    critical_gap: TimeDelta,
+    // Leader of the group the pedestrian crossed with, if not themselves
+    group: Option<ID>,
+    // Kerb stepped out from: that beside the nearside lane in this direction
+    kerb: Direction,
 }
 
 impl Person for Pedestrian {
@@ -28,6 +137,18 @@ impl Person for Pedestrian {
         self.arrival_time
     }
 
+    fn crossing_time(&self) -> TimeDelta {
+        self.crossing_time
+    }
+
+    fn start_time(&self) -> Option<Time> {
+        self.start_time
+    }
+
+    fn critical_gap(&self) -> TimeDelta {
+        self.critical_gap
+    }
+
     fn set_id(&mut self, id: ID) {
 	self.id = id;
     }
@@ -61,10 +182,19 @@ impl Obstacle for Pedestrian {
     }
 
     fn is_active(&self, time: Time) -> bool {
-        let arrival_time = self.arrival_time();
-        let crossing_time = self.location.stop_time();
-        let end_time = crossing_time + arrival_time;
-        if time < end_time && time >= arrival_time {
+        // Pedestrians at a pelican wait for the lights, so the red light is
+        // the obstacle rather than the pedestrian.
+        if let Crossing::Pelican {..} = *self.location {
+            return false;
+        }
+        // Waiting pedestrians are not yet in the road.
+        let start_time = match self.start_time() {
+            Some(start_time) => start_time,
+            None => return false
+        };
+        let crossing_time = self.crossing_time();
+        let end_time = crossing_time + start_time;
+        if time < end_time && time >= start_time {
             return true;
         }
         false
@@ -73,13 +203,62 @@ impl Obstacle for Pedestrian {
 
 
 impl Pedestrian {
+    // The pedestrian takes the usual crossing time at their crossing, and
+    // steps out as soon as they arrive.
     pub fn new(id: ID, location: Rc<Crossing>, arrival_time: Time) -> Pedestrian {
+        let crossing_time = location.stop_time();
         Pedestrian {
             id,
             location,
             arrival_time,
+            crossing_time,
+            start_time: Some(arrival_time),
+            critical_gap: TimeDelta::new(0),
+            group: None,
+            kerb: Direction::Up,
         }
     }
+
+    pub fn set_crossing_time(&mut self, crossing_time: TimeDelta) {
+        self.crossing_time = crossing_time;
+    }
+
+    pub fn kerb(&self) -> Direction {
+        self.kerb
+    }
+
+    pub fn set_kerb(&mut self, kerb: Direction) {
+        self.kerb = kerb;
+    }
+
+    // Wait at the kerb until a gap in the traffic of at least `critical_gap`.
+    pub fn wait(&mut self, critical_gap: TimeDelta) {
+        self.start_time = None;
+        self.critical_gap = critical_gap;
+    }
+
+    pub fn is_waiting(&self) -> bool {
+        self.start_time.is_none()
+    }
+
+    // Step out with the group led by pedestrian `group`.
+    pub fn start_crossing(&mut self, time: Time, group: ID) {
+        assert!(self.is_waiting());
+        assert!(time >= self.arrival_time);
+        self.start_time = Some(time);
+        self.group = Some(group);
+    }
+
+    // ID of the group the pedestrian is crossing in: a group of pedestrians
+    // at a crossing is a single obstacle to traffic.
+    pub fn group(&self) -> ID {
+        self.group.unwrap_or(self.id)
+    }
+
+    // Time spent waiting before starting to cross.
+    pub fn delay(&self) -> Option<TimeDelta> {
+        self.start_time.map(|start_time| TimeDelta::new(start_time - self.arrival_time))
+    }
 }
 
 impl Serialize for Pedestrian {
@@ -88,10 +267,129 @@ impl Serialize for Pedestrian {
         S: Serializer,
     {
         // Number of fields in the struct and name.
-        let mut state = serializer.serialize_struct("Pedestrian", 3)?;
+        let mut state = serializer.serialize_struct("Pedestrian", 4)?;
         state.serialize_field("id", &self.id)?;
         state.serialize_field("location", &self.location.get_id())?;
         state.serialize_field("arrival_time", &self.arrival_time)?;
+        state.serialize_field("start_time", &self.start_time)?;
+        state.end()
+    }
+}
+
+// A pedestrian crossing informally, at a position along the road (in the up
+// direction) away from any crossing. Without priority over traffic, they wait
+// at the kerb for a gap they accept, and neither queue nor cross in groups.
+#[derive(Debug)]
+pub struct InformalPedestrian {
+    id: ID,
+    position: Position,
+    arrival_time: Time,
+    crossing_time: TimeDelta,
+    // Time the pedestrian stepped out, None while waiting to cross
+    start_time: Option<Time>,
+    // Shortest time gap to approaching traffic the pedestrian will cross in
+    critical_gap: TimeDelta,
+    // Kerb stepped out from: that beside the nearside lane in this direction
+    kerb: Direction
+}
+
+impl InformalPedestrian {
+    // The pedestrian waits at the kerb on arrival.
+    pub fn new(id: ID, position: Position, arrival_time: Time, crossing_time: TimeDelta, critical_gap: TimeDelta) -> InformalPedestrian {
+        InformalPedestrian { id, position, arrival_time, crossing_time, start_time: None, critical_gap, kerb: Direction::Up }
+    }
+
+    pub fn kerb(&self) -> Direction {
+        self.kerb
+    }
+
+    pub fn set_kerb(&mut self, kerb: Direction) {
+        self.kerb = kerb;
+    }
+
+    pub fn get_id(&self) -> ID {
+        self.id
+    }
+
+    pub fn position(&self) -> Position {
+        self.position
+    }
+
+    pub fn arrival_time(&self) -> Time {
+        self.arrival_time
+    }
+
+    pub fn crossing_time(&self) -> TimeDelta {
+        self.crossing_time
+    }
+
+    pub fn start_time(&self) -> Option<Time> {
+        self.start_time
+    }
+
+    pub fn critical_gap(&self) -> TimeDelta {
+        self.critical_gap
+    }
+
+    pub fn is_waiting(&self) -> bool {
+        self.start_time.is_none()
+    }
+
+    pub fn start_crossing(&mut self, time: Time) {
+        assert!(self.is_waiting());
+        assert!(time >= self.arrival_time);
+        self.start_time = Some(time);
+    }
+
+    // Time the pedestrian reaches the far kerb, once they have stepped out.
+    pub fn end_time(&self) -> Option<Time> {
+        self.start_time.map(|start_time| self.crossing_time + start_time)
+    }
+
+    // Time spent waiting before starting to cross.
+    pub fn delay(&self) -> Option<TimeDelta> {
+        self.start_time.map(|start_time| TimeDelta::new(start_time - self.arrival_time))
+    }
+}
+
+impl Obstacle for InformalPedestrian {
+    fn get_position(&self, road: &Road, direction: &Direction) -> f32 {
+        match direction {
+            Direction::Up => self.position,
+            Direction::Down => road.get_length() - self.position
+        }
+    }
+
+    fn get_obstacle_length(&self) -> f32 {
+        1.0
+    }
+
+    fn get_speed(&self) -> f32 {
+        0.0
+    }
+
+    fn get_acceleration(&self) -> f32 {
+        0.0
+    }
+
+    fn is_active(&self, time: Time) -> bool {
+        match (self.start_time, self.end_time()) {
+            (Some(start_time), Some(end_time)) => start_time <= time && time < end_time,
+            _ => false
+        }
+    }
+}
+
+impl Serialize for InformalPedestrian {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut state = serializer.serialize_struct("InformalPedestrian", 4)?;
+        state.serialize_field("id", &self.id)?;
+        state.serialize_field("position", &self.position)?;
+        state.serialize_field("arrival_time", &self.arrival_time)?;
+        state.serialize_field("start_time", &self.start_time)?;
         state.end()
     }
 }
@@ -100,13 +398,15 @@ impl Serialize for Pedestrian {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
 
     #[test]
     fn test_serialize_pedestrian() {
         let test_pelican = Rc::new(Crossing::pelican(0));
         let test_pedestrian = Pedestrian::new(1, Rc::clone(&test_pelican), 0);
         let as_json = to_json(&test_pedestrian).unwrap();
-        assert_eq!(&as_json, "{\"id\":1,\"location\":0,\"arrival_time\":0}");
+        assert_eq!(&as_json, "{\"id\":1,\"location\":0,\"arrival_time\":0,\"start_time\":0}");
     }
 
     #[test]
@@ -141,10 +441,117 @@ mod tests {
         let test_pedestrian = Pedestrian::new(0, Rc::clone(&test_zebra), arrival_time);
 
         // Expect the exit time to be the arrival time plus the time taken to cross.
-        assert_eq!(test_pedestrian.is_active(arrival_time - 1), false);
-        assert_eq!(test_pedestrian.is_active(arrival_time), true);
-        assert_eq!(test_pedestrian.is_active(arrival_time + test_zebra.stop_time() - 1), true);
-        assert_eq!(test_pedestrian.is_active(arrival_time + test_zebra.stop_time()), false);
+        assert!(!test_pedestrian.is_active(arrival_time - 1));
+        assert!(test_pedestrian.is_active(arrival_time));
+        assert!(test_pedestrian.is_active(arrival_time + test_zebra.stop_time() - 1));
+        assert!(!test_pedestrian.is_active(arrival_time + test_zebra.stop_time()));
+    }
+
+    #[test]
+    fn test_pedestrian_own_crossing_time() {
+        let test_zebra = Rc::new(Crossing::zebra(0));
+        let mut test_pedestrian = Pedestrian::new(0, Rc::clone(&test_zebra), 2000);
+        assert_eq!(test_pedestrian.crossing_time(), CROSSING_TIME);
+
+        test_pedestrian.set_crossing_time(TimeDelta::from_secs(15));
+        assert!(test_pedestrian.is_active(CROSSING_TIME + 2000));
+        assert!(!test_pedestrian.is_active(17_000));
+    }
+
+    #[test]
+    fn test_waiting_pedestrian() {
+        let test_zebra = Rc::new(Crossing::zebra(0));
+        let mut test_pedestrian = Pedestrian::new(0, Rc::clone(&test_zebra), 2000);
+        test_pedestrian.wait(TimeDelta::from_secs(4));
+        assert!(test_pedestrian.is_waiting());
+        assert_eq!(test_pedestrian.critical_gap(), TimeDelta::from_secs(4));
+        assert_eq!(test_pedestrian.delay(), None);
+        assert!(!test_pedestrian.is_active(2000));
+
+        // Active for the crossing time once they step out.
+        assert_eq!(test_pedestrian.group(), 0);
+        test_pedestrian.start_crossing(5000, 7);
+        assert_eq!(test_pedestrian.delay(), Some(TimeDelta::new(3000)));
+        assert_eq!(test_pedestrian.group(), 7);
+        assert!(!test_pedestrian.is_active(4999));
+        assert!(test_pedestrian.is_active(5000));
+        assert!(test_pedestrian.is_active(CROSSING_TIME + 4999));
+        assert!(!test_pedestrian.is_active(CROSSING_TIME + 5000));
+    }
+
+    #[test]
+    fn test_walking_speed() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let fixed = WalkingSpeed { mean: 1.2, sd: 0.0, slow_fraction: 0.0, slow_mean: 0.0, slow_sd: 0.0 };
+        assert_eq!(fixed.sample(&mut rng), 1.2);
+        assert_eq!(fixed.sample_crossing_time(6.0, &mut rng), TimeDelta::from_secs(5));
+
+        // Everyone is slow, and never slower than the minimum walking speed.
+        let slow = WalkingSpeed { slow_fraction: 1.0, slow_mean: 0.6, slow_sd: 0.3, ..fixed.clone() };
+        let speeds: Vec<Speed> = (0..50).map(|_| slow.sample(&mut rng)).collect();
+        assert!(speeds.iter().all(|speed| *speed >= MIN_WALKING_SPEED));
+        assert!(speeds.iter().sum::<Speed>() / 50.0 < 1.0);
+        assert!(speeds.iter().any(|speed| *speed != speeds[0]));
+    }
+
+    #[test]
+    fn test_informal_pedestrian() {
+        let road = Road::new(100.0, Vec::new());
+        let mut ped = InformalPedestrian::new(3, 40.0, 1000, TimeDelta::from_secs(5), TimeDelta::from_secs(4));
+        assert!(ped.is_waiting());
+        assert!(!ped.is_active(1000));
+
+        ped.start_crossing(3000);
+        assert_eq!(ped.delay(), Some(TimeDelta::from_secs(2)));
+        assert!(ped.is_active(3000));
+        assert!(!ped.is_active(8000));
+
+        // Positions are measured along the road in each direction of travel.
+        assert_eq!(ped.get_position(&road, &Direction::Up), 40.0);
+        assert_eq!(ped.get_position(&road, &Direction::Down), 60.0);
+    }
+
+    #[test]
+    fn test_crossing_choice() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let crossings = vec![(Rc::new(Crossing::zebra(0)), 20.0), (Rc::new(Crossing::zebra(1)), 80.0)];
+
+        // Crossings are chosen in proportion to their weights.
+        let weighted = CrossingChoice::Weighted { crossing_weights: vec![3.0, 1.0], informal_weight: 0.0 };
+        let routes: Vec<Route> = (0..1000).map(|_| weighted.choose(&crossings, 100.0, 0.0..=100.0, &mut rng)).collect();
+        let n_first = routes.iter().filter(|&&route| route == Route::Crossing(0)).count();
+        assert!((n_first as f32 / 1000.0 - 0.75).abs() < 0.05);
+
+        // With no weight on the crossings, everyone crosses informally, where
+        // they may.
+        let informal = CrossingChoice::Weighted { crossing_weights: vec![0.0, 0.0], informal_weight: 1.0 };
+        assert!(matches!(informal.choose(&crossings, 100.0, 0.0..=100.0, &mut rng), Route::Informal(_)));
+        assert!((0..100).all(|_| matches!(informal.choose(&crossings, 100.0, 30.0..=70.0, &mut rng),
+            Route::Informal(position) if (30.0..=70.0).contains(&position))));
+
+        // Pedestrians prepared to walk any distance use the crossing with
+        // the shortest detour, and those who walk no further cross directly
+        // unless a crossing happens to be on their way.
+        let nearest = CrossingChoice::Nearest { max_detour: f32::INFINITY };
+        assert!((0..100).all(|_| matches!(nearest.choose(&crossings, 100.0, 0.0..=100.0, &mut rng), Route::Crossing(_))));
+        let direct = CrossingChoice::Nearest { max_detour: 0.0 };
+        let routes: Vec<Route> = (0..100).map(|_| direct.choose(&crossings, 100.0, 0.0..=100.0, &mut rng)).collect();
+        assert!(routes.iter().any(|route| matches!(route, Route::Informal(_))));
+        assert!(routes.iter().any(|route| matches!(route, Route::Crossing(_))));
+        assert!((0..100).all(|_| match direct.choose(&crossings, 100.0, 30.0..=70.0, &mut rng) {
+            Route::Informal(position) => (30.0..=70.0).contains(&position),
+            Route::Crossing(_) => true
+        }));
+    }
+
+    #[test]
+    fn test_pelican_pedestrian_is_not_active() {
+        let test_pelican = Rc::new(Crossing::pelican(0));
+        let arrival_time = 2000;
+        let test_pedestrian = Pedestrian::new(0, Rc::clone(&test_pelican), arrival_time);
+
+        // The red light, not the pedestrian, stops the traffic at a pelican.
+        assert!(!test_pedestrian.is_active(arrival_time));
     }
 
     #[test]
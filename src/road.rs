@@ -4,6 +4,7 @@ use serde::{Serialize, Deserialize};
 use crate::obstacle::Obstacle;
 use crate::{ID, TimeDelta, get_zebra_config_option};
 use crate::config::{get_zebra_config};
+use crate::config::ZebraConfig;
 use crate::{Length, Position, Time};
 use std::rc::Rc;
 
@@ -52,13 +53,15 @@ pub enum Crossing {
 	    id: ID, // unique identifier
         stop_time: TimeDelta, // time traffic is stopped
         wait_time: TimeDelta, // time from pressing button to stop
-        go_time: TimeDelta // min time traffic flow before a stop can occur
+        go_time: TimeDelta, // min time traffic flow before a stop can occur
+        amber_time: TimeDelta // time lights show amber before red
     }
 }
 
 pub const CROSSING_TIME: TimeDelta = TimeDelta::from_secs(10);
 pub const WAIT_TIME: TimeDelta = TimeDelta::from_secs(5);
 pub const GO_TIME: TimeDelta = TimeDelta::from_secs(5);
+pub const AMBER_TIME: TimeDelta = TimeDelta::from_secs(3);
 
 
 impl Crossing {
@@ -74,7 +77,8 @@ impl Crossing {
 	        id,
             stop_time: CROSSING_TIME,
             wait_time: WAIT_TIME,
-            go_time: GO_TIME
+            go_time: GO_TIME,
+            amber_time: AMBER_TIME
         }
     }
 
@@ -113,6 +117,13 @@ impl Crossing {
         }
     }
 
+    pub fn amber_time(&self) -> TimeDelta {
+        match self {
+            Crossing::Zebra {..} => TimeDelta::from(0),
+            Crossing::Pelican {amber_time, ..} => *amber_time
+        }
+    }
+
 }
 
 impl Obstacle for Crossing {
@@ -145,15 +156,19 @@ pub struct Road {
     length: Length,
     crossings_up: Vec<(Rc<Crossing>, Position)>,
     crossings_down: Vec<(Rc<Crossing>, Position)>,
-    exit: Exit
+    exit: Exit,
+    // Number of lanes in each direction
+    lanes: usize
 }
 
-fn get_crossings_up_and_down(length: Length, crossings: Vec<(Crossing, Position)>) -> (Vec<(Rc<Crossing>, Position)>, Vec<(Rc<Crossing>, Position)>) {
+// Crossings along the road in one direction, with their positions
+type Crossings = Vec<(Rc<Crossing>, Position)>;
+
+fn get_crossings_up_and_down(length: Length, crossings: Vec<(Crossing, Position)>) -> (Crossings, Crossings) {
     // Make vec of crossings for up
-    let mut crossings_up: Vec<(Rc<Crossing>, Position)> = Vec::new();
-    for i in 0..crossings.len() {
-        let (crossing, pos) = crossings[i];
-        crossings_up.push((Rc::new(crossing), pos));
+    let mut crossings_up: Crossings = Vec::new();
+    for (crossing, pos) in crossings.iter() {
+        crossings_up.push((Rc::new(*crossing), *pos));
     }
 
     // Make vec of crossings for down with reference to crossings in up
@@ -174,10 +189,8 @@ impl Road {
         }
 
         // Assert the ordering of the ids is correct.
-        let mut i: ID = 0 as ID;
-        for (crossing, _) in &crossings {
+        for (i, (crossing, _)) in (0 as ID..).zip(crossings.iter()) {
             assert_eq!(crossing.get_id(), i);
-            i += 1;
         }
 
         // Make vec of crossings for up and down with Rc
@@ -185,7 +198,7 @@ impl Road {
 
         let exit = Exit::new(length);
 
-        Road { length, crossings_up, crossings_down, exit}
+        Road { length, crossings_up, crossings_down, exit, lanes: 1 }
     }
 
     // Here the position of the crossings is assumed to be in the `Up` direction.
@@ -193,30 +206,22 @@ impl Road {
 
         // Load from zebra.toml
         let config = get_zebra_config_option(file_name);
+        Road::from_config(&config)
+    }
+
+    pub fn from_config(config: &ZebraConfig) -> Road {
 
         // Assign length from config
         let length = config.road_length;
 
-        // Load in crossings
-        let mut crossings: Vec<(Crossing, Position)> = Vec::new();
-        for &crossing in &config.zebra_crossings {
-            crossings.push(
-                (Crossing::zebra(u64::max_value()), crossing)
-            );
-        }
-        for &crossing in &config.pelican_crossings {
-            crossings.push(
-                (Crossing::pelican(u64::max_value()), crossing)
-            );
-        }
+        // Load in crossings, with the timings from the config
+        let mut crossings = config.all_crossings();
 
         // Sort crossings by position (second element of tuple)
         crossings.sort_by(|x, y| std::cmp::PartialOrd::partial_cmp(&x.1, &y.1).unwrap());
 
-        let mut i = 0;
-        for (crossing, _) in &mut crossings {
+        for (i, (crossing, _)) in crossings.iter_mut().enumerate() {
             crossing.set_id(i as ID);
-            i += 1;
         }
 
         // Check valid crossings
@@ -230,7 +235,9 @@ impl Road {
         // Make exit
         let exit = Exit::new(length);
 
-        Road { length, crossings_up, crossings_down, exit }
+        let mut road = Road { length, crossings_up, crossings_down, exit, lanes: 1 };
+        road.set_lanes(config.lanes_per_direction);
+        road
     }
 
     pub fn get_length(&self) -> Length {
@@ -241,6 +248,25 @@ impl Road {
         &self.exit
     }
 
+    pub fn get_lanes(&self) -> usize {
+        self.lanes
+    }
+
+    pub fn set_lanes(&mut self, lanes: usize) {
+        assert!(lanes > 0);
+        self.lanes = lanes;
+    }
+
+    // Place of a lane across the road, counting from the kerb beside the
+    // nearside lane (lane 0) in the up direction.
+    pub fn lane_slot(&self, direction: Direction, lane: usize) -> usize {
+        assert!(lane < self.lanes);
+        match direction {
+            Direction::Up => lane,
+            Direction::Down => 2 * self.lanes - 1 - lane
+        }
+    }
+
     pub fn get_crossings(&self, direction: &Direction) -> &[(Rc<Crossing>, Position)]
     {
         match direction {
@@ -421,4 +447,45 @@ mod tests {
         assert_eq!(road.get_crossing_position(&0, Direction::Down), 30.0 - 10.0);
         assert_eq!(road.get_crossing_position(&1, Direction::Down), 30.0 - 13.0);
     }
+
+    #[test]
+    fn test_road_from_config() {
+        let config = ZebraConfig {
+            road_length: 500.0,
+            crossing_time: TimeDelta::from_secs(6),
+            pelican_wait_time: TimeDelta::from_secs(3),
+            pelican_go_time: TimeDelta::from_secs(20),
+            pelican_amber_time: TimeDelta::from_secs(2),
+            zebra_crossings: vec![300.0],
+            pelican_crossings: vec![100.0],
+            ..Default::default()
+        };
+        let road = Road::from_config(&config);
+        let crossings = road.get_crossings(&Direction::Up);
+
+        // Crossings are numbered in order of position, with the timings in the config.
+        assert_eq!(*crossings[0].0, Crossing::Pelican {
+            id: 0,
+            stop_time: TimeDelta::from_secs(6),
+            wait_time: TimeDelta::from_secs(3),
+            go_time: TimeDelta::from_secs(20),
+            amber_time: TimeDelta::from_secs(2)
+        });
+        assert_eq!(*crossings[1].0, Crossing::Zebra { id: 1, cross_time: TimeDelta::from_secs(6) });
+        assert_eq!(road.get_lanes(), 1);
+    }
+
+    #[test]
+    fn test_lane_slot() {
+        let config = ZebraConfig { road_length: 500.0, lanes_per_direction: 2, ..Default::default() };
+        let road = Road::from_config(&config);
+
+        // Lanes across the road, from the nearside lane going up to the
+        // nearside lane coming down.
+        assert_eq!(road.get_lanes(), 2);
+        assert_eq!(road.lane_slot(Direction::Up, 0), 0);
+        assert_eq!(road.lane_slot(Direction::Up, 1), 1);
+        assert_eq!(road.lane_slot(Direction::Down, 1), 2);
+        assert_eq!(road.lane_slot(Direction::Down, 0), 3);
+    }
 }
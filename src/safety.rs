@@ -0,0 +1,488 @@
+use serde::{Serialize, Deserialize};
+
+use crate::{ID, Time, Speed, Acceleration, Length, Position};
+use crate::obstacle::AsObstacle;
+use crate::road::{Direction, Road};
+use crate::state::State;
+use crate::time::TimeDelta;
+use crate::vehicle::Vehicle;
+use std::collections::{HashMap, HashSet};
+
+// Gap to an obstacle below which an approach counts as a near miss
+pub const NEAR_MISS_GAP: Length = 0.5;
+
+#[derive(Copy, Clone, Serialize, Deserialize, Debug, PartialEq, Eq, Hash)]
+pub enum ObstacleKind {
+    Pedestrian,
+    Vehicle,
+    Signal
+}
+
+// Record of a vehicle that reacted too late to stop with normal braking.
+//
+// Whether the vehicle hits the obstacle is decided when braking begins, by
+// comparing its emergency stopping position with the near edge of the
+// obstacle (assuming a vehicle obstacle brakes at the same moment).
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct EmergencyStop {
+    pub vehicle_id: ID,
+    pub time: Time,
+    pub speed: Speed,
+    pub obstacle_kind: ObstacleKind,
+    pub obstacle_id: ID,
+    pub stop_position: Position,
+    pub obstacle_position: Position,
+    pub rest_time: Option<Time>,
+    pub hit: bool
+}
+
+impl EmergencyStop {
+    pub fn new(
+        vehicle_id: ID,
+        time: Time,
+        speed: Speed,
+        obstacle_kind: ObstacleKind,
+        obstacle_id: ID,
+        stop_position: Position,
+        obstacle_position: Position
+    ) -> EmergencyStop {
+        EmergencyStop {
+            vehicle_id,
+            time,
+            speed,
+            obstacle_kind,
+            obstacle_id,
+            stop_position,
+            obstacle_position,
+            rest_time: None,
+            hit: stop_position > obstacle_position
+        }
+    }
+}
+
+#[derive(Copy, Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub enum IncidentKind {
+    Collision,
+    NearMiss
+}
+
+// Record of a vehicle hitting, or coming within `NEAR_MISS_GAP` of, an
+// obstacle. The time and closing speed are those of the moment of contact (or
+// of coming within the near miss gap), interpolated within the time step, and
+// the gap is that at the end of the step, so it is negative for collisions.
+//
+// Pedestrians crossing together are a single obstacle, as they are to
+// drivers: a vehicle running into a group is one incident, recorded against
+// the group's leader.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct Incident {
+    pub kind: IncidentKind,
+    pub time: Time,
+    pub vehicle_id: ID,
+    pub obstacle_kind: ObstacleKind,
+    pub obstacle_id: ID,
+    pub closing_speed: Speed,
+    pub gap: Length
+}
+
+// Kinematic state of a vehicle at the start of a time step.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Kinematics {
+    pub id: ID,
+    pub position: Position,
+    pub speed: Speed,
+    pub acceleration: Acceleration
+}
+
+impl Kinematics {
+    pub fn of_vehicles(state: &dyn State) -> Vec<Kinematics> {
+        state.get_vehicles()
+            .iter()
+            .map(|vehicle| Kinematics {
+                id: vehicle.get_id(),
+                position: vehicle.get_veh_position(),
+                speed: vehicle.get_speed(),
+                acceleration: vehicle.get_acceleration()
+            })
+            .collect()
+    }
+}
+
+// Closing speed once the gap has shrunk from `gap` to `target_gap`, under
+// constant relative acceleration.
+fn closing_speed_at(gap: Length, closing_speed: Speed, rel_accel: Acceleration, target_gap: Length) -> Speed {
+    let squared = closing_speed * closing_speed + 2.0 * rel_accel * (gap - target_gap);
+    f32::sqrt(f32::max(squared, 0.0))
+}
+
+// Time within the step from `start_time` to `end_time` at which the gap has
+// shrunk from `gap` to `target_gap`, under constant relative acceleration. The
+// end of the step if the motion at its start would not close the gap (as when
+// the obstacle moved rather than the vehicle).
+fn time_at_gap(gap: Length, closing_speed: Speed, rel_accel: Acceleration, target_gap: Length, start_time: Time, end_time: Time) -> Time {
+    let distance = gap - target_gap;
+    if distance <= 0.0 {
+        return start_time;
+    }
+    let squared = closing_speed * closing_speed + 2.0 * rel_accel * distance;
+    if squared < 0.0 {
+        return end_time;
+    }
+    // Solve closing_speed * t + rel_accel * t^2 / 2 = distance for t
+    let seconds = 2.0 * distance / (closing_speed + f32::sqrt(squared));
+    if !seconds.is_finite() {
+        return end_time;
+    }
+    Time::min(start_time + TimeDelta::from(seconds).millis(), end_time)
+}
+
+type Conflict = (ID, ObstacleKind, ID);
+
+// Checks the state after each time step for vehicles that have hit, or come
+// close to hitting, a pedestrian or the vehicle in front.
+//
+// Each conflict between a vehicle and an obstacle is recorded once, when it
+// starts, rather than at every step while it persists.
+#[derive(Debug, Default)]
+pub struct CollisionDetector {
+    in_contact: HashSet<Conflict>,
+    in_conflict: HashSet<Conflict>,
+    incidents: Vec<Incident>
+}
+
+impl CollisionDetector {
+    pub fn new() -> CollisionDetector {
+        Default::default()
+    }
+
+    pub fn get_incidents(&self) -> &[Incident] {
+        &self.incidents
+    }
+
+    pub fn count(&self, kind: IncidentKind) -> usize {
+        self.incidents.iter().filter(|incident| incident.kind == kind).count()
+    }
+
+    // Check the step from `start_time`, where `previous` holds the vehicles'
+    // kinematics at the start of the step, to the current state.
+    pub fn check(&mut self, state: &dyn State, road: &Road, previous: &[Kinematics], start_time: Time) -> &[Incident] {
+        let time = *state.timestamp();
+        let n_incidents = self.incidents.len();
+        let mut in_contact = HashSet::new();
+        let mut in_conflict = HashSet::new();
+        // Groups hit during this step, recorded once for all their members
+        let mut hit = HashSet::new();
+        let starts: HashMap<ID, &Kinematics> = previous.iter().map(|before| (before.id, before)).collect();
+
+        // Pedestrians are only active or inactive between events, so a
+        // vehicle that drove through an active pedestrian during the step
+        // hit them, even if it has since passed. They are ordered by their
+        // near edge as seen travelling each way.
+        let pedestrians = |direction: Direction| {
+            let mut pedestrians: Vec<(Position, Position, ID)> = state.get_pedestrians().iter()
+                .map(|ped| (ped.group(), ped.as_obstacle()))
+                .chain(state.get_informal_pedestrians().iter().map(|ped| (ped.get_id(), ped.as_obstacle())))
+                .filter(|(_, ped)| ped.is_active(start_time))
+                .map(|(ped_id, ped)| {
+                    let position = ped.get_position(road, &direction);
+                    (position - ped.get_obstacle_length(), position, ped_id)
+                })
+                .collect();
+            pedestrians.sort_by(|x, y| x.0.total_cmp(&y.0));
+            pedestrians
+        };
+        let (pedestrians_up, pedestrians_down) = (pedestrians(Direction::Up), pedestrians(Direction::Down));
+        let longest = pedestrians_up.iter().map(|&(edge, position, _)| position - edge).fold(0.0, f32::max);
+
+        // The vehicle in front of each in its lane, now and at the start of
+        // the step
+        let lane = |vehicle: &dyn Vehicle| (vehicle.get_direction() == Direction::Up, vehicle.get_lane());
+        let leaders = |position: &dyn Fn(&dyn Vehicle) -> Position| {
+            let mut vehicles: Vec<&dyn Vehicle> = state.get_vehicles().iter().map(|vehicle| vehicle.as_ref()).collect();
+            vehicles.sort_by(|x, y| lane(*x).cmp(&lane(*y)).then(position(*x).total_cmp(&position(*y))));
+            vehicles.windows(2)
+                .filter(|pair| lane(pair[0]) == lane(pair[1]))
+                .map(|pair| (pair[0].get_id(), pair[1]))
+                .collect::<HashMap<ID, &dyn Vehicle>>()
+        };
+        let leaders_now = leaders(&|vehicle| vehicle.get_veh_position());
+        let leaders_before = leaders(&|vehicle| starts[&vehicle.get_id()].position);
+
+        for vehicle in state.get_vehicles().iter() {
+            let before = starts[&vehicle.get_id()];
+            let direction = vehicle.get_direction();
+            let front = vehicle.get_veh_position();
+            let length = vehicle.get_length();
+
+            // Pedestrians the vehicle passed or came near during the step
+            let pedestrians = match direction {
+                Direction::Up => &pedestrians_up,
+                Direction::Down => &pedestrians_down
+            };
+            let first = pedestrians.partition_point(|&(edge, _, _)| edge <= before.position - length - longest);
+            for &(edge, position, ped_id) in pedestrians[first..].iter().take_while(|&&(edge, _, _)| edge < front + NEAR_MISS_GAP) {
+                let gap = edge - front;
+                let start_gap = edge - before.position;
+                let key = (vehicle.get_id(), ObstacleKind::Pedestrian, ped_id);
+
+                if front > edge && before.position < position + length {
+                    // Contact at some point in the step
+                    if !self.in_contact.contains(&key) && hit.insert(key) {
+                        let target_gap = f32::min(start_gap, 0.0);
+                        let closing_speed = closing_speed_at(start_gap, before.speed, before.acceleration, target_gap);
+                        let contact_time = time_at_gap(start_gap, before.speed, before.acceleration, target_gap, start_time, time);
+                        self.record(IncidentKind::Collision, contact_time, vehicle.get_id(), key, closing_speed, gap);
+                    }
+                    if front - length < position {
+                        in_contact.insert(key);
+                    }
+                } else if (0.0..NEAR_MISS_GAP).contains(&gap) {
+                    if !self.in_conflict.contains(&key) && !in_conflict.contains(&key) {
+                        let target_gap = f32::min(start_gap, NEAR_MISS_GAP);
+                        let closing_speed = closing_speed_at(start_gap, before.speed, before.acceleration, target_gap);
+                        let conflict_time = time_at_gap(start_gap, before.speed, before.acceleration, target_gap, start_time, time);
+                        self.record(IncidentKind::NearMiss, conflict_time, vehicle.get_id(), key, closing_speed, gap);
+                    }
+                    in_conflict.insert(key);
+                }
+            }
+
+            // The vehicle in front
+            if let Some(leader) = leaders_now.get(&vehicle.get_id()) {
+                let key = (vehicle.get_id(), ObstacleKind::Vehicle, leader.get_id());
+                let gap = leader.get_veh_position() - leader.get_length() - front;
+                let leader_before = starts[&leader.get_id()];
+                let start_gap = leader_before.position - leader.get_length() - before.position;
+                let rel_speed = before.speed - leader_before.speed;
+                let rel_accel = before.acceleration - leader_before.acceleration;
+                if gap < 0.0 {
+                    if !self.in_contact.contains(&key) {
+                        let target_gap = f32::min(start_gap, 0.0);
+                        let closing_speed = closing_speed_at(start_gap, rel_speed, rel_accel, target_gap);
+                        let contact_time = time_at_gap(start_gap, rel_speed, rel_accel, target_gap, start_time, time);
+                        self.record(IncidentKind::Collision, contact_time, vehicle.get_id(), key, closing_speed, gap);
+                    }
+                    in_contact.insert(key);
+                } else if gap < NEAR_MISS_GAP {
+                    if !self.in_conflict.contains(&key) {
+                        let target_gap = f32::min(start_gap, NEAR_MISS_GAP);
+                        let closing_speed = closing_speed_at(start_gap, rel_speed, rel_accel, target_gap);
+                        let conflict_time = time_at_gap(start_gap, rel_speed, rel_accel, target_gap, start_time, time);
+                        self.record(IncidentKind::NearMiss, conflict_time, vehicle.get_id(), key, closing_speed, gap);
+                    }
+                    in_conflict.insert(key);
+                }
+            }
+
+            // Vehicles only overtake by changing lane, so a vehicle that ended
+            // the step in front of the one that was ahead of it in its lane
+            // drove through it.
+            if let Some(other) = leaders_before.get(&vehicle.get_id()) {
+                let other_before = starts[&other.get_id()];
+                if other_before.position > before.position && other.get_veh_position() < front {
+                    let key = (vehicle.get_id(), ObstacleKind::Vehicle, other.get_id());
+                    if !self.in_contact.contains(&key) {
+                        let start_gap = other_before.position - other.get_length() - before.position;
+                        let rel_speed = before.speed - other_before.speed;
+                        let rel_accel = before.acceleration - other_before.acceleration;
+                        let target_gap = f32::min(start_gap, 0.0);
+                        let closing_speed = closing_speed_at(start_gap, rel_speed, rel_accel, target_gap);
+                        let contact_time = time_at_gap(start_gap, rel_speed, rel_accel, target_gap, start_time, time);
+                        self.record(IncidentKind::Collision, contact_time, vehicle.get_id(), key, closing_speed, other.get_veh_position() - front);
+                    }
+                }
+            }
+        }
+
+        self.in_contact = in_contact;
+        self.in_conflict = in_conflict;
+        &self.incidents[n_incidents..]
+    }
+
+    fn record(&mut self, kind: IncidentKind, time: Time, vehicle_id: ID, key: Conflict, closing_speed: Speed, gap: Length) {
+        let (_, obstacle_kind, obstacle_id) = key;
+        self.incidents.push(Incident {
+            kind,
+            time,
+            vehicle_id,
+            obstacle_kind,
+            obstacle_id,
+            closing_speed,
+            gap
+        });
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::obstacle::Obstacle;
+    use crate::pedestrian::Pedestrian;
+    use crate::road::{Crossing, Direction};
+    use crate::state::SimulatorState;
+    use crate::time::TimeDelta;
+    use crate::vehicle::{Action, Car, Vehicle};
+    use std::collections::VecDeque;
+    use std::rc::Rc;
+
+    fn zebra_road() -> Road {
+        Road::new(300.0f32, vec![(Crossing::zebra(0), 170.0)])
+    }
+
+    fn single_car_state(road: &Road, car: Car, with_pedestrian: bool) -> SimulatorState {
+        let mut vehicles: VecDeque<Box<dyn Vehicle>> = VecDeque::new();
+        vehicles.push_back(Box::new(car));
+        let mut peds: VecDeque<Pedestrian> = VecDeque::new();
+        if with_pedestrian {
+            let crossing = Rc::clone(&road.get_crossings(&Direction::Up)[0].0);
+            peds.push_back(Pedestrian::new(0, crossing, 0));
+        }
+        SimulatorState::dummy(vehicles, peds, 0)
+    }
+
+    #[test]
+    fn test_detect_drive_through_pedestrian() {
+        let road = zebra_road();
+        let mut car = Car::new(0, Direction::Up, 10.0, Action::StaticSpeed);
+        car.set_position(150.);
+        let mut state = single_car_state(&road, car, true);
+        let mut detector = CollisionDetector::new();
+
+        // Drive straight through the crossing in a single step.
+        let previous = Kinematics::of_vehicles(&state);
+        state.update(TimeDelta::from_secs(4));
+        let incidents = detector.check(&state, &road, &previous, 0);
+        assert_eq!(incidents.len(), 1);
+        assert_eq!(incidents[0].kind, IncidentKind::Collision);
+        assert_eq!(incidents[0].obstacle_kind, ObstacleKind::Pedestrian);
+        assert_eq!(incidents[0].closing_speed, 10.0);
+
+        // Contact comes when the front of the car reaches the pedestrian, part
+        // way through the step.
+        let ped = &state.get_pedestrians()[0];
+        let edge = ped.get_position(&road, &Direction::Up) - ped.get_obstacle_length();
+        assert_eq!(incidents[0].time, TimeDelta::from((edge - 150.0) / 10.0).millis());
+        assert!(incidents[0].time > 0 && incidents[0].time < 4000);
+
+        // Once past, nothing further is recorded.
+        let previous = Kinematics::of_vehicles(&state);
+        state.update(TimeDelta::from_secs(1));
+        assert_eq!(detector.check(&state, &road, &previous, 4000).len(), 0);
+        assert_eq!(detector.count(IncidentKind::Collision), 1);
+    }
+
+    #[test]
+    fn test_detect_near_miss_once() {
+        let road = zebra_road();
+
+        // Stop 0.2m short of the pedestrian.
+        let mut car = Car::new(0, Direction::Up, 0.0, Action::StaticSpeed);
+        car.set_position(170.0 - 1.0 - 0.2);
+        let mut state = single_car_state(&road, car, true);
+        let mut detector = CollisionDetector::new();
+
+        for step in 0..3 {
+            let previous = Kinematics::of_vehicles(&state);
+            state.update(TimeDelta::from_secs(1));
+            detector.check(&state, &road, &previous, step * 1000);
+        }
+        assert_eq!(detector.count(IncidentKind::NearMiss), 1);
+        assert_eq!(detector.count(IncidentKind::Collision), 0);
+    }
+
+    #[test]
+    fn test_detect_rear_end_collision() {
+        let road = zebra_road();
+        let mut follower = Car::new(1, Direction::Up, 8.0, Action::StaticSpeed);
+        follower.set_position(20.0);
+        let mut leader = Car::new(0, Direction::Up, 0.0, Action::StaticSpeed);
+        leader.set_position(30.0);
+
+        let mut vehicles: VecDeque<Box<dyn Vehicle>> = VecDeque::new();
+        vehicles.push_back(Box::new(leader));
+        vehicles.push_back(Box::new(follower));
+        let mut state = SimulatorState::dummy(vehicles, VecDeque::new(), 0);
+        let mut detector = CollisionDetector::new();
+
+        let previous = Kinematics::of_vehicles(&state);
+        state.update(TimeDelta::from_secs(1));
+        let incidents = detector.check(&state, &road, &previous, 0);
+        assert_eq!(incidents.len(), 1);
+        assert_eq!(incidents[0].vehicle_id, 1);
+        assert_eq!(incidents[0].obstacle_kind, ObstacleKind::Vehicle);
+        assert_eq!(incidents[0].obstacle_id, 0);
+        assert!(incidents[0].gap < 0.0);
+
+        // The 6m gap closes at 8m/s after 750ms.
+        assert_eq!(incidents[0].time, 750);
+        assert_eq!(incidents[0].closing_speed, 8.0);
+    }
+
+    #[test]
+    fn test_detect_group_collision_once() {
+        let road = zebra_road();
+        let mut car = Car::new(0, Direction::Up, 10.0, Action::StaticSpeed);
+        car.set_position(150.);
+        let mut vehicles: VecDeque<Box<dyn Vehicle>> = VecDeque::new();
+        vehicles.push_back(Box::new(car));
+
+        // Three pedestrians step out together, led by pedestrian 4.
+        let crossing = Rc::clone(&road.get_crossings(&Direction::Up)[0].0);
+        let mut peds: VecDeque<Pedestrian> = VecDeque::new();
+        for id in [4, 5, 6] {
+            let mut ped = Pedestrian::new(id, Rc::clone(&crossing), 0);
+            ped.wait(TimeDelta::new(0));
+            ped.start_crossing(0, 4);
+            peds.push_back(ped);
+        }
+        let mut state = SimulatorState::dummy(vehicles, peds, 0);
+        let mut detector = CollisionDetector::new();
+
+        // Driving into the group, and on through it over later steps, is a
+        // single collision, with its leader.
+        for step in 0..4 {
+            let previous = Kinematics::of_vehicles(&state);
+            state.update(TimeDelta::from_secs(1));
+            detector.check(&state, &road, &previous, step * 1000);
+        }
+        assert_eq!(detector.count(IncidentKind::Collision), 1);
+        assert_eq!(detector.get_incidents()[0].obstacle_id, 4);
+    }
+
+    #[test]
+    fn test_detect_drive_through_vehicle() {
+        let road = zebra_road();
+        let mut follower = Car::new(1, Direction::Up, 20.0, Action::StaticSpeed);
+        follower.set_position(20.0);
+        let mut leader = Car::new(0, Direction::Up, 0.0, Action::StaticSpeed);
+        leader.set_position(30.0);
+
+        let mut vehicles: VecDeque<Box<dyn Vehicle>> = VecDeque::new();
+        vehicles.push_back(Box::new(leader));
+        vehicles.push_back(Box::new(follower));
+        let mut state = SimulatorState::dummy(vehicles, VecDeque::new(), 0);
+        let mut detector = CollisionDetector::new();
+
+        // In a single step the follower ends up in front of the vehicle it was
+        // behind in its lane, and the collision is recorded rather than lost.
+        let previous = Kinematics::of_vehicles(&state);
+        state.update(TimeDelta::from_secs(1));
+        let incidents = detector.check(&state, &road, &previous, 0);
+        assert_eq!(incidents.len(), 1);
+        assert_eq!(incidents[0].vehicle_id, 1);
+        assert_eq!(incidents[0].obstacle_id, 0);
+        assert_eq!(incidents[0].time, 300);
+        assert!(incidents[0].gap < 0.0);
+    }
+
+    #[test]
+    fn test_emergency_stop_hit() {
+        let short = EmergencyStop::new(0, 1000, 10.0, ObstacleKind::Pedestrian, 3, 20.0, 25.0);
+        assert!(!short.hit);
+        assert_eq!(short.rest_time, None);
+
+        let over = EmergencyStop::new(0, 1000, 10.0, ObstacleKind::Vehicle, 3, 26.0, 25.0);
+        assert!(over.hit);
+    }
+}
@@ -0,0 +1,315 @@
+use serde::{Serialize, Deserialize};
+use serde::ser::{Serializer, SerializeStruct};
+
+use crate::obstacle::Obstacle;
+use crate::road::{Crossing, Direction, Road};
+use crate::{Time, Position, ID};
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+// Phase of the lights to traffic. This simplifies a pelican's cycle to three
+// phases: green, a steady amber, then red, which covers both the steady red
+// and the flashing amber that follows, while pedestrians may be on the
+// crossing. Vehicles that can still stop comfortably when the lights turn
+// amber treat them like any other obstacle from then on, and those that
+// cannot go on through. Pedestrians arriving while the lights are red step
+// out at once, joining those crossing in the current red.
+#[derive(Copy, Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub enum LightPhase {
+    Green,
+    Amber,
+    Red
+}
+
+// Dynamic state of the traffic lights at a crossing, and of the pedestrians
+// waiting there.
+//
+// Every crossing has a signal so that signals can be indexed by crossing ID,
+// but only pelican crossings have lights: for a zebra the phase is `None`.
+#[derive(Debug, Clone)]
+pub struct Signal {
+    crossing: Rc<Crossing>,
+    position: Position,
+    phase: Option<LightPhase>,
+    last_change: Time,
+    button_pressed: Option<Time>,
+    // Pedestrians waiting to cross, in order of arrival
+    queue: VecDeque<ID>,
+    // The group of pedestrians that last stepped out, and when
+    group: Option<(ID, Time)>,
+    // Vehicles too close to stop when the lights turned amber, which go on
+    // through until they next turn green
+    committed: Vec<ID>
+}
+
+impl Signal {
+    // Here the position of the crossing is assumed to be in the `Up` direction.
+    pub fn new(crossing: Rc<Crossing>, position: Position, time: Time) -> Signal {
+        let phase = match *crossing {
+            Crossing::Zebra {..} => None,
+            Crossing::Pelican {..} => Some(LightPhase::Green)
+        };
+        Signal { crossing, position, phase, last_change: time, button_pressed: None, queue: VecDeque::new(), group: None, committed: Vec::new() }
+    }
+
+    pub fn crossing(&self) -> &Rc<Crossing> {
+        &self.crossing
+    }
+
+    pub fn position(&self) -> Position {
+        self.position
+    }
+
+    pub fn phase(&self) -> Option<LightPhase> {
+        self.phase
+    }
+
+    pub fn last_change(&self) -> Time {
+        self.last_change
+    }
+
+    pub fn button_pressed(&self) -> Option<Time> {
+        self.button_pressed
+    }
+
+    // Register a pedestrian pressing the button at the given time.
+    // Presses while the lights are already amber or red, or while a change
+    // is already pending, have no effect.
+    pub fn press_button(&mut self, time: Time) {
+        if self.phase == Some(LightPhase::Green) && self.button_pressed.is_none() {
+            self.button_pressed = Some(time);
+        }
+    }
+
+    // Time of the next scheduled change of phase, if any.
+    //
+    // Lights go amber `wait_time` after the button is pressed, but never
+    // sooner than `go_time` after they last went green. They go red
+    // `amber_time` after that, and back to green `stop_time` after going red.
+    pub fn next_change_time(&self) -> Option<Time> {
+        match self.phase {
+            Some(LightPhase::Green) => self.button_pressed.map(|pressed| {
+                Time::max(
+                    self.crossing.arrival_to_stop_time() + pressed,
+                    self.crossing.min_time_to_next_stop() + self.last_change
+                )
+            }),
+            Some(LightPhase::Amber) => Some(self.crossing.amber_time() + self.last_change),
+            Some(LightPhase::Red) => Some(self.crossing.stop_time() + self.last_change),
+            None => None
+        }
+    }
+
+    pub fn queue(&self) -> &VecDeque<ID> {
+        &self.queue
+    }
+
+    pub fn join_queue(&mut self, id: ID) {
+        self.queue.push_back(id);
+    }
+
+    // The group of pedestrians that last stepped out, by the ID of their
+    // group, and the time they stepped out.
+    pub fn group(&self) -> Option<(ID, Time)> {
+        self.group
+    }
+
+    // The whole queue steps out as a group, led by the pedestrian at the
+    // front. Returns the pedestrians in the group.
+    pub fn start_group(&mut self, time: Time) -> Vec<ID> {
+        let members: Vec<ID> = self.queue.drain(..).collect();
+        if let Some(&leader) = members.first() {
+            self.group = Some((leader, time));
+        }
+        members
+    }
+
+    // Whether the lights stop a vehicle: while amber or red, unless it was
+    // too close to stop when they turned amber.
+    pub fn stops(&self, vehicle_id: ID, time: Time) -> bool {
+        self.is_active(time) && !self.committed.contains(&vehicle_id)
+    }
+
+    pub fn switch_to_amber(&mut self, time: Time, committed: Vec<ID>) {
+        assert_eq!(self.phase, Some(LightPhase::Green));
+        self.phase = Some(LightPhase::Amber);
+        self.last_change = time;
+        self.button_pressed = None;
+        self.committed = committed;
+    }
+
+    pub fn switch_to_red(&mut self, time: Time) {
+        assert_eq!(self.phase, Some(LightPhase::Amber));
+        self.phase = Some(LightPhase::Red);
+        self.last_change = time;
+    }
+
+    pub fn switch_to_green(&mut self, time: Time) {
+        assert_eq!(self.phase, Some(LightPhase::Red));
+        self.phase = Some(LightPhase::Green);
+        self.last_change = time;
+        self.committed.clear();
+    }
+}
+
+impl Obstacle for Signal {
+    fn get_position(&self, road: &Road, direction: &Direction) -> f32 {
+        road.get_crossing_position(&self.crossing.get_id(), *direction)
+    }
+
+    fn get_obstacle_length(&self) -> f32 {
+        // Set length as 1m to get cars to stop buffer plus 1m before the crossing
+        1.0
+    }
+
+    fn get_speed(&self) -> f32 {
+        0.0
+    }
+
+    fn get_acceleration(&self) -> f32 {
+        0.0
+    }
+
+    // Only amber and red lights are obstacles to traffic.
+    fn is_active(&self, _: Time) -> bool {
+        matches!(self.phase, Some(LightPhase::Amber | LightPhase::Red))
+    }
+}
+
+impl Serialize for Signal {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let kind = match *self.crossing {
+            Crossing::Zebra {..} => "Zebra",
+            Crossing::Pelican {..} => "Pelican"
+        };
+
+        // Number of fields in the struct and name.
+        let mut state = serializer.serialize_struct("Crossing", 6)?;
+        state.serialize_field("id", &self.crossing.get_id())?;
+        state.serialize_field("kind", kind)?;
+        state.serialize_field("position", &self.position)?;
+        state.serialize_field("phase", &self.phase)?;
+        state.serialize_field("last_change", &self.last_change)?;
+        state.serialize_field("queue_length", &self.queue.len())?;
+        state.end()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::road::{AMBER_TIME, CROSSING_TIME, WAIT_TIME};
+    use crate::time::TimeDelta;
+    use serde_json::to_string as to_json;
+
+    #[test]
+    fn test_zebra_has_no_lights() {
+        let mut signal = Signal::new(Rc::new(Crossing::zebra(0)), 10.0, 0);
+        signal.press_button(1000);
+        assert_eq!(signal.phase(), None);
+        assert_eq!(signal.next_change_time(), None);
+        assert!(!signal.is_active(1000));
+    }
+
+    #[test]
+    fn test_pelican_light_cycle() {
+        let mut signal = Signal::new(Rc::new(Crossing::pelican(0)), 10.0, 0);
+        assert_eq!(signal.phase(), Some(LightPhase::Green));
+        assert_eq!(signal.next_change_time(), None);
+
+        // Button press after the go time has elapsed: amber after the wait time.
+        let pressed = 20_000;
+        signal.press_button(pressed);
+        assert_eq!(signal.next_change_time(), Some(WAIT_TIME + pressed));
+
+        // A second press does not delay the change.
+        signal.press_button(pressed + 1000);
+        assert_eq!(signal.next_change_time(), Some(WAIT_TIME + pressed));
+
+        // Amber stops every vehicle but those too close to stop when it came on.
+        let amber = WAIT_TIME + pressed;
+        signal.switch_to_amber(amber, vec![3]);
+        assert!(signal.is_active(amber));
+        assert!(signal.stops(2, amber));
+        assert!(!signal.stops(3, amber));
+        assert_eq!(signal.button_pressed(), None);
+        assert_eq!(signal.next_change_time(), Some(AMBER_TIME + amber));
+
+        // Presses while amber are ignored.
+        signal.press_button(amber + 1000);
+        assert_eq!(signal.button_pressed(), None);
+
+        // Those vehicles go on through the red too.
+        let red = AMBER_TIME + amber;
+        signal.switch_to_red(red);
+        assert!(signal.stops(2, red));
+        assert!(!signal.stops(3, red));
+        assert_eq!(signal.next_change_time(), Some(CROSSING_TIME + red));
+
+        // Presses while red are ignored.
+        signal.press_button(red + 1000);
+        assert_eq!(signal.button_pressed(), None);
+
+        let green = CROSSING_TIME + red;
+        signal.switch_to_green(green);
+        assert!(!signal.is_active(green));
+        assert!(!signal.stops(2, green));
+        assert_eq!(signal.last_change(), green);
+
+        // The next amber starts afresh.
+        signal.switch_to_amber(green + 10_000, Vec::new());
+        assert!(signal.stops(3, green + 10_000));
+    }
+
+    #[test]
+    fn test_pelican_go_time_lockout() {
+        let pelican = Crossing::Pelican {
+            id: 0,
+            stop_time: CROSSING_TIME,
+            wait_time: TimeDelta::from_secs(2),
+            go_time: TimeDelta::from_secs(8),
+            amber_time: AMBER_TIME
+        };
+        let mut signal = Signal::new(Rc::new(pelican), 10.0, 0);
+        signal.switch_to_amber(0, Vec::new());
+        signal.switch_to_red(0);
+        signal.switch_to_green(10_000);
+
+        // Pressed shortly after going green: the go time dominates.
+        signal.press_button(11_000);
+        assert_eq!(signal.next_change_time(), Some(18_000));
+    }
+
+    #[test]
+    fn test_queue_steps_out_as_group() {
+        let mut zebra = Signal::new(Rc::new(Crossing::zebra(0)), 10.0, 0);
+        assert_eq!(zebra.start_group(500), Vec::<ID>::new());
+        assert_eq!(zebra.group(), None);
+
+        zebra.join_queue(4);
+        zebra.join_queue(2);
+        assert_eq!(zebra.queue().front(), Some(&4));
+        assert_eq!(zebra.start_group(1000), vec![4, 2]);
+        assert!(zebra.queue().is_empty());
+        assert_eq!(zebra.group(), Some((4, 1000)));
+    }
+
+    #[test]
+    fn test_serialize_signal() {
+        let mut zebra = Signal::new(Rc::new(Crossing::zebra(0)), 10.0, 0);
+        zebra.press_button(500);
+        let as_json = to_json(&zebra).unwrap();
+        assert_eq!(&as_json, "{\"id\":0,\"kind\":\"Zebra\",\"position\":10.0,\"phase\":null,\"last_change\":0,\"queue_length\":0}");
+
+        let mut pelican = Signal::new(Rc::new(Crossing::pelican(1)), 20.0, 0);
+        pelican.switch_to_amber(1500, Vec::new());
+        pelican.switch_to_red(1500);
+        pelican.join_queue(3);
+        let as_json = to_json(&pelican).unwrap();
+        assert_eq!(&as_json, "{\"id\":1,\"kind\":\"Pelican\",\"position\":20.0,\"phase\":\"Red\",\"last_change\":1500,\"queue_length\":1}");
+    }
+}
@@ -1,20 +1,26 @@
 
 use rand_distr::{Exp, Distribution};
 use rand::{SeedableRng}; // SeedableRng needed for the seed_from_u64 method.
+use rand::Rng;
 use rand::rngs::StdRng;
+use serde::{Serialize, Deserialize};
+use log::debug;
+use std::fs::File;
 use crate::events::{Event, EventResult, EventType};
 
-use crate::Time;
+use crate::{Time, raw_input};
+use crate::config::ArrivalRate;
+use crate::headway::{Headway, Exponential};
 use crate::road::Road;
 use crate::time::TIME_RESOLUTION;
 use crate::time::TimeDelta;
 use crate::state::{State, SimulatorState};
-use crate::vehicle::{Vehicle, Car};
 
 
 pub trait Simulation  {
 
-    // get time interval until next event
+    // get time interval until next event, with the events to handle then in
+    // the order to handle them (see Event)
     fn next_events(&mut self) -> Vec<Event>;
 
     // roll simulation forward by time interval
@@ -29,15 +35,73 @@ pub trait Simulation  {
 
     fn get_road(&self) -> &Road;
 
-    fn run(&mut self) -> ();
+    fn get_end_time(&self) -> Time;
+
+    // Whether to wait for Enter before each event
+    fn is_stepping(&self) -> bool;
+
+    // Longest time the state is rolled forward by between logging it, if
+    // the simulation moves in fixed steps
+    fn time_step(&self) -> Option<TimeDelta> {
+        None
+    }
+
+    // Open the file states are logged to, if any.
+    fn open_outfile(&self) -> Option<File>;
+
+    // Log the state at time t to the file, and trace it.
+    fn report(&self, t: Time, file: &mut Option<File>);
+
+    // Write the records of the run to the records file, if any, and log the
+    // totals.
+    fn finish(&self);
+
+    // Simulation algorithm: roll forward to the next events, handle them in
+    // turn and log the state after the update, until the end time.
+    fn run(&mut self) {
+
+        let mut file = self.open_outfile();
+
+        let mut t: Time = *self.get_state().timestamp();
+        while t < self.get_end_time() {
+            // Debugging
+            if self.is_stepping() && t > 0 {
+                raw_input();
+            }
+
+            let next_events = self.next_events();
+            let next_event_time = next_events[0].0;
+
+            // Step forward to the start of the step the events fall in
+            if let Some(time_step) = self.time_step() {
+                while next_event_time - t > time_step.millis() {
+                    self.roll_forward_by(time_step);
+                    t += time_step.millis();
+                    self.report(t, &mut file);
+                }
+            }
+            self.roll_forward_by(TimeDelta::new(next_event_time - t));
+
+            for next_event in next_events {
+                debug!("Event: {:?}", next_event);
+                self.instantaneous_update(next_event.1);
+            }
+
+            t = next_event_time;
+
+            self.report(t, &mut file);
+        }
+
+        self.finish();
+    }
 }
 
-pub fn arrival_times(start_time: &Time, end_time: &Time, arrival_rate: f32, rng: &mut StdRng) -> Vec<Time> {
+pub fn arrival_times(start_time: &Time, end_time: &Time, headway: &mut dyn Headway, rng: &mut StdRng) -> Vec<Time> {
 
     let mut ret = Vec::new();
-    let mut t = start_time.clone();
+    let mut t = *start_time;
     loop {
-        t = t + interarrival_time(arrival_rate, rng);
+        t = t.saturating_add(headway.next_headway(t, rng));
         if &t > end_time { break ret }
         ret.push(t);
     }
@@ -48,6 +112,66 @@ pub fn interarrival_time(arrival_rate: f32, rng: &mut StdRng) -> Time {
     f32::round(exp.sample(rng) * (TIME_RESOLUTION as f32)) as i64
 }
 
+// Arrival rate from a start time (in ms) until the start of the next window.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RateWindow {
+    pub start: Time,
+    pub rate: ArrivalRate
+}
+
+// Piecewise-constant arrival rate over time, e.g. with peaks for the school
+// run. The rate is zero before the first window.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(from = "Vec<RateWindow>", into = "Vec<RateWindow>")]
+pub struct RateProfile {
+    windows: Vec<RateWindow>
+}
+
+impl RateProfile {
+    pub fn new(windows: Vec<RateWindow>) -> RateProfile {
+        assert!(!windows.is_empty());
+        assert!(windows.windows(2).all(|pair| pair[0].start < pair[1].start));
+        assert!(windows.iter().all(|window| window.rate >= 0.0));
+        RateProfile { windows }
+    }
+
+    pub fn rate_at(&self, time: Time) -> ArrivalRate {
+        self.windows.iter()
+            .take_while(|window| window.start <= time)
+            .last()
+            .map_or(0.0, |window| window.rate)
+    }
+
+    pub fn max_rate(&self) -> ArrivalRate {
+        self.windows.iter().map(|window| window.rate).fold(0.0, f32::max)
+    }
+}
+
+impl From<Vec<RateWindow>> for RateProfile {
+    fn from(windows: Vec<RateWindow>) -> Self {
+        RateProfile::new(windows)
+    }
+}
+
+impl From<RateProfile> for Vec<RateWindow> {
+    fn from(profile: RateProfile) -> Self {
+        profile.windows
+    }
+}
+
+// Arrival times of a non-homogeneous Poisson process, by thinning: candidate
+// arrivals at the peak rate are kept in proportion to the rate at the time.
+pub fn profile_arrival_times(start_time: &Time, end_time: &Time, profile: &RateProfile, rng: &mut StdRng) -> Vec<Time> {
+    let max_rate = profile.max_rate();
+    if max_rate == 0.0 {
+        return Vec::new();
+    }
+    arrival_times(start_time, end_time, &mut Exponential::new(max_rate), rng)
+        .into_iter()
+        .filter(|&t| rng.gen::<f32>() * max_rate < profile.rate_at(t))
+        .collect()
+}
+
 
 #[cfg(test)]
 mod tests {
@@ -63,6 +187,46 @@ mod tests {
         assert_eq!(actual, expected);
     }
 
+    #[test]
+    fn test_rate_profile() {
+        let profile = RateProfile::new(vec![
+            RateWindow { start: 1000, rate: 0.5 },
+            RateWindow { start: 5000, rate: 2.0 },
+            RateWindow { start: 8000, rate: 0.0 }
+        ]);
+        assert_eq!(profile.rate_at(0), 0.0);
+        assert_eq!(profile.rate_at(1000), 0.5);
+        assert_eq!(profile.rate_at(4999), 0.5);
+        assert_eq!(profile.rate_at(5000), 2.0);
+        assert_eq!(profile.rate_at(100_000), 0.0);
+        assert_eq!(profile.max_rate(), 2.0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_rate_profile_out_of_order_panics() {
+        RateProfile::new(vec![RateWindow { start: 5000, rate: 1.0 }, RateWindow { start: 1000, rate: 1.0 }]);
+    }
+
+    #[test]
+    fn test_profile_arrival_times() {
+        let mut rng = StdRng::seed_from_u64(147);
+
+        // No arrivals in the quiet hour, and about 3600 in the busy hour.
+        let hour = 3600 * TIME_RESOLUTION;
+        let profile = RateProfile::new(vec![
+            RateWindow { start: 0, rate: 0.0 },
+            RateWindow { start: hour, rate: 1.0 }
+        ]);
+        let actual = profile_arrival_times(&0, &(2 * hour), &profile, &mut rng);
+        assert!(actual.iter().all(|&t| t >= hour && t <= 2 * hour));
+        assert!(actual.len() > 3400 && actual.len() < 3800);
+        assert!(actual.windows(2).all(|pair| pair[0] <= pair[1]));
+
+        let quiet = RateProfile::new(vec![RateWindow { start: 0, rate: 0.0 }]);
+        assert!(profile_arrival_times(&0, &hour, &quiet, &mut rng).is_empty());
+    }
+
     #[test]
     fn test_arrival_times() {
 
@@ -71,7 +235,7 @@ mod tests {
 
         // With this seed, there are 2 arrivals in 10 seconds.
         let end_time = 10 * TIME_RESOLUTION;
-        let actual = arrival_times(&0, &end_time, 0.2, &mut rng);
+        let actual = arrival_times(&0, &end_time, &mut Exponential::new(0.2), &mut rng);
         assert_eq!(actual.len(), 2);
 
         // Check that all arrivals occur before the simulation end time.
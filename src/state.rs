@@ -1,9 +1,10 @@
-use crate::{Time, raw_input};
+use crate::{ID, Time, raw_input};
 use crate::time::TimeDelta;
 use crate::vehicle::{Vehicle, Car, Action};
-use std::collections::VecDeque;
+use std::collections::{HashMap, VecDeque};
 use crate::road::{Direction, Crossing};
-use crate::pedestrian::Pedestrian;
+use crate::pedestrian::{Pedestrian, InformalPedestrian};
+use crate::signal::Signal;
 use serde::ser::{Serialize, Serializer, SerializeStruct};
 use serde_json::to_string as to_json;
 use std::rc::Rc;
@@ -22,13 +23,26 @@ pub trait State  {
     // get the list of pedestrians
     fn get_pedestrians(&self) ->  &VecDeque<Pedestrian>;
 
+    // get the list of pedestrians crossing away from any crossing
+    fn get_informal_pedestrians(&self) -> &VecDeque<InformalPedestrian>;
+
     fn get_vehicle(&self, idx: usize) -> &dyn Vehicle;
+    // Index of the vehicle with the given id, if it is on the road
+    fn vehicle_index(&self, id: ID) -> Option<usize>;
     fn get_mut_vehicle(&mut self, idx: usize) -> &mut dyn Vehicle;
     fn get_pedestrian(&self, idx: usize) -> &Pedestrian;
     fn get_mut_pedestrian(&mut self, idx: usize) -> &mut Pedestrian;
+    fn get_mut_informal_pedestrian(&mut self, idx: usize) -> &mut InformalPedestrian;
+
+    // get the crossing signals, indexed by crossing ID
+    fn get_signals(&self) -> &[Signal];
+    fn get_mut_signal(&mut self, idx: usize) -> &mut Signal;
+    fn set_signals(&mut self, signals: Vec<Signal>);
 
     fn push_pedestrian(&mut self, pedestrian: Pedestrian) -> usize;
     fn pop_pedestrian(&mut self, idx: usize);
+    fn push_informal_pedestrian(&mut self, pedestrian: InformalPedestrian) -> usize;
+    fn pop_informal_pedestrian(&mut self, idx: usize);
     // fn pop_pedestrian(&mut self, idx: usize) -> Pedestrian;
     fn push_vehicle(&mut self, vehicle: Box<dyn Vehicle>) -> usize;
     fn pop_vehicle(&mut self, idx: usize);
@@ -41,10 +55,12 @@ impl  Serialize for dyn State  {
         S: Serializer,
     {
         // Number of fields in the struct and name.
-        let mut state = serializer.serialize_struct("State", 3)?;
+        let mut state = serializer.serialize_struct("State", 5)?;
         state.serialize_field("timestamp", &self.timestamp())?;
         state.serialize_field("pedestrians", &self.get_pedestrians())?;
+        state.serialize_field("informal_pedestrians", &self.get_informal_pedestrians())?;
         state.serialize_field("vehicles", &self.get_vehicles())?;
+        state.serialize_field("crossings", &self.get_signals())?;
         state.end()
     }
 }
@@ -53,7 +69,14 @@ impl  Serialize for dyn State  {
 pub struct SimulatorState {
 
     vehicles: VecDeque<Box<dyn Vehicle>>,
+    // Slot of each vehicle by id, its index being its slot less that of the
+    // first vehicle. Vehicles keep their slots as others are removed, except
+    // those on the shorter side of the one removed, which shift over by one.
+    vehicle_slots: HashMap<ID, i64>,
+    first_slot: i64,
     pedestrians: VecDeque<Pedestrian>,
+    informal_pedestrians: VecDeque<InformalPedestrian>,
+    signals: Vec<Signal>,
     timestamp: Time
 }
 
@@ -63,20 +86,28 @@ impl Serialize for SimulatorState {
         S: Serializer,
     {
         // Number of fields in the struct and name.
-        let mut state = serializer.serialize_struct("State", 3)?;
+        let mut state = serializer.serialize_struct("State", 5)?;
         state.serialize_field("timestamp", &self.timestamp)?;
         state.serialize_field("pedestrians", &self.get_pedestrians())?;
+        state.serialize_field("informal_pedestrians", &self.informal_pedestrians)?;
         state.serialize_field("vehicles", &self.vehicles)?;
+        state.serialize_field("crossings", &self.signals)?;
         state.end()
     }
 }
 
+impl Default for SimulatorState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl SimulatorState {
 
     // Constructor for the initial state at time 0.
     pub fn new() -> SimulatorState {
 
-        SimulatorState {vehicles: VecDeque::new(), pedestrians: VecDeque::new(), timestamp: 0}
+        SimulatorState {vehicles: VecDeque::new(), vehicle_slots: HashMap::new(), first_slot: 0, pedestrians: VecDeque::new(), informal_pedestrians: VecDeque::new(), signals: Vec::new(), timestamp: 0}
     }
 
     // Construct a state with arbitrary content
@@ -84,7 +115,16 @@ impl SimulatorState {
         pedestrians: VecDeque<Pedestrian>,
         timestamp: Time) -> SimulatorState {
 
-        SimulatorState{vehicles, pedestrians, timestamp}
+        let mut state = SimulatorState{vehicles, vehicle_slots: HashMap::new(), first_slot: 0, pedestrians, informal_pedestrians: VecDeque::new(), signals: Vec::new(), timestamp};
+        state.index_vehicles(0);
+        state
+    }
+
+    // Give the vehicles from the given index on the slots for their indices.
+    fn index_vehicles(&mut self, from: usize) {
+        for (idx, vehicle) in self.vehicles.iter().enumerate().skip(from) {
+            self.vehicle_slots.insert(vehicle.get_id(), self.first_slot + idx as i64);
+        }
     }
 }
 
@@ -109,10 +149,18 @@ impl State  for SimulatorState {
         &self.pedestrians
     }
 
+    fn get_informal_pedestrians(&self) -> &VecDeque<InformalPedestrian> {
+        &self.informal_pedestrians
+    }
+
     fn get_vehicle(&self, idx: usize) -> &dyn Vehicle {
         &*self.get_vehicles()[idx]
     }
 
+    fn vehicle_index(&self, id: ID) -> Option<usize> {
+        self.vehicle_slots.get(&id).map(|slot| (slot - self.first_slot) as usize)
+    }
+
     fn get_mut_vehicle(&mut self, idx: usize) -> &mut dyn Vehicle {
         &mut *self.vehicles[idx]
     }
@@ -125,6 +173,22 @@ impl State  for SimulatorState {
         &mut self.pedestrians[idx]
     }
 
+    fn get_mut_informal_pedestrian(&mut self, idx: usize) -> &mut InformalPedestrian {
+        &mut self.informal_pedestrians[idx]
+    }
+
+    fn get_signals(&self) -> &[Signal] {
+        &self.signals
+    }
+
+    fn get_mut_signal(&mut self, idx: usize) -> &mut Signal {
+        &mut self.signals[idx]
+    }
+
+    fn set_signals(&mut self, signals: Vec<Signal>) {
+        self.signals = signals;
+    }
+
     fn push_pedestrian(&mut self, pedestrian: Pedestrian) -> usize {
         self.pedestrians.push_back(pedestrian);
         self.pedestrians.len() - 1
@@ -138,18 +202,38 @@ impl State  for SimulatorState {
         self.pedestrians.remove(idx);
     }
 
+    fn push_informal_pedestrian(&mut self, pedestrian: InformalPedestrian) -> usize {
+        self.informal_pedestrians.push_back(pedestrian);
+        self.informal_pedestrians.len() - 1
+    }
+
+    fn pop_informal_pedestrian(&mut self, idx: usize) {
+        self.informal_pedestrians.remove(idx);
+    }
+
     fn push_vehicle(&mut self, vehicle: Box<dyn Vehicle>) -> usize {
         self.vehicles.push_back(vehicle);
+        self.index_vehicles(self.vehicles.len() - 1);
         self.vehicles.len() - 1
     }
 
-    // fn pop_vehicle(&mut self, idx: usize) -> Box<dyn Vehicle> {
+    // Remove the vehicle at an index, keeping the order of the others.
     fn pop_vehicle(&mut self, idx: usize) {
-        // todo!()
-        // TODO: should this really be "pop" if it is taking a particular idx
-        // TODO: if it is taking particular idx this breaks order of vector
-        //       or will be very slow. What is intended?
-        self.vehicles.remove(idx);
+        let vehicle = self.vehicles.remove(idx).unwrap();
+        self.vehicle_slots.remove(&vehicle.get_id());
+        // Shift the slots of the vehicles on the shorter side, as the
+        // vehicles themselves are shifted, so a vehicle leaving from the
+        // front (as most do) costs no more than one leaving from the back
+        if idx < self.vehicles.len() - idx {
+            for vehicle in self.vehicles.iter().take(idx) {
+                *self.vehicle_slots.get_mut(&vehicle.get_id()).unwrap() += 1;
+            }
+            self.first_slot += 1;
+        } else {
+            for vehicle in self.vehicles.iter().skip(idx) {
+                *self.vehicle_slots.get_mut(&vehicle.get_id()).unwrap() -= 1;
+            }
+        }
     }
 
 
@@ -170,6 +254,37 @@ mod tests {
         assert_eq!(state.get_pedestrians().len(), 0); // No pedestrians
     }
 
+    #[test]
+    fn test_vehicle_index() {
+        let mut state = SimulatorState::new();
+        for (id, direction) in [(3, Direction::Up), (5, Direction::Down), (7, Direction::Up)] {
+            state.push_vehicle(Box::new(Car::new(id, direction, 13.0, Action::StaticSpeed)));
+        }
+        assert_eq!(state.vehicle_index(5), Some(1));
+
+        // Removing a vehicle moves up those behind it, which are still found by id.
+        state.pop_vehicle(0);
+        assert_eq!(state.vehicle_index(3), None);
+        assert_eq!(state.vehicle_index(5), Some(0));
+        assert_eq!(state.vehicle_index(7), Some(1));
+        assert_eq!(state.get_vehicle(state.vehicle_index(7).unwrap()).get_id(), 7);
+
+        // Removing from the front leaves the slots of the others alone.
+        assert_eq!(state.vehicle_slots[&7], 2);
+        state.push_vehicle(Box::new(Car::new(9, Direction::Down, 13.0, Action::StaticSpeed)));
+        state.push_vehicle(Box::new(Car::new(11, Direction::Up, 13.0, Action::StaticSpeed)));
+
+        // Vehicles are found whichever side of the one removed they are on.
+        state.pop_vehicle(2);
+        state.pop_vehicle(1);
+        let ids: Vec<ID> = state.get_vehicles().iter().map(|vehicle| vehicle.get_id()).collect();
+        assert_eq!(ids, vec![5, 11]);
+        for (idx, id) in ids.into_iter().enumerate() {
+            assert_eq!(state.vehicle_index(id), Some(idx));
+        }
+        assert_eq!(state.vehicle_index(9), None);
+    }
+
     #[test]
     fn test_simulator_state_serialize() {
         // Make test state
@@ -190,13 +305,15 @@ mod tests {
         let ped_vec: Vec<Pedestrian> = vec![ped1, ped2];
         let veh_vec: Vec<Box<dyn Vehicle>> = vec![Box::new(car1), Box::new(car2)];
 
-        // Assign ped_vec and veh_vec to state
+        // Assign ped_vec, veh_vec and signals to state
         test_state.pedestrians = ped_vec.into();
         test_state.vehicles = veh_vec.into();
+        test_state.index_vehicles(0);
+        test_state.signals = vec![Signal::new(Rc::clone(&test_pelican), 0.0, 0)];
 
         let as_json= to_json(&test_state).unwrap();
         println!("{}", &as_json);
-        assert_eq!(&as_json, "{\"timestamp\":0,\"pedestrians\":[{\"id\":1,\"location\":0,\"arrival_time\":0},{\"id\":2,\"location\":0,\"arrival_time\":20}],\"vehicles\":[{\"id\":1,\"length\":4.0,\"buffer_zone\":1.0,\"direction\":\"Up\",\"position\":0.0,\"speed\":13.0,\"acceleration\":3.0},{\"id\":2,\"length\":4.0,\"buffer_zone\":1.0,\"direction\":\"Down\",\"position\":0.0,\"speed\":10.0,\"acceleration\":3.0}]}");
+        assert_eq!(&as_json, "{\"timestamp\":0,\"pedestrians\":[{\"id\":1,\"location\":0,\"arrival_time\":0,\"start_time\":0},{\"id\":2,\"location\":0,\"arrival_time\":20,\"start_time\":20}],\"informal_pedestrians\":[],\"vehicles\":[{\"id\":1,\"kind\":\"car\",\"length\":4.0,\"buffer_zone\":1.0,\"direction\":\"Up\",\"lane\":0,\"position\":0.0,\"speed\":13.0,\"acceleration\":3.0},{\"id\":2,\"kind\":\"car\",\"length\":4.0,\"buffer_zone\":1.0,\"direction\":\"Down\",\"lane\":0,\"position\":0.0,\"speed\":10.0,\"acceleration\":3.0}],\"crossings\":[{\"id\":0,\"kind\":\"Pelican\",\"position\":0.0,\"phase\":\"Green\",\"last_change\":0,\"queue_length\":0}]}");
     }
 
 }
@@ -17,6 +17,7 @@ impl TimeDelta {
 
     pub const fn new(millis: Time) -> TimeDelta { TimeDelta(millis) }
     pub const fn from_secs(secs: Time) -> TimeDelta { TimeDelta(secs * TIME_RESOLUTION) }
+    pub const fn millis(&self) -> Time { self.0 }
 
     pub fn floor(secs: f32) -> TimeDelta {
         TimeDelta(f32::floor(secs * (TIME_RESOLUTION as f32)) as Time)
@@ -0,0 +1,532 @@
+use crate::{ID, Time, Acceleration, Position, Speed};
+use crate::events::{Event, EventType};
+use crate::event_driven_sim::{EventDrivenSim, Hazard};
+use crate::obstacle::Obstacle;
+use crate::road::{Road, Direction};
+use crate::safety::ObstacleKind;
+use crate::simulation::Simulation;
+use crate::state::State;
+use crate::time::TimeDelta;
+use crate::vehicle::Vehicle;
+use std::collections::HashMap;
+use std::fs::File;
+
+// Time (in s) a driver pulling away must be able to go on accelerating for
+// and still stop in time, so as not to stop and start
+const ACCELERATE_HORIZON: f32 = 1.0;
+// Allowance for the rounding of positions and speeds when checking there is
+// room to stop
+const ROOM_TOLERANCE: f32 = 0.001;
+
+// Fixed-time-step simulation. At the start of each step of dt every driver
+// picks an acceleration from what they see ahead, and the vehicles then move
+// at those accelerations for the step. No vehicle event is timed
+// analytically, so the results can be checked against the event-driven
+// simulation, and behaviour with no closed-form event times can be run.
+// Arrivals, pedestrians and signals are those of the event-driven simulation
+// it wraps: their events falling due during a step take effect at its end.
+pub struct TimeSteppedSim {
+    sim: EventDrivenSim,
+    dt: TimeDelta,
+    // Times at which drivers first perceived the hazards ahead of them
+    perceived: HashMap<ID, Vec<(Hazard, Time)>>,
+    // Times from which drivers have been free to speed up
+    clear: HashMap<ID, Time>
+}
+
+// A position a vehicle must be able to stop by, for a hazard, with the
+// headway (in s) it keeps short of it to allow for reacting, to a vehicle
+// ahead not yet braking: how much longer than a step it must be able to keep
+// going before braking. A driver short of the headway brakes normally. The
+// speed is that of the obstacle, if a vehicle.
+#[derive(Copy, Clone, Debug)]
+struct Limit {
+    hazard: Hazard,
+    position: Position,
+    headway: f32,
+    speed: Speed
+}
+
+// Position and speed of a vehicle after keeping an acceleration for a time
+// (in s), staying at rest or at its max speed once it gets there.
+fn motion_after(vehicle: &dyn Vehicle, acceleration: Acceleration, secs: f32) -> (Position, Speed) {
+    let speed = vehicle.get_speed();
+    let moving = if acceleration > 0.0 {
+        f32::min(secs, (vehicle.get_max_speed() - speed) / acceleration)
+    } else if acceleration < 0.0 {
+        f32::min(secs, speed / -acceleration)
+    } else {
+        secs
+    };
+    let end_speed = speed + acceleration * moving;
+    let mut position = vehicle.get_veh_position() + speed * moving + 0.5 * acceleration * moving * moving;
+    if acceleration > 0.0 {
+        position += end_speed * (secs - moving);
+    }
+    (position, end_speed)
+}
+
+// Whether a vehicle could keep an acceleration for a time (in s) and then
+// still brake normally to a stop by a position.
+fn room_to_stop(vehicle: &dyn Vehicle, acceleration: Acceleration, secs: f32, limit: Position) -> bool {
+    let (position, speed) = motion_after(vehicle, acceleration, secs);
+    position + (speed * speed) / (-2.0 * vehicle.get_max_deceleration()) <= limit
+}
+
+// Fastest a vehicle can drive for a step (in s) and still stop short of the
+// limits, keeping its headway, if any: accelerating towards its max speed
+// (for long enough not to stop and start, if pulling away), holding its
+// speed or braking. A vehicle already braking keeps on. None if it cannot
+// brake normally to a stop by them, and must stop in an emergency.
+fn fastest_acceleration<'a>(vehicle: &dyn Vehicle, limits: impl Iterator<Item = &'a Limit> + Clone, secs: f32) -> Option<Acceleration> {
+    let fits = |acceleration: Acceleration, secs: f32, headway: bool| limits.clone().all(|limit| {
+        // Braking to a stop at a limit may overrun it by a rounding error
+        if headway {
+            room_to_stop(vehicle, acceleration, secs + limit.headway, limit.position)
+        } else {
+            room_to_stop(vehicle, acceleration, secs, limit.position + ROOM_TOLERANCE)
+        }
+    });
+    let speed = vehicle.get_speed();
+    let current = vehicle.get_acceleration();
+
+    // Reach the max speed at the end of the step rather than overshoot it
+    let accelerate = f32::min(vehicle.get_max_acceleration(), (vehicle.get_max_speed() - speed) / secs);
+    let horizon = if current > 0.0 { secs } else { f32::max(secs, ACCELERATE_HORIZON) };
+    if accelerate > 0.0 && fits(accelerate, horizon, true) {
+        return Some(accelerate);
+    }
+    // A braking vehicle only eases off once down to the speed of a vehicle
+    // ahead that is not braking, and not to creep on when coming to rest
+    // within the step
+    let braking = vehicle.get_max_deceleration();
+    let following = limits.clone().any(|limit| matches!(limit.hazard, (ObstacleKind::Vehicle, _, false)) && speed <= limit.speed);
+    let easing_off = current >= 0.0 || (following && speed > -braking * secs);
+    if speed == 0.0 || (easing_off && fits(0.0, secs, true)) {
+        return Some(0.0);
+    }
+    if current <= braking {
+        return Some(current);
+    }
+    if fits(braking, secs, false) {
+        return Some(braking);
+    }
+    None
+}
+
+impl TimeSteppedSim {
+
+    pub fn new(sim: EventDrivenSim, dt: TimeDelta) -> Self {
+        assert!(dt > TimeDelta::new(0));
+        TimeSteppedSim { sim, dt, perceived: HashMap::new(), clear: HashMap::new() }
+    }
+
+    pub fn get_sim(&self) -> &EventDrivenSim {
+        &self.sim
+    }
+
+    // End of the step a time falls in, with steps counted from the start
+    // time, or the end time if sooner. A time on a step boundary ends the
+    // step before.
+    fn step_end(&self, time: Time) -> Time {
+        let start_time = self.sim.get_start_time();
+        let dt = self.dt.millis();
+        Time::min(start_time + (time - start_time + dt - 1).div_euclid(dt) * dt, self.sim.get_end_time())
+    }
+
+    // Limits a vehicle must be able to stop by: short of the nearest
+    // pedestrian or red light ahead, and behind the vehicle ahead should it
+    // brake, keeping room to react if it is not yet braking.
+    fn limits(&self, vehicle: &dyn Vehicle) -> Vec<Limit> {
+        let road = self.sim.get_road();
+        let mut limits = Vec::new();
+        if let Some((obstacle, hazard)) = self.sim.next_obstacle(vehicle) {
+            let edge = obstacle.get_position(road, &vehicle.get_direction()) - obstacle.get_obstacle_length();
+            limits.push(Limit { hazard, position: edge - vehicle.get_buffer_zone(), headway: 0.0, speed: 0.0 });
+        }
+        if let Some(leader) = vehicle.next_vehicle(self.sim.state.get_vehicles()) {
+            let speed = leader.get_speed();
+            let stopping = leader.get_acceleration() < 0.0 || speed == 0.0;
+            let mut position = leader.get_veh_position() - leader.get_length() - vehicle.get_buffer_zone();
+            if speed > 0.0 {
+                // The leader brakes at least as hard as the vehicle can
+                let braking = f32::min(leader.get_acceleration(), vehicle.get_max_deceleration());
+                position += (speed * speed) / (-2.0 * braking);
+            }
+            let headway = if stopping { 0.0 } else { vehicle.get_reaction_time().into() };
+            limits.push(Limit { hazard: (ObstacleKind::Vehicle, leader.get_id(), stopping), position, headway, speed });
+        }
+        limits
+    }
+
+    // Each driver picks how to drive for a step of the given length. Drivers
+    // only brake for a hazard once they have had time to react to it, and
+    // only speed up once they have had time to react to the road ahead
+    // clearing, with no hazard they have yet to react to in the way.
+    fn drive(&mut self, step: TimeDelta) {
+        let time = *self.sim.state.timestamp();
+        // Drivers' perceptions of the gaps in the traffic change every step
+        self.sim.reschedule_all();
+        if self.sim.is_car_following() {
+            self.sim.update_car_following();
+            return;
+        }
+        for idx in 0..self.sim.state.get_vehicles().len() {
+            let vehicle = self.sim.state.get_vehicle(idx);
+            if self.sim.lane_change_time(vehicle).is_some_and(|due| due <= time) {
+                self.sim.consider_lane_change(idx);
+            }
+        }
+
+        let secs: f32 = step.into();
+        let mut perceived = HashMap::new();
+        let mut decisions = Vec::new();
+        for (idx, vehicle) in self.sim.state.get_vehicles().iter().enumerate() {
+            let id = vehicle.get_id();
+            let reaction_time = vehicle.get_reaction_time();
+            let mut limits = self.limits(&**vehicle);
+            let earlier = self.perceived.get(&id);
+            let perceived_at = |hazard: &Hazard| earlier
+                .and_then(|hazards| hazards.iter().find(|(perceived, _)| perceived == hazard))
+                .map_or(time, |&(_, time)| time);
+            // The headway kept behind a vehicle ahead that is not braking
+            // already allows for reacting to it braking
+            let reacted = |hazard: &Hazard| matches!(hazard, (ObstacleKind::Vehicle, _, false))
+                || reaction_time + perceived_at(hazard) <= time;
+
+            // A driver yet to react to the vehicle ahead braking keeps the
+            // headway they kept behind it before meanwhile
+            let braking_leader = limits.iter().find(|limit| matches!(limit.hazard, (ObstacleKind::Vehicle, _, true))).copied();
+            if let Some(limit @ Limit { hazard: (_, leader_id, _), .. }) = braking_leader {
+                if !reacted(&limit.hazard) {
+                    let hazard = (ObstacleKind::Vehicle, leader_id, false);
+                    limits.push(Limit { hazard, headway: reaction_time.into(), ..limit });
+                }
+            }
+            let mut hazards = self.sim.hazards_ahead(&**vehicle);
+            for limit in &limits {
+                if !hazards.contains(&limit.hazard) {
+                    hazards.push(limit.hazard);
+                }
+            }
+            let hazards: Vec<(Hazard, Time)> = hazards.into_iter().map(|hazard| (hazard, perceived_at(&hazard))).collect();
+
+            let reacted_limits = limits.iter().filter(|limit| reacted(&limit.hazard));
+            let current = vehicle.get_acceleration();
+            let decision = match fastest_acceleration(&**vehicle, reacted_limits.clone(), secs) {
+                Some(acceleration) if acceleration > current => {
+                    // Easing off or speeding up, as far as any hazard yet to
+                    // be reacted to allows
+                    let acceleration = f32::min(acceleration, fastest_acceleration(&**vehicle, limits.iter(), secs).unwrap_or(current));
+                    if acceleration > 0.0 && acceleration > current {
+                        let clear = *self.clear.entry(id).or_insert(time);
+                        Some(if reaction_time + clear <= time { acceleration } else { current })
+                    } else {
+                        self.clear.remove(&id);
+                        Some(f32::max(acceleration, current))
+                    }
+                }
+                decision => {
+                    self.clear.remove(&id);
+                    decision
+                }
+            };
+            // An emergency stop is for the nearest limit the driver reacted to
+            let cause = reacted_limits
+                .min_by(|x, y| x.position.partial_cmp(&y.position).unwrap())
+                .map(|&Limit { hazard: (kind, obstacle_id, _), .. }| (kind, obstacle_id));
+            perceived.insert(id, hazards);
+            decisions.push((idx, decision, cause));
+        }
+        self.perceived = perceived;
+
+        for (idx, decision, cause) in decisions {
+            match decision {
+                Some(acceleration) => self.sim.state.get_mut_vehicle(idx).set_acceleration(acceleration),
+                None => self.sim.emergency_stop(idx, cause)
+            }
+        }
+    }
+
+    // Bring to a halt the vehicles that came to rest during a step, and take
+    // off the road those that reached its end.
+    fn settle(&mut self) {
+        let exit = self.sim.get_road().get_exit().get_position(self.sim.get_road(), &Direction::Up);
+        let mut exits = Vec::new();
+        for idx in 0..self.sim.state.get_vehicles().len() {
+            let vehicle = self.sim.state.get_vehicle(idx);
+            let id = vehicle.get_id();
+            if vehicle.get_speed() == 0.0 && vehicle.get_acceleration() < 0.0 {
+                if vehicle.is_emergency_stopping() {
+                    self.sim.complete_emergency_stop(id);
+                }
+                self.sim.state.get_mut_vehicle(idx).set_acceleration(0.0);
+            }
+            if self.sim.state.get_vehicle(idx).get_veh_position() >= exit {
+                exits.push(id);
+            }
+        }
+        for id in exits {
+            self.sim.handle_event(EventType::VehicleExit(id));
+            self.perceived.remove(&id);
+            self.clear.remove(&id);
+        }
+    }
+}
+
+impl Simulation for TimeSteppedSim {
+
+    // The events other than those of vehicles falling due in the next step
+    // with any, at its end. Arrivals falling due during an earlier step take
+    // place at once (the event-driven simulation only looks ahead for them).
+    fn next_events(&mut self) -> Vec<Event> {
+        let curr_time = *self.sim.state.timestamp();
+        let (ped_arrival, veh_arrival) = self.sim.next_arrival_times();
+        let mut late_arrivals = Vec::new();
+        if ped_arrival.is_some_and(|time| time < curr_time) {
+            late_arrivals.push(EventType::PedestrianArrival);
+        }
+        if veh_arrival.is_some_and(|time| time < curr_time) {
+            late_arrivals.push(EventType::VehicleArrival);
+        }
+
+        // Vehicles update their car-following accelerations every step
+        let events: Vec<Event> = self.sim.global_events()
+            .into_iter()
+            .filter(|event| !matches!(event.1, EventType::CarFollowingUpdate))
+            .collect();
+        let step_end = if late_arrivals.is_empty() {
+            self.step_end(Time::max(events.iter().min().unwrap().0, curr_time))
+        } else {
+            curr_time
+        };
+        let mut events: Vec<Event> = events
+            .into_iter()
+            .filter(|event| event.0 <= step_end)
+            .map(|event| event.1)
+            .chain(late_arrivals)
+            .map(|event| Event(step_end, event))
+            .collect();
+        events.sort();
+        events
+    }
+
+    // Roll forward a step of dt at a time (the last possibly shorter), with
+    // the drivers deciding afresh at the start of each.
+    fn roll_forward_by(&mut self, time_delta: TimeDelta) {
+        let mut remaining = time_delta.millis();
+        while remaining > 0 {
+            let step = TimeDelta::new(Time::min(remaining, self.dt.millis()));
+            self.drive(step);
+            self.sim.roll_forward_by(step);
+            self.settle();
+            remaining -= step.millis();
+        }
+    }
+
+    fn instantaneous_update(&mut self, event_type: EventType) {
+        self.sim.instantaneous_update(event_type);
+    }
+
+    fn get_state(&self) -> &Box<dyn State> {
+        self.sim.get_state()
+    }
+
+    fn handle_event(&mut self, event: EventType) {
+        self.sim.handle_event(event);
+    }
+
+    fn get_road(&self) -> &Road {
+        self.sim.get_road()
+    }
+
+    fn get_end_time(&self) -> Time {
+        self.sim.get_end_time()
+    }
+
+    fn is_stepping(&self) -> bool {
+        self.sim.is_stepping()
+    }
+
+    // The state is logged after every step.
+    fn time_step(&self) -> Option<TimeDelta> {
+        Some(self.dt)
+    }
+
+    fn open_outfile(&self) -> Option<File> {
+        self.sim.open_outfile()
+    }
+
+    fn report(&self, t: Time, file: &mut Option<File>) {
+        self.sim.report(t, file);
+    }
+
+    fn finish(&self) {
+        self.sim.finish();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+    use crate::{ID, Crossing};
+    use crate::event_driven_sim::RunConfig;
+    use crate::safety::IncidentKind;
+    use crate::state::SimulatorState;
+    use crate::vehicle::{Action, Car, MAX_SPEED};
+    use super::*;
+
+    fn no_arrivals_sim(vehicles: VecDeque<Box<dyn Vehicle>>, road: Road) -> EventDrivenSim {
+        let state = Box::new(SimulatorState::dummy(vehicles, VecDeque::new(), 0));
+        let mut sim = EventDrivenSim::new(RunConfig::no_arrivals(), state, road);
+        sim.set_ped_arrival_times(Vec::new());
+        sim.set_veh_arrival_times(Vec::new());
+        sim
+    }
+
+    // A road with a pelican crossing at 170m whose lights have just turned
+    // red, and cars travelling up it at the given positions.
+    fn red_light_sim(positions: &[Position]) -> EventDrivenSim {
+        let crossing = Crossing::Pelican {
+            id: 0,
+            stop_time: TimeDelta::from_secs(60),
+            wait_time: TimeDelta::from_secs(5),
+            go_time: TimeDelta::from_secs(5),
+            amber_time: TimeDelta::from_secs(3)
+        };
+        let road = Road::new(300.0, vec![(crossing, 170.0)]);
+        let mut vehicles: VecDeque<Box<dyn Vehicle>> = VecDeque::new();
+        for (id, &position) in positions.iter().enumerate() {
+            let mut car = Car::new(id as ID, Direction::Up, MAX_SPEED, Action::StaticSpeed);
+            car.set_position(position);
+            vehicles.push_front(Box::new(car));
+        }
+        let mut sim = no_arrivals_sim(vehicles, road);
+        sim.instantaneous_update(EventType::LightsToAmber(0));
+        sim.instantaneous_update(EventType::LightsToRed(0));
+        sim
+    }
+
+    // Step a simulation until the given time, handling its events.
+    fn run_until(sim: &mut dyn Simulation, end_time: Time) {
+        while *sim.get_state().timestamp() < end_time {
+            let events = sim.next_events();
+            let time = events[0].0;
+            sim.roll_forward_by(TimeDelta::new(time - *sim.get_state().timestamp()));
+            for event in events {
+                sim.instantaneous_update(event.1);
+            }
+        }
+    }
+
+    #[test]
+    fn test_events_at_step_end() {
+        // Three pedestrians arrive at a zebra within the same step.
+        let road = Road::new(100.0, vec![(Crossing::zebra(0), 50.0)]);
+        let mut sim = no_arrivals_sim(VecDeque::new(), road);
+        sim.set_ped_arrival_times(vec![1050, 1060, 1070]);
+        let mut sim = TimeSteppedSim::new(sim, TimeDelta::new(100));
+        assert_eq!(sim.step_end(1050), 1100);
+        assert_eq!(sim.step_end(1100), 1100);
+
+        // All of them arrive at the end of the step.
+        while sim.get_state().get_pedestrians().len() < 3 {
+            let events = sim.next_events();
+            assert!(events.iter().all(|event| event.0 == 1100));
+            sim.roll_forward_by(TimeDelta::new(1100 - *sim.get_state().timestamp()));
+            for event in events {
+                sim.instantaneous_update(event.1);
+            }
+        }
+        assert_eq!(*sim.get_state().timestamp(), 1100);
+        assert_eq!(sim.get_sim().next_arrival_times(), (None, None));
+    }
+
+    #[test]
+    fn test_vehicle_exits_within_step() {
+        // A vehicle reaching the end of the road during a step leaves then.
+        let mut car = Car::new(0 as ID, Direction::Up, MAX_SPEED, Action::StaticSpeed);
+        car.set_position(95.0);
+        let mut vehicles: VecDeque<Box<dyn Vehicle>> = VecDeque::new();
+        vehicles.push_back(Box::new(car));
+        let sim = no_arrivals_sim(vehicles, Road::new(100.0, Vec::new()));
+        let mut sim = TimeSteppedSim::new(sim, TimeDelta::new(1000));
+
+        sim.roll_forward_by(TimeDelta::new(1000));
+        assert!(sim.get_state().get_vehicles().is_empty());
+    }
+
+    #[test]
+    fn test_stop_at_red_light() {
+        // A car approaching a red light at full speed stops short of it, its
+        // buffer zone back from the 1m of the lights, braking normally. The
+        // driver brakes at the last step they can, so smaller steps stop
+        // closer to the lights.
+        let limit = 170.0 - 1.0 - 1.0;
+        for (dt, slack) in [(10, 0.2), (100, 1.5), (250, 3.5)] {
+            let mut sim = TimeSteppedSim::new(red_light_sim(&[100.0]), TimeDelta::new(dt));
+            run_until(&mut sim, 30_000);
+            let car = sim.get_state().get_vehicle(0);
+            assert_eq!(car.get_speed(), 0.0);
+            assert!(car.get_veh_position() <= limit);
+            assert!(car.get_veh_position() > limit - slack);
+            assert!(sim.get_sim().get_emergency_stops().is_empty());
+        }
+    }
+
+    #[test]
+    fn test_queue_at_red_light() {
+        // A car following another to a red light stops its buffer zone back
+        // from the rear of the one in front, without an emergency stop.
+        let mut sim = TimeSteppedSim::new(red_light_sim(&[80.0, 100.0]), TimeDelta::new(100));
+        run_until(&mut sim, 30_000);
+        let vehicles = sim.get_state().get_vehicles();
+        assert!(vehicles.iter().all(|vehicle| vehicle.get_speed() == 0.0));
+        let gap = vehicles[0].get_veh_position() - vehicles[0].get_length() - vehicles[1].get_veh_position();
+        assert!((1.0..2.5).contains(&gap));
+        assert!(sim.get_sim().get_emergency_stops().is_empty());
+    }
+
+    #[test]
+    fn test_matches_event_driven() {
+        // The example with every behaviour model on, run by each simulation
+        // on the same arrivals. The behaviour is chaotic, so mean pedestrian
+        // delays are compared across several runs.
+        let config = crate::get_zebra_config_option(Some(&"examples/full.toml".to_string()));
+        let run = |seed: u64, dt: Option<TimeDelta>| {
+            let run = RunConfig {
+                seed,
+                start_time: 0,
+                end_time: config.simulation.run_time,
+                ped_arrival_rate: config.simulation.pedestrian_arrival_rate,
+                veh_arrival_rate: config.simulation.vehicle_arrival_rate
+            };
+            let mut sim = EventDrivenSim::new(run, Box::new(SimulatorState::new()), Road::from_config(&config));
+            sim.configure(&config);
+            let sim = match dt {
+                Some(dt) => {
+                    let mut sim = TimeSteppedSim::new(sim, dt);
+                    sim.run();
+                    sim.sim
+                }
+                None => {
+                    let mut sim = sim;
+                    sim.run();
+                    sim
+                }
+            };
+            assert!(sim.get_incidents().iter().all(|incident| incident.kind != IncidentKind::Collision));
+            (sim.mean_pedestrian_delay(), sim.get_pedestrian_delays().len() as f32)
+        };
+
+        let (mut event_driven, mut time_stepped) = ((0.0, 0.0), (0.0, 0.0));
+        for seed in 0..4 {
+            let (delay, crossed) = run(seed, None);
+            event_driven = (event_driven.0 + delay, event_driven.1 + crossed);
+            let (delay, crossed) = run(seed, Some(TimeDelta::new(100)));
+            time_stepped = (time_stepped.0 + delay, time_stepped.1 + crossed);
+        }
+        assert!((time_stepped.0 / event_driven.0 - 1.0).abs() < 0.25);
+        assert!((time_stepped.1 / event_driven.1 - 1.0).abs() < 0.05);
+    }
+}
@@ -0,0 +1,224 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use serde::{Serialize, Deserialize};
+
+use crate::{ID, Time};
+use crate::road::Direction;
+
+#[derive(Copy, Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub enum AgentType {
+    Pedestrian,
+    Vehicle
+}
+
+// An observed arrival: the time (in ms) an agent arrived, the direction of
+// travel of a vehicle and the crossing used by a pedestrian. A pedestrian
+// without a crossing is sent to one at random.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+#[serde(try_from = "TraceRecord")]
+pub struct TraceEvent {
+    pub time: Time,
+    pub agent: AgentType,
+    #[serde(default)]
+    pub direction: Option<Direction>,
+    #[serde(default)]
+    pub crossing: Option<ID>
+}
+
+// An event as written in a JSON trace, before checking that a vehicle has a
+// direction.
+#[derive(Deserialize)]
+struct TraceRecord {
+    time: Time,
+    agent: AgentType,
+    #[serde(default)]
+    direction: Option<Direction>,
+    #[serde(default)]
+    crossing: Option<ID>
+}
+
+impl TryFrom<TraceRecord> for TraceEvent {
+    type Error = String;
+
+    fn try_from(record: TraceRecord) -> Result<TraceEvent, String> {
+        if record.agent == AgentType::Vehicle && record.direction.is_none() {
+            return Err(format!("vehicle arriving at {} has no direction", record.time));
+        }
+        Ok(TraceEvent { time: record.time, agent: record.agent, direction: record.direction, crossing: record.crossing })
+    }
+}
+
+// A trace that cannot be read, or a malformed line in it (counting from one,
+// including the header of a CSV trace).
+#[derive(Debug)]
+pub enum TraceError {
+    Read(io::Error),
+    Invalid { line: usize, message: String }
+}
+
+impl fmt::Display for TraceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TraceError::Read(err) => write!(f, "cannot read trace: {}", err),
+            TraceError::Invalid { line, message } => write!(f, "line {}: {}", line, message)
+        }
+    }
+}
+
+impl std::error::Error for TraceError {}
+
+// Recorded arrivals, e.g. from a site survey, to be replayed in place of
+// randomly generated ones.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Trace {
+    events: Vec<TraceEvent>
+}
+
+impl Trace {
+    pub fn new(mut events: Vec<TraceEvent>) -> Trace {
+        for event in &events {
+            assert!(event.agent == AgentType::Pedestrian || event.direction.is_some(),
+                "vehicle arriving at {} has no direction", event.time);
+        }
+        events.sort_by_key(|event| event.time);
+        Trace { events }
+    }
+
+    // Load a trace from a JSON file (a list of events) or, for any other
+    // extension, a CSV file with a header row naming the columns.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Trace, TraceError> {
+        let path = path.as_ref();
+        let data = fs::read_to_string(path).map_err(TraceError::Read)?;
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => Trace::from_json(&data),
+            _ => Trace::from_csv(&data)
+        }
+    }
+
+    pub fn from_json(data: &str) -> Result<Trace, TraceError> {
+        match serde_json::from_str(data) {
+            Ok(events) => Ok(Trace::new(events)),
+            Err(err) => {
+                // Drop the position serde_json appends, as the line is reported separately.
+                let message = err.to_string();
+                let message = message.rsplit_once(" at line ").map_or(message.as_str(), |(message, _)| message);
+                Err(TraceError::Invalid { line: err.line(), message: message.to_string() })
+            }
+        }
+    }
+
+    // Columns are `time`, `agent`, `direction` and `crossing`, in any order.
+    // The last two may be left empty.
+    pub fn from_csv(data: &str) -> Result<Trace, TraceError> {
+        let mut lines = data.lines().enumerate()
+            .map(|(i, line)| (i + 1, line))
+            .filter(|(_, line)| !line.trim().is_empty());
+        let invalid = |line: usize, message: String| TraceError::Invalid { line, message };
+        let (header_line, header) = lines.next().ok_or_else(|| invalid(1, "empty trace".to_string()))?;
+        let header: Vec<&str> = header.split(',').map(str::trim).collect();
+        let column = |name: &str| header.iter().position(|&col| col == name);
+        let time_col = column("time").ok_or_else(|| invalid(header_line, "no time column".to_string()))?;
+        let agent_col = column("agent").ok_or_else(|| invalid(header_line, "no agent column".to_string()))?;
+        let direction_col = column("direction");
+        let crossing_col = column("crossing");
+
+        let events = lines.map(|(line, text)| {
+            let fields: Vec<&str> = text.split(',').map(str::trim).collect();
+            let field = |col: Option<usize>| col.and_then(|i| fields.get(i)).copied().filter(|s| !s.is_empty());
+            let time = field(Some(time_col)).and_then(|time| time.parse().ok())
+                .ok_or_else(|| invalid(line, format!("invalid time: {}", text)))?;
+            let agent = match field(Some(agent_col)) {
+                Some("Pedestrian") => AgentType::Pedestrian,
+                Some("Vehicle") => AgentType::Vehicle,
+                other => return Err(invalid(line, format!("unknown agent: {}", other.unwrap_or(""))))
+            };
+            let direction = match field(direction_col) {
+                Some("Up") => Some(Direction::Up),
+                Some("Down") => Some(Direction::Down),
+                Some(other) => return Err(invalid(line, format!("unknown direction: {}", other))),
+                None if agent == AgentType::Vehicle => return Err(invalid(line, format!("vehicle arriving at {} has no direction", time))),
+                None => None
+            };
+            let crossing = match field(crossing_col) {
+                Some(crossing) => Some(crossing.parse().map_err(|_| invalid(line, format!("invalid crossing: {}", text)))?),
+                None => None
+            };
+            Ok(TraceEvent { time, agent, direction, crossing })
+        }).collect::<Result<_, _>>()?;
+        Ok(Trace::new(events))
+    }
+
+    // Arrival times of the pedestrians, with the crossing each used.
+    pub fn pedestrian_arrivals(&self) -> Vec<(Time, Option<ID>)> {
+        self.events.iter()
+            .filter(|event| event.agent == AgentType::Pedestrian)
+            .map(|event| (event.time, event.crossing))
+            .collect()
+    }
+
+    // Arrival times of the vehicles, with their direction of travel.
+    pub fn vehicle_arrivals(&self) -> Vec<(Time, Direction)> {
+        self.events.iter()
+            .filter(|event| event.agent == AgentType::Vehicle)
+            .filter_map(|event| event.direction.map(|direction| (event.time, direction)))
+            .collect()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trace_from_csv() {
+        let trace = Trace::from_csv("time,agent,direction,crossing\n\
+            3000,Vehicle,Down,\n\
+            1000,Pedestrian,,1\n\
+            2000,Vehicle,Up,\n\
+            4000,Pedestrian,,\n").unwrap();
+        assert_eq!(trace.pedestrian_arrivals(), vec![(1000, Some(1)), (4000, None)]);
+        assert_eq!(trace.vehicle_arrivals(), vec![(2000, Direction::Up), (3000, Direction::Down)]);
+    }
+
+    #[test]
+    fn test_trace_from_json() {
+        let trace = Trace::from_json(r#"[
+            {"time": 2000, "agent": "Vehicle", "direction": "Up"},
+            {"time": 1000, "agent": "Pedestrian", "crossing": 0}
+        ]"#).unwrap();
+        assert_eq!(trace, Trace::from_csv("agent,time,crossing,direction\nPedestrian,1000,0,\nVehicle,2000,,Up").unwrap());
+    }
+
+    #[test]
+    fn test_vehicle_needs_direction() {
+        let err = Trace::from_csv("time,agent\n1000,Vehicle\n").unwrap_err();
+        assert_eq!(err.to_string(), "line 2: vehicle arriving at 1000 has no direction");
+        match Trace::from_json(r#"[{"time": 1000, "agent": "Vehicle"}]"#) {
+            Err(TraceError::Invalid { message, .. }) => assert_eq!(message, "vehicle arriving at 1000 has no direction"),
+            result => panic!("expected an invalid trace, got {:?}", result)
+        }
+    }
+
+    #[test]
+    fn test_malformed_trace() {
+        let error_line = |data: &str| match Trace::from_csv(data) {
+            Err(TraceError::Invalid { line, .. }) => line,
+            result => panic!("expected an invalid trace, got {:?}", result)
+        };
+        assert_eq!(error_line(""), 1);
+        assert_eq!(error_line("agent,direction\nVehicle,Up\n"), 1);
+        assert_eq!(error_line("time,agent\n1000,Pedestrian\n\nsoon,Pedestrian\n"), 4);
+        assert_eq!(error_line("time,agent\n1000,Cyclist\n"), 2);
+        assert_eq!(error_line("time,agent,direction\n1000,Vehicle,Left\n"), 2);
+        assert_eq!(error_line("time,agent,crossing\n1000,Pedestrian,first\n"), 2);
+        assert_eq!(error_line("time,agent\n1000\n"), 2);
+
+        let err = Trace::from_json("[\n{\"time\": 1000}\n]").unwrap_err();
+        assert_eq!(err.to_string(), "line 2: missing field `agent`");
+        assert!(matches!(Trace::from_file("no_such_trace.csv"), Err(TraceError::Read(_))));
+    }
+}
@@ -1,9 +1,11 @@
-use serde::ser::{Serialize, Serializer, SerializeStruct};
+use serde::{Serialize, Deserialize};
+use serde::ser::{Serializer, SerializeStruct};
 use serde_json::to_string as to_json;
 use std::collections::VecDeque;
 
 use crate::pedestrian::Pedestrian;
-use crate::{Time, ID};
+use crate::signal::Signal;
+use crate::{Time, ID, Length, Speed, Acceleration};
 use crate::time::TimeDelta;
 use crate::time::TIME_RESOLUTION;
 use crate::road::Crossing;
@@ -18,29 +20,83 @@ use std::rc::Rc;
 pub const MAX_SPEED: f32 = 13.41;
 pub const ACCELERATION_VALUE: f32 = 3.0;
 pub const DECCELERATION_VALUE: f32 = -4.0;
+pub const EMERGENCY_DECCELERATION_VALUE: f32 = -8.0;
 
 const ROUNDING: f32 = 1000.0;
 
+// Kinds of road vehicle, each with its own dimensions and performance.
+#[derive(Copy, Clone, Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum VehicleKind {
+    Car,
+    Van,
+    Bus,
+    Hgv,
+    Bicycle,
+    Motorbike
+}
+
+// Dimensions and performance of a vehicle. Decelerations are negative.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct VehicleSpec {
+    pub kind: VehicleKind,
+    pub length: Length,
+    pub buffer_zone: Length,
+    pub max_speed: Speed,
+    pub max_acceleration: Acceleration,
+    pub max_deceleration: Acceleration,
+    pub max_emergency_deceleration: Acceleration
+}
+
+impl VehicleKind {
+    // Default spec for the kind of vehicle.
+    pub fn spec(&self) -> VehicleSpec {
+        let (length, buffer_zone, max_speed, max_acceleration, max_deceleration, max_emergency_deceleration) = match self {
+            VehicleKind::Car => (4.0, 1.0, MAX_SPEED, ACCELERATION_VALUE, DECCELERATION_VALUE, EMERGENCY_DECCELERATION_VALUE),
+            VehicleKind::Van => (5.5, 1.0, MAX_SPEED, 2.5, DECCELERATION_VALUE, -7.0),
+            VehicleKind::Bus => (12.0, 1.5, MAX_SPEED, 1.5, -3.5, -5.0),
+            VehicleKind::Hgv => (16.5, 2.0, MAX_SPEED, 1.0, -3.0, -5.0),
+            VehicleKind::Bicycle => (1.8, 0.5, 6.0, 1.0, -3.0, -5.0),
+            VehicleKind::Motorbike => (2.2, 1.0, MAX_SPEED, 4.0, -5.0, -7.0)
+        };
+        VehicleSpec { kind: *self, length, buffer_zone, max_speed, max_acceleration, max_deceleration, max_emergency_deceleration }
+    }
+}
+
 #[derive(Copy,Clone)]
 pub enum Action {
     Accelerate,
     Deccelerate,
+    EmergencyStop,
     StaticSpeed
 }
 
 pub trait Vehicle : Obstacle {
     fn get_id(&self) -> ID;
     fn set_id(&mut self, id: ID);
+    fn get_kind(&self) -> VehicleKind;
     fn get_length(&self) -> f32;
     fn get_buffer_zone(&self) -> f32;
+    fn get_max_speed(&self) -> f32;
+    fn get_max_acceleration(&self) -> f32;
+    fn get_max_deceleration(&self) -> f32;
+    fn get_max_emergency_deceleration(&self) -> f32;
+    // Whether the vehicle has been braking in an emergency since its last action.
+    fn is_emergency_stopping(&self) -> bool;
     fn get_direction(&self) -> Direction;
+    fn get_lane(&self) -> usize;
+    fn set_lane(&mut self, lane: usize);
+    fn get_reaction_time(&self) -> TimeDelta;
+    fn set_reaction_time(&mut self, reaction_time: TimeDelta);
     fn get_veh_position(&self) -> f32;
     fn set_position(&mut self, pos: f32);
     fn set_speed(&mut self, speed: f32);
+    fn set_acceleration(&mut self, acceleration: f32);
     fn action(&mut self, action:Action);
     fn roll_forward_by(&mut self, duration: TimeDelta);
     fn next_crossing<'a>(&'a self, road: &'a Road) -> Option<(&Rc<Crossing>, &f32)>;
     fn next_pedestrian<'a>(&'a self, road: &'a Road, peds: &'a VecDeque<Pedestrian>, time: Time) -> Option<&Pedestrian>;
+    fn next_signal<'a>(&self, road: &Road, signals: &'a [Signal], time: Time) -> Option<&'a Signal>;
     fn relative_speed(&self, obstacle: &dyn Obstacle) -> f32;
     fn relative_position(&self, obstacle: &dyn Obstacle, road: &Road) -> f32;
     fn relative_veh_position(&self, vehicle: &dyn Vehicle) -> f32;
@@ -54,11 +110,13 @@ impl Serialize for dyn Vehicle {
         S: Serializer,
     {
         // Number of fields in the struct and name.
-        let mut state = serializer.serialize_struct("Car", 7)?;
+        let mut state = serializer.serialize_struct("Car", 9)?;
         state.serialize_field("id", &self.get_id())?;
+        state.serialize_field("kind", &self.get_kind())?;
         state.serialize_field("length", &self.get_length())?;
         state.serialize_field("buffer_zone", &self.get_buffer_zone())?;
         state.serialize_field("direction", &self.get_direction())?;
+        state.serialize_field("lane", &self.get_lane())?;
         state.serialize_field("position", &self.get_veh_position())?;
         state.serialize_field("speed", &self.get_speed())?;
         state.serialize_field("acceleration", &self.get_acceleration())?;
@@ -69,24 +127,54 @@ impl Serialize for dyn Vehicle {
 #[derive(Debug)]
 pub struct Car {
     id: ID,
+    kind: VehicleKind,
     length: f32,
     buffer_zone: f32,
+    max_speed: f32,
+    max_acceleration: f32,
+    max_deceleration: f32,
+    max_emergency_deceleration: f32,
+    emergency_stopping: bool,
     direction: Direction,
+    // Lane within the direction of travel, from the nearside lane 0
+    lane: usize,
     position: f32,
     speed: f32,
     acceleration: f32,
+    reaction_time: TimeDelta,
 }
 
+// Vehicles with the built-in spec of their kind, for tests. Simulations
+// build theirs from the spec in the config.
+#[cfg(test)]
 impl Car {
     pub fn new(id: ID, direction: Direction, speed: f32, action: Action) -> Car {
+        Car::of_kind(VehicleKind::Car, id, direction, speed, action)
+    }
+
+    pub fn of_kind(kind: VehicleKind, id: ID, direction: Direction, speed: f32, action: Action) -> Car {
+        Car::from_spec(&kind.spec(), id, direction, speed, action)
+    }
+}
+
+impl Car {
+    pub fn from_spec(spec: &VehicleSpec, id: ID, direction: Direction, speed: f32, action: Action) -> Car {
 	let mut car = Car {
-	        id: id,
+	        id,
+            kind: spec.kind,
 	        position: 0.0f32,
-            length: 4.0f32,
-            buffer_zone: 1.0f32,
+            length: spec.length,
+            buffer_zone: spec.buffer_zone,
+            max_speed: spec.max_speed,
+            max_acceleration: spec.max_acceleration,
+            max_deceleration: spec.max_deceleration,
+            max_emergency_deceleration: spec.max_emergency_deceleration,
+            emergency_stopping: false,
             direction,
+            lane: 0,
             speed,
             acceleration: 0.0f32,
+            reaction_time: TimeDelta::new(0),
         };
 
         car.action(action);
@@ -135,17 +223,51 @@ impl Vehicle for Car {
     fn get_id(&self) -> ID {
         self.id
     }
+    fn get_kind(&self) -> VehicleKind {
+        self.kind
+    }
     fn get_length(&self) -> f32 {
        self.length
     }
     fn get_buffer_zone(&self) -> f32 {
         self.buffer_zone
     }
+    fn get_max_speed(&self) -> f32 {
+        self.max_speed
+    }
+    fn get_max_acceleration(&self) -> f32 {
+        self.max_acceleration
+    }
+    fn get_max_deceleration(&self) -> f32 {
+        self.max_deceleration
+    }
+    fn get_max_emergency_deceleration(&self) -> f32 {
+        self.max_emergency_deceleration
+    }
+    fn is_emergency_stopping(&self) -> bool {
+        self.emergency_stopping
+    }
 
     fn get_direction(&self) -> Direction {
         self.direction
     }
 
+    fn get_lane(&self) -> usize {
+        self.lane
+    }
+
+    fn set_lane(&mut self, lane: usize) {
+        self.lane = lane;
+    }
+
+    fn get_reaction_time(&self) -> TimeDelta {
+        self.reaction_time
+    }
+
+    fn set_reaction_time(&mut self, reaction_time: TimeDelta) {
+        self.reaction_time = reaction_time;
+    }
+
     fn get_veh_position(&self) -> f32 {
         self.position
     }
@@ -158,50 +280,60 @@ impl Vehicle for Car {
         self.speed = speed;
     }
 
+    fn set_acceleration(&mut self, acceleration: f32) {
+        self.acceleration = acceleration;
+        self.emergency_stopping &= acceleration < self.max_deceleration;
+    }
+
 
     fn action(&mut self, action:Action) {
         match action {
-            Action::Accelerate  => self.acceleration = ACCELERATION_VALUE,
-            Action::Deccelerate => self.acceleration = DECCELERATION_VALUE,
+            Action::Accelerate  => self.acceleration = self.max_acceleration,
+            Action::Deccelerate => self.acceleration = self.max_deceleration,
+            Action::EmergencyStop => self.acceleration = self.max_emergency_deceleration,
             Action::StaticSpeed => self.acceleration = 0.0
         };
+        self.emergency_stopping = matches!(action, Action::EmergencyStop);
     }
 
     fn roll_forward_by(&mut self, time_delta: TimeDelta) {
 
         let seconds: f32 = time_delta.into();
 
+        // A braking vehicle comes to rest rather than reversing
+        let moving = if self.acceleration < 0.0 {
+            f32::min(seconds, self.speed / -self.acceleration)
+        } else {
+            seconds
+        };
+
         // Update the vehicle's position with rounding
         self.position = ((
-            self.position + self.speed * seconds + (0.5 * self.acceleration * seconds * seconds)
+            self.position + self.speed * moving + (0.5 * self.acceleration * moving * moving)
         )*ROUNDING).round()/ROUNDING;
 
-        // Update the vehicle's speed with: rounding and min=0, max=MAX_SPEED
-        self.speed = f32::min(f32::max(((self.speed + self.acceleration * seconds) * ROUNDING).round()/ROUNDING, 0.0), MAX_SPEED);
+        // Update the vehicle's speed with: rounding and min=0, max=max_speed
+        self.speed = f32::min(f32::max(((self.speed + self.acceleration * seconds) * ROUNDING).round()/ROUNDING, 0.0), self.max_speed);
 
-        assert!(self.speed <= MAX_SPEED);
+        assert!(self.speed <= self.max_speed);
         assert!(self.speed >= 0.0);
     }
 
     fn relative_position(&self, obstacle: &dyn Obstacle, road: &Road)-> f32 {
 
         // Negative relative_position means obstacle in front of car
+        // Positive if the vehicle has reached it, as in a collision
         let relative_position: f32 = &self.get_veh_position() - obstacle.get_position(road, &self.get_direction());
 
-        // We should never need to have a positive relative position (looking behind)
-        assert!(relative_position <= 0.0);
-
         relative_position
     }
 
     fn relative_veh_position(&self, vehicle: &dyn Vehicle) -> f32 {
 
         // Negative relative_position means obstacle in front of car
+        // Positive if the vehicle has reached it, as in a collision
         let relative_position: f32 = &self.get_veh_position() - vehicle.get_veh_position();
 
-        // We should never need to have a positive relative position (looking behind)
-        assert!(relative_position <= 0.0);
-
         relative_position
     }
 
@@ -264,26 +396,35 @@ impl Vehicle for Car {
         }
         next_ped
     }
-    fn next_vehicle<'a>(&self, vehicles: &'a VecDeque<Box<dyn Vehicle>>) -> Option<&'a Box<dyn Vehicle>> {
+    fn next_signal<'a>(&self, road: &Road, signals: &'a [Signal], time: Time) -> Option<&'a Signal> {
         let my_direction = &self.get_direction();
-        if vehicles.len() == 0 {
-            return Option::None
-        }
-        for vehicle in vehicles.into_iter().rev() {
-            // Ignore vehicles going in the other direction OR are the same vehicle
-            if vehicle.get_direction() != *my_direction || vehicle.get_id() == self.get_id() {
+        let mut next_signal: Option<&Signal> = None;
+        for signal in signals {
+            // Only lights ahead of the vehicle that stop it are of interest.
+            if !signal.stops(self.get_id(), time) {
                 continue;
             }
-
-            // This assumes vehicles are ordered by increasing position.
-            if &vehicle.get_veh_position() < &self.get_veh_position() {
+            let pos = signal.get_position(road, my_direction);
+            if self.get_veh_position() >= pos {
                 continue;
             }
-            return Option::Some(vehicle)
+            match next_signal {
+                Some(current) if current.get_position(road, my_direction) <= pos => (),
+                _ => next_signal = Some(signal)
+            }
         }
+        next_signal
+    }
 
-        // If this vehicle is in front of all the others, return None.
-        Option::None
+    fn next_vehicle<'a>(&self, vehicles: &'a VecDeque<Box<dyn Vehicle>>) -> Option<&'a Box<dyn Vehicle>> {
+        // Vehicles overtake by changing lane, so are not necessarily in order
+        // of position: take the nearest ahead in the same lane.
+        vehicles.iter()
+            .filter(|vehicle| vehicle.get_direction() == self.get_direction()
+                && vehicle.get_lane() == self.get_lane()
+                && vehicle.get_id() != self.get_id()
+                && vehicle.get_veh_position() >= self.get_veh_position())
+            .min_by(|x, y| x.get_veh_position().partial_cmp(&y.get_veh_position()).unwrap())
     }
 }
 
@@ -293,11 +434,13 @@ impl Serialize for Car {
         S: Serializer,
     {
         // Number of fields in the struct and name.
-        let mut state = serializer.serialize_struct("Car", 7)?;
+        let mut state = serializer.serialize_struct("Car", 9)?;
         state.serialize_field("id", &self.get_id())?;
+        state.serialize_field("kind", &self.get_kind())?;
         state.serialize_field("length", &self.get_length())?;
         state.serialize_field("buffer_zone", &self.get_buffer_zone())?;
         state.serialize_field("direction", &self.get_direction())?;
+        state.serialize_field("lane", &self.get_lane())?;
         state.serialize_field("position", &self.get_veh_position())?;
         state.serialize_field("speed", &self.get_speed())?;
         state.serialize_field("acceleration", &self.get_acceleration())?;
@@ -340,7 +483,59 @@ mod tests {
         let test_car = Car::new(1, Direction::Up, 13.0,Action::Accelerate);
         let as_json= to_json(&test_car).unwrap();
         // println!("{}", &as_json);
-        assert_eq!(&as_json, "{\"id\":1,\"length\":4.0,\"buffer_zone\":1.0,\"direction\":\"Up\",\"position\":0.0,\"speed\":13.0,\"acceleration\":3.0}");
+        assert_eq!(&as_json, "{\"id\":1,\"kind\":\"car\",\"length\":4.0,\"buffer_zone\":1.0,\"direction\":\"Up\",\"lane\":0,\"position\":0.0,\"speed\":13.0,\"acceleration\":3.0}");
+    }
+
+    #[test]
+    fn test_serialize_vehicle_kind(){
+        let test_bus = Car::of_kind(VehicleKind::Bus, 2, Direction::Down, 10.0, Action::StaticSpeed);
+        let as_json = to_json(&test_bus).unwrap();
+        assert_eq!(&as_json, "{\"id\":2,\"kind\":\"bus\",\"length\":12.0,\"buffer_zone\":1.5,\"direction\":\"Down\",\"lane\":0,\"position\":0.0,\"speed\":10.0,\"acceleration\":0.0}");
+    }
+
+    #[test]
+    fn test_vehicle_kind_performance(){
+        // Each kind accelerates and brakes at its own rate, up to its own speed limit.
+        let mut test_bicycle = Car::of_kind(VehicleKind::Bicycle, 0, Direction::Up, 5.5, Action::Accelerate);
+        assert_eq!(test_bicycle.get_acceleration(), VehicleKind::Bicycle.spec().max_acceleration);
+        test_bicycle.roll_forward_by(TimeDelta::new(1000));
+        assert_eq!(test_bicycle.get_speed(), test_bicycle.get_max_speed());
+
+        let mut test_hgv = Car::of_kind(VehicleKind::Hgv, 1, Direction::Up, MAX_SPEED, Action::Deccelerate);
+        assert_eq!(test_hgv.get_acceleration(), test_hgv.get_max_deceleration());
+        test_hgv.roll_forward_by(TimeDelta::new(1000));
+        assert_eq!(test_hgv.get_speed(), MAX_SPEED - 3.0);
+    }
+
+    #[test]
+    fn test_emergency_stop_action(){
+        let mut test_bus = Car::of_kind(VehicleKind::Bus, 0, Direction::Up, 10.0, Action::StaticSpeed);
+        assert!(!test_bus.is_emergency_stopping());
+        test_bus.action(Action::EmergencyStop);
+        assert!(test_bus.is_emergency_stopping());
+        assert_eq!(test_bus.get_acceleration(), test_bus.get_max_emergency_deceleration());
+        assert_eq!(test_bus.get_max_emergency_deceleration(), VehicleKind::Bus.spec().max_emergency_deceleration);
+
+        // Braking harder than normal, as set by a car-following model, is
+        // still an emergency, until the vehicle takes another action.
+        test_bus.set_acceleration(-4.0);
+        assert!(test_bus.is_emergency_stopping());
+        test_bus.set_acceleration(test_bus.get_max_deceleration());
+        assert!(!test_bus.is_emergency_stopping());
+        test_bus.action(Action::EmergencyStop);
+        test_bus.action(Action::StaticSpeed);
+        assert!(!test_bus.is_emergency_stopping());
+    }
+
+    #[test]
+    fn test_car_from_spec(){
+        let spec = VehicleSpec { max_speed: 10.0, max_deceleration: -2.0, ..VehicleKind::Van.spec() };
+        let mut test_van = Car::from_spec(&spec, 0, Direction::Up, 10.0, Action::Deccelerate);
+        assert_eq!(test_van.get_kind(), VehicleKind::Van);
+        assert_eq!(test_van.get_max_speed(), 10.0);
+        assert_eq!(test_van.get_acceleration(), -2.0);
+        test_van.roll_forward_by(TimeDelta::new(1000));
+        assert_eq!(test_van.get_speed(), 8.0);
     }
 
     #[test]
@@ -370,13 +565,43 @@ mod tests {
         spawn_car_take_action(Action::Accelerate, 0.0);
     }
 
-    // IMP TODO: Test next_vehicle()
+    #[test]
+    fn test_next_vehicle_in_lane(){
+        // Vehicle 2 has overtaken vehicle 0, so is ahead despite arriving later.
+        let mut vehicles: VecDeque<Box<dyn Vehicle>> = VecDeque::new();
+        for (id, lane, position) in [(0, 0, 20.0), (1, 1, 40.0), (2, 0, 60.0)] {
+            let mut car = Car::new(id, Direction::Up, 10.0, Action::StaticSpeed);
+            car.set_lane(lane);
+            car.set_position(position);
+            vehicles.push_back(Box::new(car));
+        }
+
+        // Only vehicles ahead in the same lane lead.
+        let mut car = Car::new(3, Direction::Up, 10.0, Action::StaticSpeed);
+        assert_eq!(car.next_vehicle(&vehicles).unwrap().get_id(), 0);
+        car.set_position(30.0);
+        assert_eq!(car.next_vehicle(&vehicles).unwrap().get_id(), 2);
+        car.set_lane(1);
+        assert_eq!(car.next_vehicle(&vehicles).unwrap().get_id(), 1);
+    }
 
     #[test]
     fn test_roll_forward_deceleration(){
         spawn_car_take_action(Action::Deccelerate, MAX_SPEED);
     }
 
+    #[test]
+    fn test_roll_forward_braking_to_rest(){
+        let mut test_car = Car::new(0, Direction::Up, 4.0, Action::Deccelerate);
+        test_car.roll_forward_by(TimeDelta::new(3000));
+        assert_eq!(test_car.get_speed(), 0.0);
+        assert_eq!(test_car.get_veh_position(), 2.0);
+
+        // A vehicle at rest does not move backwards while braking.
+        test_car.roll_forward_by(TimeDelta::new(1000));
+        assert_eq!(test_car.get_veh_position(), 2.0);
+    }
+
     #[test]
     fn test_next_crossing(){
 